@@ -1,19 +1,48 @@
+pub mod ai;
+pub mod bsp;
+pub mod combat;
+pub mod content;
+pub mod display;
+pub mod fluid;
+pub mod forced;
+pub mod fov;
 pub mod gps;
+pub mod item;
+pub mod keys;
 pub mod los;
 pub mod messages;
 pub mod numerics;
-
+pub mod path;
+pub mod power;
+pub mod prefab;
+pub mod prompt;
+pub mod replay;
+pub mod rng;
+pub mod save;
+pub mod sched;
+pub mod tower;
+pub mod transition;
+
+use crate::isk::ai::*;
+use crate::isk::combat::*;
+use crate::isk::display::*;
+use crate::isk::fov::*;
 use crate::isk::gps::*;
-use rand_xoshiro::rand_core::SeedableRng;
-use rand_xoshiro::Xoshiro256PlusPlus;
+use crate::isk::item::*;
+use crate::isk::keys::*;
+use crate::isk::los::*;
+use crate::isk::prefab::*;
+use crate::isk::prompt::*;
+use crate::isk::replay::*;
+use crate::isk::rng::*;
+use crate::isk::sched::*;
+use crate::isk::transition::*;
 use tcod::colors;
-use tcod::console::{Root , Offscreen, Console, FontLayout, FontType, BackgroundFlag, blit};
 use tcod::input::Key;
 use std::cmp::{min,max};
 use std::cell::RefCell;
 use std::convert::TryFrom;
 use std::rc::{Rc,Weak};
-use std::time::SystemTime;
 
 // at some point we'll want both a sidebar and a message bar
 pub const VIEW_RADIUS: i32 = 21;    // Cf. Cataclysm:Z, Rogue Survivor Revived
@@ -62,16 +91,30 @@ pub struct Terrain {
     pub tile: TileSpec, // how it displays
     pub bg: BackgroundSpec,
     pub walkable: bool,
-    pub transparent: bool
+    pub transparent: bool,
+    pub sunken: u8,     // depth below the floor; water pools here first
+    pub flow: Option<Compass>,  // a current carries water this way
+    pub drain: u8,      // water depth removed per turn, unless something covers it
+    pub power: bool,    // turns machinery while a current flows through
+    pub conveyor: Option<Compass>,  // carries actors and items this way each turn (only while turning, if power)
+    pub unsupported: bool,  // won't support weight: arrivals fall, or are hurt if there is nowhere to fall to
+    pub move_cost: i16  // percent of BASE_ACTION_COST to move onto this
 }
 type r_Terrain = Rc<Terrain>;
 
 impl Terrain {
     pub fn new(_name: &str, _tile: TileSpec, _walkable:bool, _transparent:bool) -> Terrain {
-        return Terrain{name:_name.to_string(), tile:_tile, bg:Ok(colors::BLACK), walkable:_walkable, transparent:_transparent};
+        return Terrain{name:_name.to_string(), tile:_tile, bg:Ok(colors::BLACK), walkable:_walkable, transparent:_transparent,
+            sunken:0, flow:None, drain:0, power:false, conveyor:None, unsupported:false, move_cost:100};
     }
 
     pub fn is_named(&self, _name:&str) -> bool { return self.name == _name; }
+
+    // energy spent moving onto this
+    pub fn move_energy(&self) -> i16 {
+        let ret = i32::from(BASE_ACTION_COST)*i32::from(self.move_cost)/100;
+        return i16::try_from(ret.max(1)).unwrap_or(i16::MAX);
+    }
 }
 
 // member function overloading assistants
@@ -80,22 +123,31 @@ trait Draw<T> {
 }
 
 pub struct DisplayManager {
-    pub root: Root,
-    pub offscr: Offscreen,
+    backend: Box<dyn Backend>,
     last_fg: colors::Color,
 }
 
 impl DisplayManager {
     pub fn new(name: &str, ft : &str) -> DisplayManager {
-        let root = Root::initializer().size(SCREEN_WIDTH, SCREEN_HEIGHT).title(name).font(ft,FontLayout::Tcod).font_type(FontType::Greyscale).init();
-        let offscr = Offscreen::new(SCREEN_WIDTH, SCREEN_HEIGHT);    // going to double-buffer at some point
-        return DisplayManager{root, offscr, last_fg:colors::WHITE};
+        return DisplayManager::with_backend(Box::new(TcodBackend::new(name, ft)));
     }
 
+    pub fn new_headless() -> DisplayManager {
+        return DisplayManager::with_backend(Box::new(HeadlessBackend::new()));
+    }
+
+    pub fn with_backend(backend: Box<dyn Backend>) -> DisplayManager {
+        return DisplayManager{backend, last_fg:colors::WHITE};
+    }
+
+    #[cfg(test)]
+    pub fn headless(&self) -> Option<&HeadlessBackend> { return self.backend.as_any().downcast_ref::<HeadlessBackend>(); }
+    #[cfg(test)]
+    pub fn headless_mut(&mut self) -> Option<&mut HeadlessBackend> { return self.backend.as_any_mut().downcast_mut::<HeadlessBackend>(); }
+
     pub fn clear(&mut self) {
         self.last_fg = colors::WHITE;
-        self.offscr.set_default_foreground(self.last_fg);
-        self.offscr.clear();
+        self.backend.clear();
     }
 
     pub fn in_bounds(scr_loc: &[i32;2]) -> bool {
@@ -121,17 +173,19 @@ impl DisplayManager {
             match bg {
                 Ok(mut col) => {
                     if !in_sight { col = col*0.75; }
-                    self.offscr.set_char_background(scr_loc[0], scr_loc[1], col , BackgroundFlag::Set);
+                    self.backend.set_bg(scr_loc, col);
                 },
                 _ => {debug_assert!(false,"image background not implemented")},
             };
         }
     }
 
-    pub fn render(&mut self) {
-        blit(&self.offscr, (0, 0), (SCREEN_WIDTH, SCREEN_HEIGHT), &mut self.root, (0, 0), 1.0, 1.0);
-        self.root.flush();
-    }
+    pub fn render(&mut self) { self.backend.present(); }
+
+    pub fn wait_for_keypress(&mut self) -> Key { return self.backend.wait_for_keypress(); }
+    pub fn window_closed(&self) -> bool { return self.backend.window_closed(); }
+    pub fn is_fullscreen(&self) -> bool { return self.backend.is_fullscreen(); }
+    pub fn set_fullscreen(&mut self, fullscreen:bool) { self.backend.set_fullscreen(fullscreen); }
 }
 
 // SFML port would also allow tiles
@@ -144,11 +198,10 @@ impl Draw<TileSpec> for DisplayManager {
                         Some(mut col) => {
                             if !in_sight { col = col*0.75; }
                             self.last_fg = col;
-                            self.offscr.set_default_foreground(self.last_fg);
                         },
                         None => ()
                     }
-                    self.offscr.put_char(scr_loc[0], scr_loc[1], t.img, BackgroundFlag::None);
+                    self.backend.put_char(scr_loc, t.img, self.last_fg);
                 },
                 _ => {debug_assert!(false,"image tiles not implemented")},
             };
@@ -170,6 +223,7 @@ impl Draw<String> for DisplayManager {
 }
 
 pub const BASE_ACTION_COST:i16 = 100;
+pub const BASE_HP:i16 = 10;
 
 pub struct HPstats {
     maxHp: i16,
@@ -189,18 +243,32 @@ impl HPstats {
         }
         self.curHp -= delta;
     }
+
+    // equipment: wounds carry over, so current HP moves with the maximum, but taking something off never kills
+    pub fn change_max(&mut self, delta:i16) {
+        self.maxHp += delta;
+        if 0 >= self.curHp { return; }
+        self.curHp = max(1, self.curHp+delta);
+    }
 }
 
 pub struct ActorModel {
     pub name: String,
     pub tile: TileSpec,
-    pub base_AP: i16
+    pub base_AP: i16,
+    pub base_HP: i16,
+    pub attack: i16,
+    pub defense: i16,
+    pub damage: i16,
+    pub capacity: usize,
+    pub ai: Behavior
 }
 type r_ActorModel = Rc<ActorModel>;
 
 impl ActorModel {
     pub fn new(_name: &str, _tile:TileSpec) -> ActorModel {
-        return ActorModel{name:_name.to_string(), tile:_tile, base_AP:BASE_ACTION_COST};
+        return ActorModel{name:_name.to_string(), tile:_tile, base_AP:BASE_ACTION_COST, base_HP:BASE_HP,
+            attack:BASE_ATTACK, defense:BASE_DEFENSE, damage:BASE_DAMAGE, capacity:BASE_CAPACITY, ai:Behavior::Idle};
     }
 
     pub fn is_named(&self, _name:&str) -> bool { return self.name == _name; }
@@ -210,7 +278,15 @@ pub struct Actor {
     pub is_pc: bool,
     pub model: r_ActorModel,
     my_loc: Location,
-    ap:i16
+    ap:i16,
+    ap_round:u64,   // the scheduler round ap is as of; later rounds' energy is granted when the actor next comes up
+    hp:HPstats,
+    fov:Option<Rc<Fov>>,
+    memory:MapMemory,
+    ai:AiState,
+    inventory:Inventory,
+    equipment:Vec<Item>,    // at most one per Slot
+    sched:Option<(u64,u32)>     // serial and generation of this actor's live entry in the World's Scheduler
 }
 pub type r_Actor = Rc<RefCell<Actor>>;
 pub type w_Actor = Weak<RefCell<Actor>>;
@@ -227,22 +303,47 @@ impl ConsoleRenderable for Actor {
         }
     }
     fn set_loc(&mut self, src:Location) -> () {
+        // the old map's position index is the only handle on our own Rc
+        let r_me = self.my_loc.map.borrow_mut().vacate(self.my_loc.pos, self);
+        if let Some(r_me) = r_me { src.map.borrow_mut().occupy(src.pos, r_me); }
         self.my_loc = src;
+        self.fov = None;
     }
 }
 
 impl Actor {
     pub fn new(_model: r_ActorModel, _loc: Location) -> Actor {
         let init_AP = _model.base_AP;
-        return Actor{model:_model, my_loc:_loc, is_pc:false, ap:init_AP};
+        let init_HP = _model.base_HP;
+        let capacity = _model.capacity;
+        return Actor{model:_model, my_loc:_loc, is_pc:false, ap:init_AP, ap_round:0, hp:HPstats::new(init_HP), fov:None, memory:MapMemory::new(), ai:AiState::new(),
+            inventory:Inventory::new(Some(capacity)), equipment:Vec::new(), sched:None};
     }
 
-    pub fn energy(&self) -> i16 { return self.ap; }
-    pub fn spend_energy(&mut self, delta:i16) { self.ap -= delta; }
-    pub fn speed(&self) -> i16 { return self.model.base_AP; } // to be modified by equipment, etc.
-    pub fn turn_postprocess(&mut self) {
-        self.ap += self.speed();
+    pub fn hp_mut(&mut self) -> &mut HPstats { return &mut self.hp; }
+    pub fn is_dead(&self) -> bool { return self.hp.isDead(); }
+    pub fn is_hostile_to(&self, other:&Actor) -> bool { return self.is_pc != other.is_pc; }   // \todo factions
+    pub fn fov_cache(&self) -> Option<Rc<Fov>> { return self.fov.clone(); }
+    pub fn set_fov_cache(&mut self, src:Rc<Fov>) { self.fov = Some(src); }
+    pub fn memory(&self) -> &MapMemory { return &self.memory; }
+    pub fn memory_mut(&mut self) -> &mut MapMemory { return &mut self.memory; }
+    pub fn ai(&self) -> &AiState { return &self.ai; }
+    pub fn ai_mut(&mut self) -> &mut AiState { return &mut self.ai; }
+    pub fn inventory(&self) -> &Inventory { return &self.inventory; }
+    pub fn inventory_mut(&mut self) -> &mut Inventory { return &mut self.inventory; }
+
+    pub fn energy(&self) -> i16 { return self.ap; }   // as of the actor's last turn
+    pub fn energy_at(&self, round:u64) -> i16 {
+        debug_assert!(self.ap_round <= round);
+        let rounds = i64::try_from(round - self.ap_round).unwrap_or(i64::MAX);
+        let ret = i64::from(self.ap).saturating_add(rounds.saturating_mul(i64::from(self.speed())));
+        return i16::try_from(min(ret, i64::from(i16::MAX))).unwrap();
     }
+    pub fn spend_energy(&mut self, delta:i16) { self.ap -= delta; }
+    pub fn speed(&self) -> i16 { return max(1, self.model.base_AP + self.bonus(|x| x.speed)); }  // never stalls
+    pub fn attack(&self) -> i16 { return max(0, self.model.attack + self.bonus(|x| x.attack)); }
+    pub fn defense(&self) -> i16 { return max(0, self.model.defense + self.bonus(|x| x.defense)); }
+    pub fn damage(&self) -> i16 { return max(1, self.model.damage + self.bonus(|x| x.damage)); }
 }
 
 pub struct MapObjectModel {
@@ -250,13 +351,14 @@ pub struct MapObjectModel {
     pub tile: TileSpec,
     pub walkable: bool,
     pub transparent: bool,
-    pub morph_on_bump: Option<Rc<MapObjectModel>>   // arguably should be in World object instead
+    pub spring: u8,     // water depth added to its cell per turn
+    pub transmits: bool // passes mechanical power on
 }
 pub type r_MapObjectModel = Rc<MapObjectModel>;
 
 impl MapObjectModel {
     pub fn new(_name: &str, _tile:TileSpec, _walkable:bool, _transparent:bool) -> MapObjectModel {
-        return MapObjectModel{name:_name.to_string(), tile:_tile, walkable:_walkable, transparent:_transparent, morph_on_bump:None};
+        return MapObjectModel{name:_name.to_string(), tile:_tile, walkable:_walkable, transparent:_transparent, spring:0, transmits:false};
     }
 
     pub fn is_named(&self, _name:&str) -> bool { return self.name == _name; }
@@ -288,24 +390,35 @@ impl MapObject {
     }
 }
 
-type Handler = fn(k:Key, r: &mut Root, w:&mut World, r_pc:r_Actor) -> bool;
+type Handler = fn(k:Key, dm: &mut DisplayManager, w:&mut World, r_pc:r_Actor) -> bool;
 pub struct World {
     atlas : Vec<r_Map>,
-//  offset: ... // (C++: std::map<std::pair<std::shared_ptr<Map>,std::shared_ptr<Map>>,[i32;2]>)
-//  exits: ... // unordered pairs of locations
-//  exits_one_way: ...  // ordered pairs of locations; falling would be damaging
+    offsets: Vec<(r_Map,r_Map,[i32;2])>, // origin of second map, in coordinates of the first; HashMap compile-errors
+    exits: Vec<[Location;2]>,   // unordered pairs of locations
+    exits_one_way: Vec<(Location,Location,i16)>,    // ordered pairs of locations, and damage taken; falling would be damaging
 //  not clear how to do C++ static member variables; put these here rather than where they belong
     actor_types: Vec<r_ActorModel>,
     obj_types: Vec<r_MapObjectModel>,
+    item_types: Vec<r_ItemModel>,
     terrain_types: Vec<r_Terrain>,
-    obj_close: Vec<[r_MapObjectModel;2]>,  // HashMap compile-errors
+    prefabs: Vec<Prefab>,
+    transitions: Vec<Transition>,  // what verbs do to map objects
+    obj_powered: Vec<[r_MapObjectModel;2]>,    // idle, running machinery
     event_handlers: Vec<Handler>,    // code locality; integrates InputManager functionality
+    keymap: KeyMap,
+    recorder: Option<Recorder>,
+    replay: Option<Replay>,
+    rng: GameRng,   // every random decision goes through this, so a seed reproduces the game
+    sched: Scheduler,
+    direction_prompt: Option<DirectionPrompt>   // state for event_direction_pc
 }
 
 impl World {
     pub fn new() -> World {
-        return World{atlas:Vec::new(), actor_types:Vec::new(), obj_types:Vec::new(), terrain_types:Vec::new(), obj_close:Vec::new(),
-            event_handlers:Vec::new()};
+        return World{atlas:Vec::new(), offsets:Vec::new(), exits:Vec::new(), exits_one_way:Vec::new(), actor_types:Vec::new(), obj_types:Vec::new(), item_types:Vec::new(), terrain_types:Vec::new(), prefabs:Vec::new(), transitions:Vec::new(), obj_powered:Vec::new(),
+            event_handlers:Vec::new(), keymap:KeyMap::standard(), recorder:None, replay:None,
+            rng:GameRng::new(GameRng::time_seed()),
+            sched:Scheduler::new(), direction_prompt:None};
     }
 
     pub fn new_map(&mut self, _name:&str, _dim: [i32;2], _terrain:r_Terrain) -> r_Map {
@@ -323,8 +436,100 @@ impl World {
         return None;
     }
 
-    pub fn new_actor_model(&mut self, _name: &str, _tile:TileSpec) -> r_ActorModel {
-        let ret = Rc::new(ActorModel::new(_name, _tile));
+    // registers both directions, and every map already stitched to either side is stitched to the other side as well;
+    // canonical_loc only looks one hop away.  Maps may only be stitched to maps they do not overlap.
+    pub fn set_offset(&mut self, a:&r_Map, b:&r_Map, delta:[i32;2]) {
+        debug_assert!(!Rc::ptr_eq(a, b));
+        debug_assert!(self.get_offset(a, b).is_none());
+        // origins, in coordinates of a
+        let mut lhs = vec![(Rc::clone(a), [0,0])];
+        lhs.extend(self.offsets.iter().filter(|x| Rc::ptr_eq(&x.0, a)).map(|x| (Rc::clone(&x.1), x.2)));
+        let mut rhs = vec![(Rc::clone(b), delta)];
+        rhs.extend(self.offsets.iter().filter(|x| Rc::ptr_eq(&x.0, b)).map(|x| (Rc::clone(&x.1), [delta[0]+x.2[0], delta[1]+x.2[1]])));
+        for (m_l, o_l) in &lhs {
+            for (m_r, o_r) in &rhs {
+                debug_assert!(!Rc::ptr_eq(m_l, m_r));
+                debug_assert!({
+                    let (l, r) = (m_l.borrow(), m_r.borrow());
+                    o_l[0]+l.width_i32() <= o_r[0] || o_r[0]+r.width_i32() <= o_l[0] || o_l[1]+l.height_i32() <= o_r[1] || o_r[1]+r.height_i32() <= o_l[1]
+                }, "stitched maps overlap");
+                let d = [o_r[0]-o_l[0], o_r[1]-o_l[1]];
+                self.offsets.push((Rc::clone(m_l), Rc::clone(m_r), d));
+                self.offsets.push((Rc::clone(m_r), Rc::clone(m_l), [-d[0], -d[1]]));
+            }
+        }
+    }
+
+    pub fn stitched_maps(&self, src:&r_Map) -> Vec<r_Map> {
+        return self.offsets.iter().filter(|x| Rc::ptr_eq(&x.0, src)).map(|x| Rc::clone(&x.1)).collect();
+    }
+
+    pub fn get_offset(&self, a:&r_Map, b:&r_Map) -> Option<[i32;2]> {
+        for x in &self.offsets {
+            if Rc::ptr_eq(&x.0, a) && Rc::ptr_eq(&x.1, b) { return Some(x.2); }
+        }
+        return None;
+    }
+
+    // stairs, gates, etc.: taken on request
+    pub fn new_exit(&mut self, a:Location, b:Location) {
+        debug_assert!(a != b);
+        self.exits.push([a, b]);
+    }
+
+    // chutes, pits, etc.: taken involuntarily, on entering from
+    pub fn new_exit_one_way(&mut self, from:Location, to:Location, damage:i16) {
+        debug_assert!(from != to);
+        debug_assert!(0 <= damage);
+        self.exits_one_way.push((from, to, damage));
+    }
+
+    pub fn get_exit(&self, o:&Location) -> Option<Location> {
+        for x in &self.exits {
+            if x[0] == *o { return Some(x[1].clone()); }
+            if x[1] == *o { return Some(x[0].clone()); }
+        }
+        return None;
+    }
+
+    pub fn get_exit_one_way(&self, o:&Location) -> Option<(Location,i16)> {
+        for x in &self.exits_one_way {
+            if x.0 == *o { return Some((x.1.clone(), x.2)); }
+        }
+        return None;
+    }
+
+    // handles transfer between owning maps (turn energy is retained), then any involuntary exits and footing
+    pub fn move_actor(&self, r_act:&r_Actor, dest:Location) {
+        use crate::isk::messages::*;
+        let mut dest = dest;
+        let mut ub = self.exits_one_way.len()+1; // a chute cycle should not hang the game
+        loop {
+            let src = r_act.borrow().loc();
+            r_act.borrow_mut().set_loc(dest.clone());
+            if !Rc::ptr_eq(&src.map, &dest.map) {
+                src.map.borrow_mut().remove_actor(r_act);
+                dest.map.borrow_mut().add_actor(Rc::clone(r_act));
+            }
+            if 0 == ub { break; }
+            ub -= 1;
+            if let Some((next, damage)) = self.get_exit_one_way(&dest) {
+                // \todo landing on someone
+                if !next.is_walkable_for(&r_act.borrow()) { break; }
+                let mut act = r_act.borrow_mut();
+                if 0 < damage { act.hp_mut().takeHit(damage); }
+                if act.is_pc {
+                    drop(act);
+                    get_messages_cache_mut().get_mut(Rc::clone(r_act)).add_message(&("You fall to ".to_string()+&next.map.borrow().named()));
+                }
+                dest = next;
+            } else { break; }
+        }
+        self.land(r_act);
+    }
+
+    pub fn new_actor_model(&mut self, src:ActorModel) -> r_ActorModel {
+        let ret = Rc::new(src);
         self.actor_types.push(Rc::clone(&ret));
         return ret;
     }
@@ -350,8 +555,21 @@ impl World {
         return None;
     }
 
-    pub fn new_terrain(&mut self, _name: &str, _tile: TileSpec, _walkable:bool, _transparent:bool) -> r_Terrain {
-        let ret = Rc::new(Terrain::new(_name, _tile, _walkable, _transparent));
+    pub fn new_item_model(&mut self, src:ItemModel) -> r_ItemModel {
+        let ret = Rc::new(src);
+        self.item_types.push(Rc::clone(&ret));
+        return ret;
+    }
+
+    pub fn get_item_model(&self, _name:&str) -> Option<r_ItemModel> {
+        for a_type in &self.item_types {
+            if a_type.is_named(_name) { return Some(Rc::clone(&a_type)); };
+        }
+        return None;
+    }
+
+    pub fn new_terrain(&mut self, src:Terrain) -> r_Terrain {
+        let ret = Rc::new(src);
         self.terrain_types.push(Rc::clone(&ret));
         return ret;
     }
@@ -363,15 +581,20 @@ impl World {
         return None;
     }
 
+    pub fn seed(&self) -> u64 { return self.rng.seed(); }
+    pub fn reseed(&mut self, seed:u64) { self.rng = GameRng::new(seed); }
+
     pub fn add_handler(&mut self, src:Handler) { self.event_handlers.push(src); }
+    pub fn keymap(&self) -> &KeyMap { return &self.keymap; }
+    pub fn set_keymap(&mut self, src:KeyMap) { self.keymap = src; }
 
-    pub fn exec_key(&mut self, r:&mut Root, r_pc:r_Actor) -> bool {
+    pub fn exec_key(&mut self, dm:&mut DisplayManager, r_pc:r_Actor) -> bool {
         debug_assert!(r_pc.borrow_mut().is_pc);
 
 //      let ev = check_for_event(EventFlags::Keypress);
-        let key = r.wait_for_keypress(true);    // could r.check_for_keypress instead but then would have to pause/multi-process explicitly
+        let key = self.next_key(dm, &r_pc);
         let n = self.event_handlers.len();
-        let ret = (self.event_handlers[n-1])(key, r, self, r_pc);
+        let ret = (self.event_handlers[n-1])(key, dm, self, r_pc);
         if 1 < n {
             if ret { self.event_handlers.pop(); }
             return false;
@@ -379,36 +602,20 @@ impl World {
         return ret;
     }
 
-    fn turn_postprocess(&mut self) -> bool {
-        let mut no_actors = true;
-        for r_m in &self.atlas {
-            if !r_m.borrow_mut().turn_postprocess() { no_actors = false; }
-        }
-        return no_actors;
-    }
-
-    fn _next_actor(&mut self) -> Option<r_Actor> {
-        for r_m in &self.atlas {    // \todo caching for CPU efficiency
-            if let Some(act) = r_m.borrow().next_actor() { return Some(act); }
-        }
-        return None;
-    }
-
-    pub fn next_actor(&mut self) -> Option<r_Actor> {
-        loop {
-            let r_act = self._next_actor();
-            if let Some(act) = r_act { return Some(act); }
-            if self.turn_postprocess() { return None; }
-        }
-    }
-
     pub fn canonical_loc(&self, viewpoint:Location) -> Option<Location> {
         match viewpoint.map.try_borrow() {
             Ok(m) => {
                 if m.in_bounds(viewpoint.pos) {
                     return Some(Location::new(&viewpoint.map, viewpoint.pos));
                 }
-                // \todo translation code
+                // set_offset stitches every map in a group to every other, so one hop is enough
+                for x in &self.offsets {
+                    if !Rc::ptr_eq(&x.0, &viewpoint.map) { continue; }
+                    let pos = [viewpoint.pos[0]-x.2[0], viewpoint.pos[1]-x.2[1]];
+                    if let Ok(m2) = x.1.try_borrow() {
+                        if m2.in_bounds(pos) { return Some(Location::new(&x.1, pos)); }
+                    }
+                }
                 return None;
             },
             _ => {
@@ -419,43 +626,15 @@ impl World {
     }
 
     pub fn coerce_map(&self, src:Location, viewpoint:r_Map) -> Option<Location> {
-        if src.map == viewpoint {
+        if Rc::ptr_eq(&src.map, &viewpoint) {
             return Some(src);
         }
-        // \todo translation code
-        return None;
-    }
-
-    fn is_closable_map_object(&self, obj:&r_MapObject) -> Option<r_MapObjectModel> {
-        for x in &self.obj_close {
-            if Rc::ptr_eq(&obj.borrow().model, &x[0]) { return Some(Rc::clone(&x[1])); }
+        if let Some(delta) = self.get_offset(&viewpoint, &src.map) {
+            return Some(Location::new(&viewpoint, [src.pos[0]+delta[0], src.pos[1]+delta[1]]));
         }
         return None;
     }
 
-    pub fn get_closable_locations(&self, o:&Location) -> Vec<Location> {
-        let mut ret = Vec::<Location>::new();
-        // \todo properly iterate over all directions; cf crates.io/enum-iterator, https://github.com/rust-lang/rust/issues/5417 (declined by devteam)
-        for i in 0..8 {
-            let test = o.clone()+Compass::try_from(i).unwrap();
-            if let Some(obj) = test.get_map_object() {
-                if let Some(_dest) = self.is_closable_map_object(&obj) {
-                    ret.push(test);
-                }
-            }
-        }
-        return ret;
-    }
-
-    pub fn close(&mut self, o:&Location, _act:&Actor) -> bool {
-        if let Some(obj) = o.get_map_object() {
-            if let Some(dest) = self.is_closable_map_object(&obj) {
-                o.set_map_object(dest);
-                return true;
-            } else { return false; }
-        } else { return false; }
-    }
-
     pub fn screen_to_loc(&self, src:[i32;2], topleft:&Location) -> Option<Location> {
         return self.canonical_loc(Location::new(&topleft.map, [topleft.pos[0]+src[0], topleft.pos[1]+src[1]]));
     }
@@ -480,31 +659,38 @@ impl World {
         tl = canon_tl.unwrap();
         if 0 >= tl.pos[0] && 0 >= tl.pos[1] { return tl; }
 
-        if 0 < tl.pos[0] {
-            let test = tl.clone()+[2*VIEW_RADIUS, 0];
-            if let None = self.canonical_loc(test.clone()) {
-                let lb = min(tl.pos[0], test.pos[0]-(test.map.borrow().width_i32()-1));
-                if 0 < lb { tl.pos[0] -= lb; }
-            }
+        // the far edge may be on a stitched map, so back off one cell at a time
+        let mut test = tl.clone()+[2*VIEW_RADIUS, 0];
+        while 0 < tl.pos[0] && self.canonical_loc(test.clone()).is_none() {
+            tl.pos[0] -= 1;
+            test.pos[0] -= 1;
         }
 
-        if 0 < tl.pos[1] {
-            let test = tl.clone()+[0, 2*VIEW_RADIUS];
-            if let None = self.canonical_loc(test.clone()) {
-                let lb = min(tl.pos[1], test.pos[1]-(test.map.borrow().height_i32()-1));
-                if 0 < lb { tl.pos[1] -= lb; }
-            }
+        test = tl.clone()+[0, 2*VIEW_RADIUS];
+        while 0 < tl.pos[1] && self.canonical_loc(test.clone()).is_none() {
+            tl.pos[1] -= 1;
+            test.pos[1] -= 1;
         }
         return tl;
     }
 
-    // \todo exceptionally GC-thrashing
+    // \todo exceptionally GC-thrashing; prefer World::can_see for actors
     // * need variants that just return bool in the Map class
-    // * need backing caches within Map class
     pub fn los(&self, from:&Location, to:&Location) -> bool {
         if Rc::ptr_eq(&from.map, &to.map) {
             return from.map.borrow().los(&from.pos, &to.pos).0;
         }
+        // across a map seam: trace in the viewer's coordinates
+        if let Some(dest) = self.coerce_map(to.clone(), Rc::clone(&from.map)) {
+            let is_visible = |x:&Point::<i32>| -> bool {
+                if dest.pos == **x { return true; }
+                match self.canonical_loc(Location::new(&from.map, **x)) {
+                    Some(loc) => return loc.map.borrow().is_transparent(loc.pos),
+                    None => return false
+                }
+            };
+            return AngbandlikeTrace(u32::MAX, &Point::new(&from.pos), &Point::new(&dest.pos), &is_visible).0;
+        }
         return false;
     }
 
@@ -517,29 +703,35 @@ impl World {
 
     pub fn draw(&self, dm:&mut DisplayManager, viewpoint:Location, o_act:&r_Actor) {
         use crate::isk::messages::*;
-        let origin = o_act.borrow().loc();
+        let fov = self.fov(o_act);
+        let viewer = o_act.borrow();
         let n = viewpoint.map.borrow().named();
+        let level = viewpoint.map.borrow().level();
         let camera = self.loc_to_td_camera(viewpoint);
         for x in 0..VIEW {
             for y in 0..VIEW {
                 let scr_loc = [x, y];
                 let src = self.canonical_loc(camera.clone()+[x,y]);
                 if let Some(loc) = src {
-                    let agent_visibility = self.los(&origin, &loc);
-                    // \todo bail if location is neither visible nor mapped
-                    let m = loc.map.borrow();
+                    let agent_visibility = match fov.origin() {
+                        Some(origin) => self.coerce_map(loc.clone(), origin.map).is_some_and(|x| fov.contains(&x.pos)),
+                        None => false
+                    };
+                    let (background, tiles) = if agent_visibility {
+                        let m = loc.map.borrow();
+                        (m.bg_i32(loc.pos), m.tiles(loc.pos).unwrap_or_default())
+                    } else if let Some(mem) = viewer.memory().get(&loc) {
+                        (mem.bg.clone(), mem.tiles.clone())
+                    } else { continue; };   // neither visible nor mapped
                     {
                     let mut bg_ok = true;
-                    let background = m.bg_i32(loc.pos);
                     if let Ok(col) = background {
                         if colors::BLACK == col { bg_ok = false; }
                     }
                     if bg_ok { dm.set_bg(&scr_loc, background, agent_visibility); }
                     }
-                    let tiles = m.tiles(loc.pos);
-                    if let Some(v) = tiles {
-                        for img in v { dm.draw(&scr_loc, img, agent_visibility); }
-                    }
+                    if self.direction_prompt.as_ref().is_some_and(|x| x.choices.contains(&loc)) { dm.set_bg(&scr_loc, Ok(HIGHLIGHT_BG), true); }
+                    for img in tiles { dm.draw(&scr_loc, img, agent_visibility); }
                 } else { continue; }    // not valid, just fail to update
             }
         }
@@ -570,14 +762,38 @@ impl World {
         }
 
         // the left panel
-        dm.draw(&[VIEW+1, VIEW-1], n, true);    // map name
+        if !viewer.inventory().is_empty() {
+            dm.draw(&[VIEW+1, 0], "Inventory".to_string(), true);
+            for (i, item) in viewer.inventory().stacks().iter().enumerate() {
+                let letter = char::from(b'a'+u8::try_from(i).unwrap());
+                dm.draw(&[VIEW+1, 1+i32::try_from(i).unwrap()], letter.to_string()+") "+&item.describe(), true);
+            }
+        }
+        if !viewer.equipment().is_empty() {
+            let mut y = VIEW/2;
+            dm.draw(&[VIEW+1, y], "Equipment".to_string(), true);
+            for slot in SLOTS.iter() {
+                if let Some(item) = viewer.equipped(*slot) {
+                    let letter = char::from(b'a'+u8::try_from(y-VIEW/2).unwrap());
+                    y += 1;
+                    dm.draw(&[VIEW+1, y], letter.to_string()+") "+slot.name()+": "+&item.describe(), true);
+                }
+            }
+        }
+        dm.draw(&[VIEW+1, VIEW-2], n, true);    // map name
+        dm.draw(&[VIEW+1, VIEW-1], crate::isk::tower::level_name(level), true);
     }
 
     pub fn new_actor(&mut self, _model: r_ActorModel, _camera:&Location, _pos:[i32;2]) -> Option<r_Actor> {
         // \todo enforce that the location is ours, at least for debug builds
         if let Some(loc) = self.screen_to_loc(_pos, _camera) {
             match loc.map.try_borrow_mut() {
-                Ok(mut m) => return Some(m.new_actor(_model, loc.clone())),
+                Ok(mut m) => {
+                    let ret = m.new_actor(_model, loc.clone());
+                    drop(m);
+                    self.schedule(&ret);
+                    return Some(ret);
+                },
                 _ => return None
             };
         }
@@ -586,23 +802,22 @@ impl World {
 
     // return value is a PC
     pub fn new_game(&mut self) -> r_Actor {
-        // \todo should be loading these tile configurations (possibly if not already loaded)
-        let _t_air = self.new_terrain("air", Ok(CharSpec{img:' ', c:None}), true, true);  // \todo but will not support weight!
-        let _t_floor = self.new_terrain("floor", Ok(CharSpec{img:'.', c:Some(colors::BRASS)}), true, true); // wooden?
-        let _t_grass = self.new_terrain("grass", Ok(CharSpec{img:'.', c:Some(colors::GREEN)}), true, true);
-        let _t_stone_floor = self.new_terrain("stone floor", Ok(CharSpec{img:'.', c:Some(colors::GREY)}), true, true);
-        let _t_wall = self.new_terrain("wall", Ok(CharSpec{img:'#', c:Some(colors::GREY)}), false, false);
-        let _t_stone_culvert_ns = self.new_terrain("stone culvert", Ok(CharSpec{img:'|', c:Some(colors::GREY)}), true, true);
-        let _t_inset_waterwheel_ns = self.new_terrain("water wheel in floor", Ok(CharSpec{img:'=', c:Some(colors::LIGHTER_SEPIA)}), true, true);    // but can't stay still on it
-        let _t_inset_waterwheel_sd = self.new_terrain("water wheel in floor", Ok(CharSpec{img:'_', c:Some(colors::LIGHTER_SEPIA)}), true, true);   // won't support weight
-
-        let _t_open_door = self.new_map_object_model(MapObjectModel::new("door (open)", Ok(CharSpec{img:'\'', c:Some(colors::LIGHTER_SEPIA)}), true, true));
-        let mut _stage_closed_door = MapObjectModel::new("door (closed)", Ok(CharSpec{img:'+', c:Some(colors::LIGHTER_SEPIA)}), false, false);
-        _stage_closed_door.morph_on_bump = Some(Rc::clone(&_t_open_door));
-        let _t_closed_door = self.new_map_object_model(_stage_closed_door);
-        self.obj_close.push([Rc::clone(&_t_open_door), Rc::clone(&_t_closed_door)]);
-        let _t_artesian_spring = self.new_map_object_model(MapObjectModel::new("artesian spring", Ok(CharSpec{img:'!', c:Some(colors::AZURE)}), true, true));
-        let _t_water = self.new_map_object_model(MapObjectModel::new("water", Ok(CharSpec{img:'~', c:Some(colors::AZURE)}), true, true));
+        // tile configurations are from the content file; fixtures from the prefab file
+        let _t_stone_floor = self.get_terrain("stone floor").unwrap();
+        let _t_wall = self.get_terrain("wall").unwrap();
+        let _t_grass = self.get_terrain("grass").unwrap();
+
+        let _t_closed_door = self.get_map_object_model("door (closed)").unwrap();
+        let _t_locked_door = self.get_map_object_model("door (locked)").unwrap();
+
+        let _i_gold_coin = self.get_item_model("gold coin").unwrap();
+        let _i_ledger = self.get_item_model("ledger").unwrap();
+        let _i_dagger = self.get_item_model("dagger").unwrap();
+        let _i_ration = self.get_item_model("ration").unwrap();
+        let _i_leather_armor = self.get_item_model("leather armor").unwrap();
+        let _i_iron_helmet = self.get_item_model("iron helmet").unwrap();
+        let _i_riding_boots = self.get_item_model("riding boots").unwrap();
+        let _i_iron_key = self.get_item_model("iron key").unwrap();
 
         // final architecture...
         // scale: 10' passage is 3 cells wide (allows centering doors properly)
@@ -657,28 +872,26 @@ impl World {
         _inner_se.rect += n_delta;
 
         // inner rooms (crowded...this is both "too small" and "too large")
-        let mut p_rng = Xoshiro256PlusPlus::seed_from_u64(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs());
-
         let mut _centerzone = MapRect::new(Rect::new(_inner_n.rect.anchor(Compass::SW),[_inner_n.rect.width(),_inner_e.rect.height()]),Rc::clone(&_t_stone_floor), Rc::clone(&_t_wall));
         _centerzone.set_wallcode(0,1,1,1);
         let mut _s_centerzone = _centerzone.clone();
-        _s_centerzone.rect = _centerzone.rect.split(&mut p_rng,Compass::S,7,10).unwrap();
+        _s_centerzone.rect = _centerzone.rect.split(&mut self.rng,Compass::S,7,10).unwrap();
         let mut _industrial = _centerzone.clone();
         {
         let w = _centerzone.rect.width();
-        _industrial.rect = _centerzone.rect.split(&mut p_rng,Compass::E,w/2-1,2*w/3-1).unwrap();
+        _industrial.rect = _centerzone.rect.split(&mut self.rng,Compass::E,w/2-1,2*w/3-1).unwrap();
         }
         _centerzone.set_wallcode(0,0,1,1);
         let mut _shop = _s_centerzone.clone();
         {
         let w = _s_centerzone.rect.width();
-        _shop.rect = _s_centerzone.rect.split(&mut p_rng,Compass::E,3*w/5, 4*w/5-1).unwrap();
+        _shop.rect = _s_centerzone.rect.split(&mut self.rng,Compass::E,3*w/5, 4*w/5-1).unwrap();
         }
         _s_centerzone.set_wallcode(0,0,1,1);
         let mut _accounting = _s_centerzone.clone();
         {
         let w = _s_centerzone.rect.width();
-        _accounting.rect = _s_centerzone.rect.split(&mut p_rng,Compass::E,w/3, 2*w/3-1).unwrap();
+        _accounting.rect = _s_centerzone.rect.split(&mut self.rng,Compass::E,w/3, 2*w/3-1).unwrap();
         }
 
         let se_anchor = _tower_se.rect.anchor(Compass::SE);
@@ -739,7 +952,7 @@ impl World {
 
         axis = _accounting.rect.anchor(Compass::W);
         m.set_terrain(axis,Rc::clone(&_t_stone_floor));
-        m.set_map_object(Rc::new(RefCell::new(MapObject::new(_t_closed_door.clone(),Location::new(&oc_ryacho_ground_floor,axis)))));
+        m.set_map_object(Rc::new(RefCell::new(MapObject::new(_t_locked_door.clone(),Location::new(&oc_ryacho_ground_floor,axis)))));
 
         axis = _s_centerzone.rect.anchor(Compass::W);
         m.set_terrain(axis,Rc::clone(&_t_stone_floor));
@@ -750,32 +963,261 @@ impl World {
         m.set_terrain(axis,Rc::clone(&_t_stone_floor));
         m.set_map_object(Rc::new(RefCell::new(MapObject::new(_t_closed_door.clone(),Location::new(&oc_ryacho_ground_floor,axis)))));
 
-        // install the waterwheel
-        axis = _industrial.rect.anchor(Compass::N);
-        m.set_terrain(axis,Rc::clone(&_t_inset_waterwheel_sd));
+        // loot
+        axis = _shop.rect.center();
+        m.add_item(axis, Item::new(Rc::clone(&_i_dagger), 1));
+        axis += Compass::E;
+        m.add_item(axis, Item::new(Rc::clone(&_i_ration), 3));
         axis += Compass::S;
-        m.set_terrain(axis,Rc::clone(&_t_inset_waterwheel_ns));
-        axis += Compass::S;
-        m.set_terrain(axis,Rc::clone(&_t_stone_culvert_ns));
-        m.set_map_object(Rc::new(RefCell::new(MapObject::new(Rc::clone(&_t_water),Location::new(&oc_ryacho_ground_floor,axis)))));
-        axis += Compass::S;
-        m.set_terrain(axis,Rc::clone(&_t_stone_culvert_ns));
-        m.set_map_object(Rc::new(RefCell::new(MapObject::new(Rc::clone(&_t_water),Location::new(&oc_ryacho_ground_floor,axis)))));
-        axis += Compass::S;
-        m.set_terrain(axis,Rc::clone(&_t_stone_culvert_ns));
-        m.set_map_object(Rc::new(RefCell::new(MapObject::new(Rc::clone(&_t_water),Location::new(&oc_ryacho_ground_floor,axis)))));
-        axis += Compass::S;
-        m.set_map_object(Rc::new(RefCell::new(MapObject::new(Rc::clone(&_t_artesian_spring),Location::new(&oc_ryacho_ground_floor,axis)))));
+        m.add_item(axis, Item::new(Rc::clone(&_i_leather_armor), 1));
+        axis += Compass::W;
+        m.add_item(axis, Item::new(Rc::clone(&_i_iron_helmet), 1));
+        axis += Compass::W;
+        m.add_item(axis, Item::new(Rc::clone(&_i_riding_boots), 1));
+        axis += Compass::N;
+        m.add_item(axis, Item::new(Rc::clone(&_i_iron_key), 1));    // to the accounting office
+        axis = _accounting.rect.center();
+        m.add_item(axis, Item::new(Rc::clone(&_i_gold_coin), 25));
+        axis += Compass::W;
+        m.add_item(axis, Item::new(Rc::clone(&_i_ledger), 1));
+        }
+
+        // install the waterwheel, inside the industrial room's north wall if it has one
+        let mut wheel_at = _industrial.rect.anchor(Compass::N);
+        if 0 < _industrial.read_wallcode(Compass::N) { wheel_at += Compass::S; }
+        self.get_prefab("waterwheel").unwrap().stamp(&Location::new(&oc_ryacho_ground_floor, wheel_at), Compass::N).unwrap();
+
+        // the race under the wheel: whoever steps onto its sunken end falls in, and stairs lead back up beside it
+        {
+        let race_name = oc_ryacho_ground_floor.borrow().named()+", wheel race";
+        let race = self.new_map(&race_name, [5,4], Rc::clone(&_t_wall));
+        race.borrow_mut().set_level(-1);
+        let mut shell = MapRect::new(Rect::new([0,0],[5,4]), Rc::clone(&_t_stone_floor), Rc::clone(&_t_wall));
+        shell.set_wallcode(1,1,1,1);
+        shell.draw(&mut race.borrow_mut());
+        self.new_exit_one_way(Location::new(&oc_ryacho_ground_floor, wheel_at), Location::new(&race, [1,1]), crate::isk::forced::FALL_DAMAGE);
+        let down = Location::new(&oc_ryacho_ground_floor, wheel_at)+Compass::E;
+        let up = Location::new(&race, [3,2]);
+        down.set_map_object(self.get_map_object_model("stairs down").unwrap());
+        up.set_map_object(self.get_map_object_model("stairs up").unwrap());
+        self.new_exit(down, up);
+        }
+
+        // the meadow east of the castle: stitched on, so it is walked into rather than entered
+        {
+        let (w, h) = { let m = oc_ryacho_ground_floor.borrow(); (m.width_i32(), m.height_i32()) };
+        let meadow_name = oc_ryacho_ground_floor.borrow().named()+", east meadow";
+        let meadow = self.new_map(&meadow_name, [12, h], Rc::clone(&_t_grass));
+        self.set_offset(&oc_ryacho_ground_floor, &meadow, [w, 0]);
+        }
+
+        // towers: stairs up and down, in their outer corners
+        for (tower, corner) in [(&_tower_nw, Compass::NW), (&_tower_ne, Compass::NE), (&_tower_se, Compass::SE), (&_tower_sw, Compass::SW)].iter() {
+            self.build_tower(&oc_ryacho_ground_floor, tower, corner.clone());
         }
 
         // end map generation
 
         // \todo construct PC(s)
         let camera_anchor = Location::new(&oc_ryacho_ground_floor, [0, 0]);
-        let player_model = self.new_actor_model("soldier", Ok(CharSpec{img:'s', c:None}));
+        let player_model = self.get_actor_model("soldier").unwrap();
         let _e1 = self.new_actor(player_model.clone(), &camera_anchor, _tower_nw.rect.center()).unwrap();
+        {
+        // walks between the NW tower and the north corridor, through the tower's east door
+        let mut route = vec![_e1.borrow().loc()];
+        route.push(Location::new(&oc_ryacho_ground_floor, _tower_nw.rect.anchor(Compass::E))+Compass::E);
+        _e1.borrow_mut().ai_mut().set_waypoints(route);
+        }
         let player = self.new_actor(player_model.clone(), &camera_anchor, [se_anchor[0]+3, se_anchor[1]+3]).unwrap();
         player.borrow_mut().is_pc = true;
+        {
+        use crate::isk::messages::*;
+        get_messages_cache_mut().get_mut(Rc::clone(&player)).add_message(&("Game seed: ".to_string()+&self.seed().to_string()));
+        }
         return player;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isk::forced::FALL_DAMAGE;
+
+    fn loaded() -> World {
+        let mut w = World::new();
+        w.load_content("data/content.txt").unwrap();
+        w.load_prefabs("data/prefabs.txt").unwrap();
+        return w;
+    }
+
+    #[test]
+    fn the_wheel_race_is_a_fall_down_and_a_climb_up() {
+        let mut w = loaded();
+        let pc = w.new_game();
+        let ground = Rc::clone(&pc.borrow().loc().map);
+        let race = w.atlas.iter().find(|m| m.borrow().named().ends_with("wheel race")).cloned().unwrap();
+        let pit = w.exits_one_way.iter().find(|x| Rc::ptr_eq(&x.1.map, &race)).map(|x| x.0.clone()).unwrap();
+        assert!(Rc::ptr_eq(&pit.map, &ground) && pit.get_terrain().unsupported);
+
+        let hp = pc.borrow().hp.curHp;
+        pc.borrow_mut().spend_energy(30);
+        let energy = pc.borrow().energy();
+        w.move_actor(&pc, pit.clone());
+        assert!(Rc::ptr_eq(&pc.borrow().loc().map, &race));
+        assert_eq!(hp-FALL_DAMAGE, pc.borrow().hp.curHp);     // the race floor is solid, so only the fall hurts
+        assert_eq!(energy, pc.borrow().energy());
+        assert!(!ground.borrow().actors().iter().any(|a| Rc::ptr_eq(a, &pc)));
+        assert_eq!(1, race.borrow().actors().len());
+
+        let stairs = race.borrow().map_objects().into_iter().find(|x| x.borrow().model.is_named("stairs up")).unwrap().borrow().loc();
+        assert!(w.get_stairs(&stairs, -1).is_none());
+        let up = w.get_stairs(&stairs, 1).unwrap();
+        assert!(Rc::ptr_eq(&up.map, &ground) && 1 == (up.pos[0]-pit.pos[0]).abs().max((up.pos[1]-pit.pos[1]).abs()));
+        w.move_actor(&pc, stairs);
+        w.move_actor(&pc, up.clone());
+        assert!(pc.borrow().loc() == up);
+        assert!(race.borrow().actors().is_empty());
+        assert_eq!(hp-FALL_DAMAGE, pc.borrow().hp.curHp);
+    }
+
+    #[test]
+    fn the_position_index_follows_actors() {
+        let mut w = World::new();
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:None}), true, true));
+        let top = w.new_map("top", [3,3], Rc::clone(&floor));
+        let mid = w.new_map("mid", [3,3], Rc::clone(&floor));
+        let bottom = w.new_map("bottom", [3,3], floor);
+        w.new_exit(Location::new(&top, [2,2]), Location::new(&mid, [0,0]));
+        w.new_exit_one_way(Location::new(&mid, [1,1]), Location::new(&bottom, [2,0]), 0);
+        let model = w.new_actor_model(ActorModel::new("m", Ok(CharSpec{img:'m', c:None})));
+        let a = w.new_actor(Rc::clone(&model), &Location::new(&top, [0,0]), [0,0]).unwrap();
+        let b = w.new_actor(model, &Location::new(&mid, [0,0]), [2,2]).unwrap();
+        let at = |m:&r_Map, pos:[i32;2]| m.borrow().get_actor(pos);
+
+        w.move_actor(&a, Location::new(&top, [2,2]));
+        assert!(at(&top, [0,0]).is_none());
+        assert!(Rc::ptr_eq(&a, &at(&top, [2,2]).unwrap()));
+
+        let exit = w.get_exit(&a.borrow().loc()).unwrap();
+        w.move_actor(&a, exit);
+        assert!(at(&top, [2,2]).is_none());
+        assert!(Rc::ptr_eq(&a, &at(&mid, [0,0]).unwrap()));
+        assert!(Rc::ptr_eq(&b, &at(&mid, [2,2]).unwrap()));
+
+        w.move_actor(&a, Location::new(&mid, [1,1]));   // through the chute, leaving nothing behind
+        assert!(at(&mid, [0,0]).is_none() && at(&mid, [1,1]).is_none());
+        assert!(Rc::ptr_eq(&a, &at(&bottom, [2,0]).unwrap()));
+
+        for r_act in [&a, &b].iter() {
+            let hp = r_act.borrow().hp.curHp;
+            r_act.borrow_mut().hp_mut().takeHit(hp);
+        }
+        w.kill(&a);
+        assert!(at(&bottom, [2,0]).is_none());
+        assert!(bottom.borrow().actors().is_empty());
+        w.kill(&b);
+        assert!(at(&mid, [2,2]).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "already occupied")]
+    fn one_actor_to_a_cell() {
+        let mut w = World::new();
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:None}), true, true));
+        let m = w.new_map("m", [3,3], floor);
+        let model = w.new_actor_model(ActorModel::new("m", Ok(CharSpec{img:'m', c:None})));
+        let a = w.new_actor(Rc::clone(&model), &Location::new(&m, [0,0]), [0,0]).unwrap();
+        let _b = w.new_actor(model, &Location::new(&m, [0,0]), [1,0]).unwrap();
+        a.borrow_mut().set_loc(Location::new(&m, [1,0]));
+    }
+
+    #[test]
+    fn one_way_exits_chain_and_stop() {
+        let mut w = World::new();
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:None}), true, true));
+        let wall = w.new_terrain(Terrain::new("wall", Ok(CharSpec{img:'#', c:None}), false, false));
+        let top = w.new_map("top", [3,3], Rc::clone(&floor));
+        let mid = w.new_map("mid", [3,3], Rc::clone(&floor));
+        let bottom = w.new_map("bottom", [3,3], Rc::clone(&floor));
+        w.new_exit_one_way(Location::new(&top, [1,1]), Location::new(&mid, [1,1]), 1);
+        w.new_exit_one_way(Location::new(&mid, [1,1]), Location::new(&bottom, [1,1]), 2);
+        // a chute cycle, and a chute into a wall
+        w.new_exit_one_way(Location::new(&top, [0,0]), Location::new(&mid, [0,0]), 0);
+        w.new_exit_one_way(Location::new(&mid, [0,0]), Location::new(&top, [0,0]), 0);
+        bottom.borrow_mut().set_terrain([2,2], wall);
+        w.new_exit_one_way(Location::new(&top, [2,2]), Location::new(&bottom, [2,2]), 5);
+
+        let model = w.new_actor_model(ActorModel::new("m", Ok(CharSpec{img:'m', c:None})));
+        let a = w.new_actor(model, &Location::new(&top, [0,0]), [0,1]).unwrap();
+        let hp = a.borrow().hp.curHp;
+        w.move_actor(&a, Location::new(&top, [1,1]));
+        assert!(a.borrow().loc() == Location::new(&bottom, [1,1]));
+        assert_eq!(hp-3, a.borrow().hp.curHp);
+        assert!(top.borrow().actors().is_empty() && mid.borrow().actors().is_empty());
+
+        w.move_actor(&a, Location::new(&top, [0,0]));
+        assert!(Rc::ptr_eq(&a.borrow().loc().map, &top) || Rc::ptr_eq(&a.borrow().loc().map, &mid));
+        assert_eq!(hp-3, a.borrow().hp.curHp);
+        w.move_actor(&a, Location::new(&top, [2,2]));
+        assert!(a.borrow().loc() == Location::new(&top, [2,2]));
+        assert_eq!(hp-3, a.borrow().hp.curHp);
+    }
+
+    #[test]
+    fn the_castle_opens_onto_the_meadow() {
+        let mut w = loaded();
+        let pc = w.new_game();
+        let ground = Rc::clone(&pc.borrow().loc().map);
+        let meadow = w.stitched_maps(&ground).pop().unwrap();
+        assert!(meadow.borrow().named().ends_with("east meadow"));
+        let (width, height) = (ground.borrow().width_i32(), ground.borrow().height_i32());
+        assert!(w.get_offset(&ground, &meadow) == Some([width, 0]));
+        assert!(w.get_offset(&meadow, &ground) == Some([-width, 0]));
+
+        let edge = Location::new(&ground, [width-1, height/2]);
+        let over = w.canonical_loc(edge.clone()+Compass::E).unwrap();
+        assert!(over == Location::new(&meadow, [0, height/2]));
+        assert!(w.canonical_loc(over.clone()+Compass::W).unwrap() == edge);
+        assert!(w.coerce_map(over.clone(), Rc::clone(&ground)).unwrap() == Location::new(&ground, [width, height/2]));
+        assert!(w.canonical_loc(Location::new(&ground, [width, height])).is_none());
+
+        w.move_actor(&pc, edge);
+        let dest = w.canonical_loc(pc.borrow().loc()+Compass::E).unwrap();
+        assert!(w.perform(&pc, Action::Move(dest)));
+        assert!(pc.borrow().loc() == over);
+        assert!(meadow.borrow().actors().iter().any(|a| Rc::ptr_eq(a, &pc)));
+    }
+
+    #[test]
+    fn stitching_reaches_every_map_in_one_hop() {
+        let mut w = World::new();
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:None}), true, true));
+        let m: Vec<r_Map> = (0..4).map(|i| w.new_map(&i.to_string(), [3,2], Rc::clone(&floor))).collect();
+        // a row of four, joined in the middle last
+        w.set_offset(&m[0], &m[1], [3,0]);
+        w.set_offset(&m[3], &m[2], [-3,0]);
+        w.set_offset(&m[1], &m[2], [3,0]);
+        for i in 0..4 {
+            assert_eq!(3, w.stitched_maps(&m[i]).len());
+            for j in 0..4 {
+                if i != j { assert!(w.get_offset(&m[i], &m[j]) == Some([3*(j as i32-i as i32), 0])); }
+            }
+        }
+        assert!(w.canonical_loc(Location::new(&m[0], [10,1])).unwrap() == Location::new(&m[3], [1,1]));
+        assert!(w.canonical_loc(Location::new(&m[3], [-8,0])).unwrap() == Location::new(&m[0], [1,0]));
+        assert!(w.coerce_map(Location::new(&m[0], [2,1]), Rc::clone(&m[3])).unwrap() == Location::new(&m[3], [-7,1]));
+
+        // and a reloaded save agrees
+        let model = w.new_actor_model(ActorModel::new("m", Ok(CharSpec{img:'m', c:None})));
+        let pc = w.new_actor(model, &Location::new(&m[0], [0,0]), [0,0]).unwrap();
+        pc.borrow_mut().is_pc = true;
+        let path = std::env::temp_dir().join("min_game_stitch.txt");
+        let path = path.to_str().unwrap();
+        w.save(path).unwrap();
+        let mut w2 = World::new();
+        w2.load(path).unwrap();
+        let _ = std::fs::remove_file(path);
+        assert_eq!(12, w2.offsets.len());
+        assert!(w2.get_offset(&w2.atlas[0], &w2.atlas[3]) == Some([9,0]));
+    }
+}
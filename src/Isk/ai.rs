@@ -0,0 +1,408 @@
+use crate::isk::*;
+use crate::isk::gps::*;
+use crate::isk::messages::*;
+use crate::isk::transition::*;
+use rand::Rng;
+
+// what an NPC does when no PC is in view.  Chasing and fleeing are chosen per turn, on sight of a PC.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Behavior {
+    Idle,
+    Wander,
+    Patrol,
+    Chase,
+    Flee
+}
+
+impl Behavior {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Behavior::Idle => return "idle",
+            Behavior::Wander => return "wander",
+            Behavior::Patrol => return "patrol",
+            Behavior::Chase => return "chase",
+            Behavior::Flee => return "flee"
+        }
+    }
+
+    // only the behaviors an ActorModel may start with
+    pub fn from_name(src:&str) -> Option<Behavior> {
+        match src {
+            "idle" => return Some(Behavior::Idle),
+            "wander" => return Some(Behavior::Wander),
+            "patrol" => return Some(Behavior::Patrol),
+            _ => return None
+        }
+    }
+}
+
+// everything an actor can do in one turn; the PC's handler and the AI both go through World::perform
+#[derive(Clone,Debug)]
+pub enum Action {
+    Wait,
+    Move(Location),
+    Use(Location,Verb),    // on the map object there
+    Attack(Location),
+    PickUp,     // the top of the pile underfoot
+    Drop(usize), // index into the actor's inventory
+    Equip(usize), // index into the actor's inventory
+    Remove(Slot)
+}
+
+// per-actor; saved with the actor's map memory
+#[derive(Clone)]
+pub struct AiState {
+    waypoints: Vec<Location>,  // patrol route; the first is the one being walked to
+    target: Option<Location>,  // where a PC was last seen
+    opened: Option<Location>   // a door passed through, to be closed behind
+}
+
+impl AiState {
+    pub fn new() -> AiState { return AiState{waypoints:Vec::new(), target:None, opened:None}; }
+
+    pub fn waypoints(&self) -> &[Location] { return &self.waypoints; }
+    pub fn set_waypoints(&mut self, src:Vec<Location>) { self.waypoints = src; }
+    pub fn target(&self) -> Option<&Location> { return self.target.as_ref(); }
+    pub fn set_target(&mut self, src:Option<Location>) { self.target = src; }
+    pub fn opened(&self) -> Option<&Location> { return self.opened.as_ref(); }
+    pub fn set_opened(&mut self, src:Option<Location>) { self.opened = src; }
+}
+
+impl World {
+    // spends energy only if the action succeeded (waiting always does)
+    pub fn perform(&mut self, r_act:&r_Actor, act:Action) -> bool {
+        let mut cost = BASE_ACTION_COST;
+        match act {
+            Action::Wait => {},
+            Action::Move(dest) => {
+                if !dest.is_walkable_for(&r_act.borrow()) { return false; }
+                cost = dest.get_terrain().move_energy();
+                self.move_actor(r_act, dest);   // handles transfer between owning maps
+            },
+            Action::Use(dest, verb) => {
+                let t = match self.check_transition(&r_act.borrow(), &dest, verb) {
+                    Ok(x) => x,
+                    Err(_) => return false
+                };
+                dest.set_map_object(t.to);
+                cost = t.cost;
+            },
+            Action::Attack(dest) => {
+                match dest.get_actor() {
+                    Some(r_def) if !Rc::ptr_eq(r_act, &r_def) => self.attack(r_act, &r_def),
+                    _ => return false
+                }
+            },
+            Action::PickUp => {
+                let loc = r_act.borrow().loc();
+                let item = match loc.get_items().last() {
+                    Some(x) => x.clone(),
+                    None => return false
+                };
+                if !r_act.borrow().inventory().can_take(&item) { return false; }
+                let item = loc.take_item().unwrap();
+                let what = item.describe();
+                let _ = r_act.borrow_mut().inventory_mut().add(item);
+                self.announce(&[loc], &|v| {
+                    let verb = if Rc::ptr_eq(v, r_act) { " pick up " } else { " picks up " };
+                    return describe(v, r_act)+verb+&what+".";
+                });
+            },
+            Action::Drop(n) => {
+                let item = match r_act.borrow_mut().inventory_mut().remove(n) {
+                    Some(x) => x,
+                    None => return false
+                };
+                let loc = r_act.borrow().loc();
+                let what = item.describe();
+                loc.add_item(item);
+                self.announce(&[loc], &|v| {
+                    let verb = if Rc::ptr_eq(v, r_act) { " drop " } else { " drops " };
+                    return describe(v, r_act)+verb+&what+".";
+                });
+            },
+            Action::Equip(n) => {
+                let what = match r_act.borrow().inventory().stacks().get(n) {
+                    Some(x) => x.model.name.clone(),
+                    None => return false
+                };
+                if !self.change_speed(r_act, |act| act.equip(n)) { return false; }
+                let loc = r_act.borrow().loc();
+                self.announce(&[loc], &|v| {
+                    let verb = if Rc::ptr_eq(v, r_act) { " equip " } else { " equips " };
+                    return describe(v, r_act)+verb+&what+".";
+                });
+            },
+            Action::Remove(slot) => {
+                let what = match r_act.borrow().equipped(slot) {
+                    Some(x) => x.model.name.clone(),
+                    None => return false
+                };
+                if !self.change_speed(r_act, |act| act.unequip(slot)) { return false; }
+                let loc = r_act.borrow().loc();
+                self.announce(&[loc], &|v| {
+                    let verb = if Rc::ptr_eq(v, r_act) { " remove " } else { " removes " };
+                    return describe(v, r_act)+verb+&what+".";
+                });
+            }
+        }
+        r_act.borrow_mut().spend_energy(cost);
+        return true;
+    }
+
+    // nearest PC within view radius and line of sight
+    pub fn visible_pc(&self, r_act:&r_Actor) -> Option<r_Actor> {
+        let origin = r_act.borrow().loc();
+        let mut maps = vec![Rc::clone(&origin.map)];
+        maps.extend(self.stitched_maps(&origin.map));
+        let mut ret:Option<(r_Actor,i32)> = None;
+        for r_m in maps {
+            for r_pc in r_m.borrow().actors() {
+                if !r_pc.borrow().is_pc { continue; }
+                let loc = r_pc.borrow().loc();
+                if let Some(x) = self.coerce_map(loc.clone(), Rc::clone(&origin.map)) {
+                    let dist = max((x.pos[0]-origin.pos[0]).abs(), (x.pos[1]-origin.pos[1]).abs());
+                    if VIEW_RADIUS < dist { continue; }
+                    if ret.as_ref().is_some_and(|y| y.1 <= dist) { continue; }
+                    if self.los(&origin, &loc) { ret = Some((Rc::clone(r_pc), dist)); }
+                }
+            }
+        }
+        return ret.map(|x| x.0);
+    }
+
+    // seen: the PC in view, if any (visible_pc)
+    pub fn choose_behavior(&self, r_act:&r_Actor, seen:Option<&r_Actor>) -> Behavior {
+        if seen.is_some() {
+            let act = r_act.borrow();
+            if 2*act.hp.curHp <= act.hp.maxHp { return Behavior::Flee; }
+            return Behavior::Chase;
+        }
+        return r_act.borrow().model.ai;
+    }
+
+    // one step towards an adjacent location: through a closed door by opening it first
+    fn step_to(&self, r_act:&r_Actor, dest:Location) -> Action {
+        if let Some(other) = dest.get_actor() {
+            if r_act.borrow().is_hostile_to(&other.borrow()) { return Action::Attack(dest); }
+            return Action::Wait;
+        }
+        if dest.is_walkable_for(&r_act.borrow()) { return Action::Move(dest); }
+        if let Some(verb) = self.opens_way(&r_act.borrow(), &dest) { return Action::Use(dest, verb); }
+        return Action::Wait;
+    }
+
+    fn walk_to(&self, r_act:&r_Actor, dest:&Location) -> Action {
+        let origin = r_act.borrow().loc();
+        let path = self.find_path(&r_act.borrow(), &origin, dest);
+        match path {
+            Some(p) if !p.is_empty() => return self.step_to(r_act, p[0].clone()),
+            _ => return Action::Wait
+        }
+    }
+
+    pub fn ai_action(&mut self, r_act:&r_Actor) -> Action {
+        let origin = r_act.borrow().loc();
+        let seen = self.visible_pc(r_act);
+        let behavior = self.choose_behavior(r_act, seen.as_ref());
+        match behavior {
+            Behavior::Chase | Behavior::Flee => {
+                let r_pc = seen.unwrap();
+                let pc_loc = r_pc.borrow().loc();
+                r_act.borrow_mut().ai_mut().target = Some(pc_loc.clone());
+                if Behavior::Chase == behavior { return self.walk_to(r_act, &pc_loc); }
+                let flee = match self.dijkstra_map(&r_act.borrow(), &[pc_loc], 2*VIEW_RADIUS) {
+                    Some(x) => x.flee(self, &r_act.borrow()),
+                    None => return Action::Wait
+                };
+                match self.downhill(&flee, &origin) {
+                    Some(dest) => return self.step_to(r_act, dest),
+                    None => return Action::Wait    // cornered
+                }
+            },
+            _ => {}
+        }
+
+        // lost sight of the PC: go to where it was last seen
+        let target = r_act.borrow().ai().target.clone();
+        if let Some(dest) = target {
+            if dest != origin {
+                let ret = self.walk_to(r_act, &dest);
+                if let Action::Move(_) | Action::Use(..) = ret { return ret; }
+            }
+            r_act.borrow_mut().ai_mut().target = None;
+        }
+
+        // close doors behind us
+        if let Some(obj) = origin.get_map_object() {
+            if self.get_transition(&obj.borrow().model, Verb::Close).is_some() { r_act.borrow_mut().ai_mut().opened = Some(origin.clone()); }
+        }
+        let opened = r_act.borrow().ai().opened.clone();
+        if let Some(door) = opened {
+            let delta = [door.pos[0]-origin.pos[0], door.pos[1]-origin.pos[1]];
+            if Rc::ptr_eq(&door.map, &origin.map) && door.pos != origin.pos && 1 >= delta[0].abs() && 1 >= delta[1].abs() {
+                r_act.borrow_mut().ai_mut().opened = None;
+                if self.check_transition(&r_act.borrow(), &door, Verb::Close).is_ok() { return Action::Use(door, Verb::Close); }
+            } else if door.pos != origin.pos {
+                r_act.borrow_mut().ai_mut().opened = None;
+            }
+        }
+
+        match behavior {
+            Behavior::Wander => {
+                let dir = Compass::try_from(self.rng.gen_range(0, 8)).unwrap();
+                match self.canonical_loc(origin+dir) {
+                    Some(dest) => {
+                        if dest.is_walkable_for(&r_act.borrow()) { return Action::Move(dest); }
+                        return Action::Wait;
+                    },
+                    None => return Action::Wait
+                }
+            },
+            Behavior::Patrol => {
+                let mut ai = r_act.borrow().ai().clone();
+                if ai.waypoints.is_empty() { return Action::Wait; }
+                if ai.waypoints[0] == origin {
+                    ai.waypoints.rotate_left(1);
+                    r_act.borrow_mut().ai_mut().set_waypoints(ai.waypoints.clone());
+                }
+                return self.walk_to(r_act, &ai.waypoints[0]);
+            },
+            _ => return Action::Wait
+        }
+    }
+
+    // falls back to waiting, so the actor's energy is always spent
+    pub fn npc_turn(&mut self, r_act:&r_Actor) {
+        debug_assert!(!r_act.borrow().is_pc);
+        let act = self.ai_action(r_act);
+        if !self.perform(r_act, act) { self.perform(r_act, Action::Wait); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an open room, with a wall down x = 3 and a closed door at its foot if walled; the NPC starts at [1,1]
+    fn room(seed:u64, ai:Behavior, walled:bool) -> (World, r_Actor, r_Map) {
+        let mut w = World::new();
+        w.reseed(seed);
+        w.load_content("data/content.txt").unwrap();
+        let floor = w.get_terrain("stone floor").unwrap();
+        let r_m = w.new_map("test", [9,5], floor);
+        if walled {
+            let wall = w.get_terrain("wall").unwrap();
+            for y in 0..4 { r_m.borrow_mut().set_terrain([3,y], Rc::clone(&wall)); }
+            Location::new(&r_m, [3,4]).set_map_object(w.get_map_object_model("door (closed)").unwrap());
+        }
+        let mut model = ActorModel::new("m", Ok(CharSpec{img:'m', c:None}));
+        model.ai = ai;
+        let model = w.new_actor_model(model);
+        let npc = w.new_actor(model, &Location::new(&r_m, [0,0]), [1,1]).unwrap();
+        return (w, npc, r_m);
+    }
+
+    fn add_pc(w:&mut World, r_m:&r_Map, pos:[i32;2]) -> r_Actor {
+        let model = w.new_actor_model(ActorModel::new("pc", Ok(CharSpec{img:'@', c:None})));
+        let pc = w.new_actor(model, &Location::new(r_m, [0,0]), pos).unwrap();
+        pc.borrow_mut().is_pc = true;
+        return pc;
+    }
+
+    fn dist(a:[i32;2], b:[i32;2]) -> i32 { return max((a[0]-b[0]).abs(), (a[1]-b[1]).abs()); }
+
+    fn pos(r_act:&r_Actor) -> [i32;2] { return r_act.borrow().loc().pos; }
+
+    #[test]
+    fn npcs_chase_a_pc_in_view_and_flee_when_hurt() {
+        let (mut w, npc, r_m) = room(1, Behavior::Idle, false);
+        let pc = add_pc(&mut w, &r_m, [5,1]);
+        assert_eq!(Behavior::Chase, w.choose_behavior(&npc, w.visible_pc(&npc).as_ref()));
+        w.npc_turn(&npc);
+        assert_eq!(3, dist(pos(&npc), pos(&pc)));
+        assert!(npc.borrow().ai().target() == Some(&pc.borrow().loc()));
+
+        w.move_actor(&npc, Location::new(&r_m, [4,1]));
+        assert!(matches!(w.ai_action(&npc), Action::Attack(dest) if dest == pc.borrow().loc()));
+
+        // at half HP or less it backs off instead
+        let hp = npc.borrow().hp.maxHp;
+        npc.borrow_mut().hp.curHp = hp/2;
+        assert_eq!(Behavior::Flee, w.choose_behavior(&npc, Some(&pc)));
+        for n in 2..5 {
+            w.npc_turn(&npc);
+            assert_eq!(n, dist(pos(&npc), pos(&pc)));
+        }
+        npc.borrow_mut().hp.curHp = hp/2+1;
+        assert_eq!(Behavior::Chase, w.choose_behavior(&npc, Some(&pc)));
+    }
+
+    #[test]
+    fn chasers_go_where_the_pc_was_last_seen() {
+        let (mut w, npc, r_m) = room(1, Behavior::Idle, false);
+        npc.borrow_mut().ai_mut().set_target(Some(Location::new(&r_m, [4,3])));
+        assert_eq!(Behavior::Idle, w.choose_behavior(&npc, w.visible_pc(&npc).as_ref()));
+        for _ in 0..3 { w.npc_turn(&npc); }
+        assert_eq!([4,3], pos(&npc));
+        // there, with no PC in view: back to idling
+        assert!(matches!(w.ai_action(&npc), Action::Wait));
+        assert!(npc.borrow().ai().target().is_none());
+    }
+
+    #[test]
+    fn wanderers_step_at_random_the_same_way_for_a_seed() {
+        let walk = |seed:u64| -> Vec<[i32;2]> {
+            let (mut w, npc, _) = room(seed, Behavior::Wander, false);
+            let mut ret = vec![pos(&npc)];
+            for _ in 0..20 {
+                w.npc_turn(&npc);
+                let here = pos(&npc);
+                assert!(1 >= dist(here, *ret.last().unwrap()));
+                ret.push(here);
+            }
+            return ret;
+        };
+        let a = walk(4);
+        assert_eq!(a, walk(4));
+        assert!(a.windows(2).any(|x| x[0] != x[1]));
+        assert_ne!(a, walk(5));
+    }
+
+    #[test]
+    fn patrols_walk_their_waypoints_in_turn() {
+        let (mut w, npc, r_m) = room(1, Behavior::Patrol, false);
+        npc.borrow_mut().ai_mut().set_waypoints(vec![Location::new(&r_m, [5,1]), Location::new(&r_m, [1,3])]);
+        let mut visits = Vec::<[i32;2]>::new();
+        for _ in 0..24 {
+            w.npc_turn(&npc);
+            if [[5,1], [1,3]].contains(&pos(&npc)) && visits.last() != Some(&pos(&npc)) { visits.push(pos(&npc)); }
+        }
+        assert_eq!(vec![[5,1], [1,3], [5,1], [1,3], [5,1], [1,3]], visits);
+        // with no route, it waits
+        npc.borrow_mut().ai_mut().set_waypoints(Vec::new());
+        assert!(matches!(w.ai_action(&npc), Action::Wait));
+    }
+
+    #[test]
+    fn doors_are_opened_and_closed_behind() {
+        let (mut w, npc, r_m) = room(1, Behavior::Patrol, true);
+        let door = Location::new(&r_m, [3,4]);
+        let door_name = || door.get_map_object().unwrap().borrow().model.name.clone();
+        w.move_actor(&npc, Location::new(&r_m, [2,4]));
+        npc.borrow_mut().ai_mut().set_waypoints(vec![Location::new(&r_m, [6,4])]);
+        w.npc_turn(&npc);
+        assert_eq!(([2,4], "door (open)".to_string()), (pos(&npc), door_name()));
+        w.npc_turn(&npc);
+        assert_eq!([3,4], pos(&npc));
+        w.npc_turn(&npc);
+        assert_eq!((4, "door (open)".to_string()), (pos(&npc)[0], door_name()));   // past it, by whichever equal path
+        assert!(npc.borrow().ai().opened() == Some(&door));
+        let past = pos(&npc);
+        w.npc_turn(&npc);
+        assert_eq!((past, "door (closed)".to_string()), (pos(&npc), door_name()));
+        assert!(npc.borrow().ai().opened().is_none());
+        w.npc_turn(&npc);
+        assert_eq!(5, pos(&npc)[0]);
+    }
+}
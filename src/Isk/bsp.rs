@@ -0,0 +1,180 @@
+use crate::isk::*;
+use crate::isk::gps::*;
+use rand::Rng;
+
+// binary space partitioning into walled rooms.  Sizes are outer dimensions, walls included; neighboring rooms share
+// their wall, so a min_size of 4 is the smallest that always leaves room for a door between neighbors.
+// Each split connects the two halves through one door, so the rooms form a tree.
+pub struct RoomGraph {
+    pub rooms: Vec<MapRect>,
+    pub doors: Vec<(usize,usize,[i32;2])>    // the rooms on either side, and where the door is
+}
+
+impl RoomGraph {
+    pub fn draw(&self, r_m:&r_Map, door:&r_MapObjectModel) {
+        let mut m = r_m.borrow_mut();
+        for room in &self.rooms { room.draw(&mut m); }
+        for x in &self.doors {
+            m.set_terrain(x.2, Rc::clone(self.rooms[x.0].floor()));
+            m.set_map_object(Rc::new(RefCell::new(MapObject::new(Rc::clone(door), Location::new(r_m, x.2)))));
+        }
+    }
+}
+
+// cells of a room's wall on the given side, corners excluded
+fn wall_cells(src:&Rect, dir:Compass) -> Vec<[i32;2]> {
+    let nw = src.anchor(Compass::NW);
+    let se = src.anchor(Compass::SE);
+    match dir {
+        Compass::N => return (nw[0]+1..se[0]-1).map(|x| [x, nw[1]]).collect(),
+        Compass::E => return (nw[1]+1..se[1]-1).map(|y| [se[0]-1, y]).collect(),
+        Compass::S => return (nw[0]+1..se[0]-1).map(|x| [x, se[1]-1]).collect(),
+        Compass::W => return (nw[1]+1..se[1]-1).map(|y| [nw[0], y]).collect(),
+        _ => {
+            debug_assert!(false, "diagonal wall");
+            return Vec::new();
+        }
+    }
+}
+
+struct Bsp<'a, R: Rng + ?Sized> {
+    rng: &'a mut R,
+    min_size: usize,
+    max_size: usize,
+    rooms: Vec<Rect>,
+    doors: Vec<(usize,usize,[i32;2])>
+}
+
+impl<'a, R: Rng + ?Sized> Bsp<'a, R> {
+    // the indexes of the rooms src was partitioned into
+    fn partition(&mut self, src:Rect) -> Vec<usize> {
+        let w = src.width();
+        let h = src.height();
+        let can_split_x = w >= 2*self.min_size-1;
+        let can_split_y = h >= 2*self.min_size-1;
+        let too_big = w > self.max_size || h > self.max_size;
+        if !(can_split_x || can_split_y) || (!too_big && 0 == self.rng.gen_range(0, 3)) {
+            self.rooms.push(src);
+            return vec![self.rooms.len()-1];
+        }
+        // split across the longer side, when there is a choice; the part split off is on either side
+        let split_x = if can_split_x && can_split_y { w > h || (w == h && self.rng.gen()) } else { can_split_x };
+        let dir = if split_x { if self.rng.gen() { Compass::E } else { Compass::W } }
+            else if self.rng.gen() { Compass::S } else { Compass::N };
+        let len = if split_x { w } else { h };
+        // both halves keep the wall on the cut line
+        let mut kept = src.clone();
+        let rest = kept.split(self.rng, dir.clone(), self.min_size-1, len-self.min_size+1).unwrap();
+        let mut nw = kept.anchor(Compass::NW);
+        match dir {
+            Compass::W => nw[0] -= 1,
+            Compass::N => nw[1] -= 1,
+            _ => ()
+        }
+        let kept = if split_x { Rect::new(nw, [kept.width()+1, h]) } else { Rect::new(nw, [w, kept.height()+1]) };
+        let mut ret = self.partition(kept);
+        let other = self.partition(rest);
+        self.connect(&ret, &other, dir);
+        ret.extend(other);
+        return ret;
+    }
+
+    // one door in a wall shared by a room from each side of the cut
+    fn connect(&mut self, near:&[usize], far:&[usize], dir:Compass) {
+        let back = dir.opposite();
+        let mut choices = Vec::<(usize,usize,[i32;2])>::new();
+        for a in near {
+            let a_cells = wall_cells(&self.rooms[*a], dir.clone());
+            for b in far {
+                for pt in wall_cells(&self.rooms[*b], back.clone()) {
+                    if a_cells.contains(&pt) { choices.push((*a, *b, pt)); }
+                }
+            }
+        }
+        debug_assert!(!choices.is_empty() || self.min_size < 4);
+        if choices.is_empty() { return; }
+        let n = self.rng.gen_range(0, choices.len());
+        self.doors.push(choices[n]);
+    }
+}
+
+// the rooms take their floor and wall from src; src's own wall code is ignored
+pub fn bsp_rooms<R: Rng + ?Sized>(r:&mut R, src:&MapRect, min_size:usize, max_size:usize) -> RoomGraph {
+    debug_assert!(3 <= min_size && min_size <= max_size);
+    let mut bsp = Bsp{rng:r, min_size, max_size, rooms:Vec::new(), doors:Vec::new()};
+    bsp.partition(src.rect.clone());
+    let mut rooms = Vec::<MapRect>::with_capacity(bsp.rooms.len());
+    for x in bsp.rooms {
+        let mut room = src.clone();
+        room.rect = x;
+        room.set_wallcode(1,1,1,1);
+        rooms.push(room);
+    }
+    return RoomGraph{rooms, doors:bsp.doors};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isk::rng::*;
+
+    fn inside(src:&Rect, pt:[i32;2]) -> bool {
+        let nw = src.anchor(Compass::NW);
+        let se = src.anchor(Compass::SE);
+        return nw[0] <= pt[0] && pt[0] < se[0] && nw[1] <= pt[1] && pt[1] < se[1];
+    }
+
+    #[test]
+    fn split_keeps_the_far_side() {
+        let mut r = GameRng::new(5);
+        for dir in CARDINALS.iter() {
+            let mut a = Rect::new([2,3], [10,8]);
+            let b = a.split(&mut r, dir.clone(), 3, 4).unwrap();
+            assert_eq!(80, a.width()*a.height()+b.width()*b.height());
+            let (a_nw, b_nw) = (a.anchor(Compass::NW), b.anchor(Compass::NW));
+            match dir {
+                Compass::N => { assert_eq!(3, a.height()); assert_eq!([2,3], b_nw); assert_eq!([2,8], a_nw); },
+                Compass::E => { assert_eq!(3, a.width()); assert_eq!([2,3], a_nw); assert_eq!([5,3], b_nw); },
+                Compass::S => { assert_eq!(3, a.height()); assert_eq!([2,3], a_nw); assert_eq!([2,6], b_nw); },
+                _ => { assert_eq!(3, a.width()); assert_eq!([2,3], b_nw); assert_eq!([9,3], a_nw); }
+            }
+        }
+    }
+
+    #[test]
+    fn rooms_tile_the_area_as_a_tree() {
+        let mut w = World::new();
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:None}), true, true));
+        let wall = w.new_terrain(Terrain::new("wall", Ok(CharSpec{img:'#', c:None}), false, false));
+        let door = w.new_map_object_model(MapObjectModel::new("door", Ok(CharSpec{img:'+', c:None}), true, false));
+        let src = MapRect::new(Rect::new([1,1], [40,30]), Rc::clone(&floor), Rc::clone(&wall));
+        let mut first_room_nw = 0;
+        for seed in 0..100 {
+            let g = bsp_rooms(&mut GameRng::new(seed), &src, 4, 10);
+            assert!(1 < g.rooms.len());
+            assert_eq!(g.rooms.len()-1, g.doors.len());
+            for x in &g.rooms { assert!((4..=10).contains(&x.rect.width()) && (4..=10).contains(&x.rect.height())); }
+            // room interiors cover the area inside the shared walls exactly once
+            let m = w.new_map("bsp", [42,32], Rc::clone(&floor));
+            g.draw(&m, &door);
+            let interior = g.rooms.iter().map(|x| (x.rect.width()-2)*(x.rect.height()-2)).sum::<usize>();
+            let floors = (0..32).flat_map(|y| (0..42).map(move |x| [x,y])).filter(|pt| 1 <= pt[0] && 40 >= pt[0] && 1 <= pt[1] && 30 >= pt[1])
+                .filter(|pt| Rc::ptr_eq(&floor, &m.borrow().get_terrain(*pt)) && m.borrow().get_map_object(*pt).is_none()).count();
+            assert_eq!(interior, floors);
+            // every door is in the wall between its two rooms, and they connect everything
+            let mut seen = vec![false; g.rooms.len()];
+            let mut todo = vec![0];
+            while let Some(n) = todo.pop() {
+                if seen[n] { continue; }
+                seen[n] = true;
+                for x in &g.doors {
+                    assert!(inside(&g.rooms[x.0].rect, x.2) && inside(&g.rooms[x.1].rect, x.2));
+                    if n == x.0 { todo.push(x.1); } else if n == x.1 { todo.push(x.0); }
+                }
+            }
+            assert!(seen.iter().all(|x| *x));
+            if [1,1] == g.rooms[0].rect.anchor(Compass::NW) { first_room_nw += 1; }
+        }
+        assert!(first_room_nw < 100);    // rooms are split off toward the west and north too
+    }
+}
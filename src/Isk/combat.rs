@@ -0,0 +1,133 @@
+use crate::isk::*;
+use crate::isk::messages::*;
+use rand::Rng;
+
+pub const BASE_ATTACK:i16 = 5;
+pub const BASE_DEFENSE:i16 = 5;
+pub const BASE_DAMAGE:i16 = 3;
+
+impl World {
+    // the dead leave the map, dropping what they carried and wore: at once when killed in play, otherwise when the
+    // scheduler comes to them
+    pub fn remove_dead(r_act:&r_Actor) {
+        debug_assert!(r_act.borrow().is_dead());
+        let loc = r_act.borrow().loc();
+        {
+        let mut act = r_act.borrow_mut();
+        for item in std::mem::take(&mut act.equipment) { loc.add_item(item); }
+        while let Some(item) = act.inventory_mut().remove(0) { loc.add_item(item); }
+        }
+        let mut m = loc.map.borrow_mut();
+        m.vacate(loc.pos, r_act.as_ptr());
+        m.remove_actor(r_act);
+    }
+
+    // killed in play: announced while still on the map, so a dying PC hears of it too
+    pub fn kill(&self, r_act:&r_Actor) {
+        let loc = r_act.borrow().loc();
+        self.announce(&[loc], &|v| {
+            let verb = if Rc::ptr_eq(v, r_act) { " die." } else { " dies." };
+            return describe(v, r_act)+verb;
+        });
+        World::remove_dead(r_act);
+    }
+
+    // to hit: attack vs. defense
+    pub fn attack(&mut self, r_att:&r_Actor, r_def:&r_Actor) {
+        let (attack, damage) = { let att = r_att.borrow(); (att.attack(), att.damage()) };
+        let defense = r_def.borrow().defense();
+        let locs = [r_att.borrow().loc(), r_def.borrow().loc()];
+        if self.rng.gen_range(0, max(1, attack+defense)) >= attack {
+            self.announce(&locs, &|v| {
+                let verb = if Rc::ptr_eq(v, r_att) { " miss " } else { " misses " };
+                return describe(v, r_att)+verb+&describe(v, r_def)+".";
+            });
+            return;
+        }
+        let dealt = self.rng.gen_range(1, max(1, damage)+1);
+        r_def.borrow_mut().hp_mut().takeHit(dealt);
+        self.announce(&locs, &|v| {
+            let verb = if Rc::ptr_eq(v, r_att) { " hit " } else { " hits " };
+            return describe(v, r_att)+verb+&describe(v, r_def)+" for "+&dealt.to_string()+".";
+        });
+        if r_def.borrow().is_dead() { self.kill(r_def); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isk::item::*;
+
+    // attacker at [1,1], defender at [2,1], both seen by the PC (the defender, if pc_defends)
+    fn duel(attack:i16, defense:i16, pc_defends:bool) -> (World, r_Actor, r_Actor) {
+        let mut w = World::new();
+        w.reseed(2);
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:None}), true, true));
+        let r_m = w.new_map("test", [4,3], floor);
+        let mut model = ActorModel::new("orc", Ok(CharSpec{img:'o', c:None}));
+        model.attack = attack;
+        model.defense = defense;
+        model.damage = 2;
+        let model = w.new_actor_model(model);
+        let att = w.new_actor(Rc::clone(&model), &Location::new(&r_m, [0,0]), [1,1]).unwrap();
+        let def = w.new_actor(model, &Location::new(&r_m, [0,0]), [2,1]).unwrap();
+        if pc_defends { def.borrow_mut().is_pc = true; } else { att.borrow_mut().is_pc = true; }
+        return (w, att, def);
+    }
+
+    fn last_message(r_pc:&r_Actor) -> String {
+        let mut c = get_messages_cache_mut();
+        let msgs = c.get(Rc::clone(r_pc));
+        return msgs.message(msgs.count()-1).map_or(String::new(), |x| x.0.clone());
+    }
+
+    #[test]
+    fn attacks_hit_or_miss_on_attack_against_defense() {
+        let (mut w, att, def) = duel(0, 5, false);
+        let hp = def.borrow().hp.curHp;
+        for _ in 0..20 { w.attack(&att, &def); }
+        assert_eq!(hp, def.borrow().hp.curHp);
+        assert_eq!("You miss the orc.", last_message(&att));
+
+        let (mut w, att, def) = duel(5, 0, false);
+        let hp = def.borrow().hp.curHp;
+        w.attack(&att, &def);
+        let dealt = hp-def.borrow().hp.curHp;
+        assert!((1..=2).contains(&dealt));
+        assert_eq!(format!("You hit the orc for {}.", dealt), last_message(&att));
+    }
+
+    #[test]
+    fn the_dead_leave_the_map_and_drop_their_things() {
+        let (mut w, att, def) = duel(5, 0, false);
+        let loc = def.borrow().loc();
+        let rock = w.new_item_model(ItemModel::new("rock", Ok(CharSpec{img:'*', c:None})));
+        let mut helmet = ItemModel::new("helmet", Ok(CharSpec{img:'^', c:None}));
+        helmet.slot = Some(Slot::Helmet);
+        let helmet = w.new_item_model(helmet);
+        {
+        let mut act = def.borrow_mut();
+        assert!(act.inventory_mut().add(Item::new(rock, 3)).is_ok());
+        assert!(act.inventory_mut().add(Item::new(helmet, 1)).is_ok());
+        assert!(act.equip(1));
+        act.hp.curHp = 1;
+        }
+        w.attack(&att, &def);
+        assert!(def.borrow().is_dead());
+        assert!(loc.get_actor().is_none());
+        assert!(!loc.map.borrow().actors().iter().any(|a| Rc::ptr_eq(a, &def)));
+        let dropped: Vec<String> = loc.get_items().iter().map(|x| x.describe()).collect();
+        assert_eq!(vec!["helmet".to_string(), "3 x rock".to_string()], dropped);
+        assert_eq!("The orc dies.", last_message(&att));
+    }
+
+    #[test]
+    fn a_dying_pc_hears_of_it() {
+        let (mut w, att, def) = duel(5, 0, true);
+        def.borrow_mut().hp.curHp = 1;
+        w.attack(&att, &def);
+        assert!(def.borrow().is_dead() && att.borrow().loc().map.borrow().actors().len() == 1);
+        assert_eq!("You die.", last_message(&def));
+    }
+}
@@ -0,0 +1,439 @@
+use crate::isk::*;
+use crate::isk::fluid::MAX_WATER_DEPTH;
+use crate::isk::save::str_to_color;
+use crate::isk::transition::*;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// content files: plain text.  Each definition starts with a [terrain], [object], [item], or [actor] header line,
+// followed by key = value lines.  Lines starting with # are comments.
+// * all: name (unique within its kind), glyph (single character; quote as ' ' for whitespace), color (optional)
+// * terrain: walkable, transparent, bg (optional; default black), sunken, drain (optional; water depths; default 0),
+//   flow (optional; direction name, n ... nw, that a current carries water), power (optional; default false),
+//   conveyor (optional; direction name that actors and items are carried), unsupported (optional; default false),
+//   move_cost (optional; percent of BASE_ACTION_COST; default 100)
+// * object: walkable, transparent, spring (optional; water depth added per turn; default 0), transmits (optional; power;
+//   default false), powered (optional; what this machinery becomes while powered, and reverts from when not)
+//   and for each verb (open, close, lock, unlock, break, pull), all optional: verb = what the object becomes;
+//   verb needs = comma-separated preconditions (key item name, free cell, strength n); verb cost = energy (default
+//   BASE_ACTION_COST)
+// * item: slot (optional; weapon, armor, helmet, or boots), speed, hp, attack, defense, damage (optional; modifiers while equipped)
+// * actor: base_AP (optional; default BASE_ACTION_COST), base_HP (optional; default BASE_HP), attack, defense, damage (optional;
+//   defaults BASE_ATTACK, BASE_DEFENSE, BASE_DAMAGE), capacity (optional; inventory stacks; default BASE_CAPACITY),
+//   ai (optional; idle, wander, or patrol; default idle)
+// colors are either r,g,b or a name from COLOR_NAMES.  Objects may refer to objects and items defined later in the file.
+
+const COLOR_NAMES: [(&str, colors::Color); 38] = [
+    ("black", colors::BLACK), ("white", colors::WHITE),
+    ("darkest grey", colors::DARKEST_GREY), ("darker grey", colors::DARKER_GREY), ("dark grey", colors::DARK_GREY), ("grey", colors::GREY),
+    ("light grey", colors::LIGHT_GREY), ("lighter grey", colors::LIGHTER_GREY), ("lightest grey", colors::LIGHTEST_GREY),
+    ("darkest sepia", colors::DARKEST_SEPIA), ("darker sepia", colors::DARKER_SEPIA), ("dark sepia", colors::DARK_SEPIA), ("sepia", colors::SEPIA),
+    ("light sepia", colors::LIGHT_SEPIA), ("lighter sepia", colors::LIGHTER_SEPIA), ("lightest sepia", colors::LIGHTEST_SEPIA),
+    ("red", colors::RED), ("flame", colors::FLAME), ("orange", colors::ORANGE), ("amber", colors::AMBER), ("yellow", colors::YELLOW),
+    ("lime", colors::LIME), ("chartreuse", colors::CHARTREUSE), ("green", colors::GREEN), ("sea", colors::SEA), ("turquoise", colors::TURQUOISE),
+    ("cyan", colors::CYAN), ("sky", colors::SKY), ("azure", colors::AZURE), ("blue", colors::BLUE), ("han", colors::HAN),
+    ("violet", colors::VIOLET), ("purple", colors::PURPLE), ("fuchsia", colors::FUCHSIA), ("magenta", colors::MAGENTA), ("pink", colors::PINK),
+    ("crimson", colors::CRIMSON), ("brass", colors::BRASS)
+];
+
+pub fn parse_color(src:&str) -> Result<colors::Color,Error> {
+    for x in COLOR_NAMES.iter() {
+        if x.0 == src { return Ok(x.1); }
+    }
+    return str_to_color(src);
+}
+
+// one [section] of a content file
+struct ContentDef {
+    kind: String,
+    line_no: usize,
+    fields: HashMap<String,(String,usize)>
+}
+
+impl ContentDef {
+    fn error(&self, path:&str, line_no:usize, msg:&str) -> Error {
+        return Error{desc:format!("{}:{}: [{}] {}", path, line_no, self.kind, msg)};
+    }
+
+    fn get(&self, path:&str, key:&str) -> Result<&(String,usize),Error> {
+        match self.fields.get(key) {
+            Some(x) => return Ok(x),
+            None => return Err(self.error(path, self.line_no, &("missing ".to_string()+key)))
+        }
+    }
+
+    fn name(&self, path:&str) -> Result<String,Error> { return Ok(self.get(path, "name")?.0.clone()); }
+
+    fn bool(&self, path:&str, key:&str) -> Result<bool,Error> {
+        let (val, line_no) = self.get(path, key)?;
+        match val.as_str() {
+            "true" | "yes" => return Ok(true),
+            "false" | "no" => return Ok(false),
+            _ => return Err(self.error(path, *line_no, &format!("{} should be true or false: {}", key, val)))
+        }
+    }
+
+    fn flag(&self, path:&str, key:&str) -> Result<bool,Error> {
+        if !self.fields.contains_key(key) { return Ok(false); }
+        return self.bool(path, key);
+    }
+
+    fn color(&self, path:&str, key:&str) -> Result<Option<colors::Color>,Error> {
+        match self.fields.get(key) {
+            Some((val, line_no)) => {
+                match parse_color(val) {
+                    Ok(col) => return Ok(Some(col)),
+                    Err(e) => return Err(self.error(path, *line_no, &e.desc))
+                }
+            },
+            None => return Ok(None)
+        }
+    }
+
+    fn tile(&self, path:&str) -> Result<TileSpec,Error> {
+        let (val, line_no) = self.get(path, "glyph")?;
+        let chars: Vec<char> = val.chars().collect();
+        let img = match chars.len() {
+            1 => chars[0],
+            3 if '\'' == chars[0] && '\'' == chars[2] => chars[1],
+            _ => return Err(self.error(path, *line_no, &("glyph should be a single character: ".to_string()+val)))
+        };
+        return Ok(Ok(CharSpec{img, c:self.color(path, "color")?}));
+    }
+
+    fn positive(&self, path:&str, key:&str) -> Result<Option<i16>,Error> {
+        match self.fields.get(key) {
+            Some((val, line_no)) => {
+                match i16::from_str(val) {
+                    Ok(x) if 0 < x => return Ok(Some(x)),
+                    _ => return Err(self.error(path, *line_no, &format!("{} should be a positive integer: {}", key, val)))
+                }
+            },
+            None => return Ok(None)
+        }
+    }
+
+    fn integer(&self, path:&str, key:&str) -> Result<Option<i16>,Error> {
+        match self.fields.get(key) {
+            Some((val, line_no)) => {
+                match i16::from_str(val) {
+                    Ok(x) => return Ok(Some(x)),
+                    _ => return Err(self.error(path, *line_no, &format!("{} should be an integer: {}", key, val)))
+                }
+            },
+            None => return Ok(None)
+        }
+    }
+
+    fn depth(&self, path:&str, key:&str) -> Result<u8,Error> {
+        match self.fields.get(key) {
+            Some((val, line_no)) => {
+                match u8::from_str(val) {
+                    Ok(x) if MAX_WATER_DEPTH >= x => return Ok(x),
+                    _ => return Err(self.error(path, *line_no, &format!("{} should be a water depth, 0 to {}: {}", key, MAX_WATER_DEPTH, val)))
+                }
+            },
+            None => return Ok(0)
+        }
+    }
+
+    fn direction(&self, path:&str, key:&str) -> Result<Option<Compass>,Error> {
+        match self.fields.get(key) {
+            Some((val, line_no)) => {
+                match direction_from_name(val) {
+                    Some(dir) => return Ok(Some(dir)),
+                    None => return Err(self.error(path, *line_no, &format!("{} should be a direction, n ... nw: {}", key, val)))
+                }
+            },
+            None => return Ok(None)
+        }
+    }
+
+    fn needs(&self, path:&str, key:&str, item:&dyn Fn(&str) -> Option<r_ItemModel>) -> Result<Vec<Precondition>,Error> {
+        let mut ret = Vec::<Precondition>::new();
+        let (val, line_no) = match self.fields.get(key) {
+            Some(x) => x,
+            None => return Ok(ret)
+        };
+        for need in val.split(',').map(|x| x.trim()) {
+            if "free cell" == need {
+                ret.push(Precondition::FreeCell);
+            } else if let Some(name) = need.strip_prefix("key ") {
+                match item(name.trim()) {
+                    Some(x) => ret.push(Precondition::Key(x)),
+                    None => return Err(self.error(path, *line_no, &format!("{}: unknown item {}", key, name.trim())))
+                }
+            } else if let Some(n) = need.strip_prefix("strength ") {
+                match i16::from_str(n.trim()) {
+                    Ok(x) if 0 < x => ret.push(Precondition::Strength(x)),
+                    _ => return Err(self.error(path, *line_no, &format!("{}: strength should be a positive integer: {}", key, n.trim())))
+                }
+            } else {
+                return Err(self.error(path, *line_no, &format!("{} should be key item, free cell, or strength n: {}", key, need)));
+            }
+        }
+        return Ok(ret);
+    }
+
+    fn reference(&self, key:&str) -> Option<(String,usize)> {
+        return self.fields.get(key).cloned();
+    }
+}
+
+// the keys the header comment lists for each kind of section
+fn is_known_key(kind:&str, key:&str) -> bool {
+    if ["name", "glyph", "color"].contains(&key) { return true; }
+    match kind {
+        "terrain" => return ["walkable", "transparent", "bg", "sunken", "drain", "flow", "power", "conveyor", "unsupported", "move_cost"].contains(&key),
+        "object" => {
+            if ["walkable", "transparent", "spring", "transmits", "powered"].contains(&key) { return true; }
+            let verb = key.strip_suffix(" needs").or_else(|| key.strip_suffix(" cost")).unwrap_or(key);
+            return Verb::from_name(verb).is_some();
+        },
+        "item" => return ["slot", "speed", "hp", "attack", "defense", "damage"].contains(&key),
+        "actor" => return ["base_AP", "base_HP", "attack", "defense", "damage", "capacity", "ai"].contains(&key),
+        _ => return false
+    }
+}
+
+fn parse_defs(path:&str, src:&str) -> Result<Vec<ContentDef>,Error> {
+    let mut ret = Vec::<ContentDef>::new();
+    for (n, raw) in src.lines().enumerate() {
+        let line_no = n+1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        if line.starts_with('[') && line.ends_with(']') {
+            let kind = line[1..line.len()-1].trim();
+            match kind {
+                "terrain" | "object" | "item" | "actor" => ret.push(ContentDef{kind:kind.to_string(), line_no, fields:HashMap::new()}),
+                _ => return Err(Error{desc:format!("{}:{}: unknown section [{}]", path, line_no, kind)})
+            }
+            continue;
+        }
+        let def = match ret.last_mut() {
+            Some(def) => def,
+            None => return Err(Error{desc:format!("{}:{}: key = value outside of a section", path, line_no)})
+        };
+        let kv: Vec<&str> = raw.splitn(2, '=').collect();
+        if 2 != kv.len() { return Err(def.error(path, line_no, "expected key = value")); }
+        let key = kv[0].trim();
+        let val = kv[1].trim();
+        if !is_known_key(&def.kind, key) { return Err(def.error(path, line_no, &("unknown key ".to_string()+key))); }
+        if def.fields.insert(key.to_string(), (val.to_string(), line_no)).is_some() {
+            return Err(def.error(path, line_no, &("duplicate key ".to_string()+key)));
+        }
+    }
+    return Ok(ret);
+}
+
+impl World {
+    pub fn load_content(&mut self, path:&str) -> Result<(),Error> {
+        match std::fs::read_to_string(path) {
+            Ok(src) => return self.parse_content(path, &src),
+            Err(e) => return Err(Error{desc:path.to_string()+": "+&e.to_string()})
+        }
+    }
+
+    // path is only used for error messages.  Nothing is registered unless the whole file is valid.
+    pub fn parse_content(&mut self, path:&str, src:&str) -> Result<(),Error> {
+        let defs = parse_defs(path, src)?;
+
+        let mut terrain = Vec::<Terrain>::new();
+        let mut items = Vec::<r_ItemModel>::new();
+        let mut actors = Vec::<ActorModel>::new();
+        let mut objs = Vec::<&ContentDef>::new();
+        for def in &defs {
+            let name = def.name(path)?;
+            match def.kind.as_str() {
+                "terrain" => {
+                    if self.get_terrain(&name).is_some() || terrain.iter().any(|t| t.is_named(&name)) {
+                        return Err(def.error(path, def.line_no, &("duplicate terrain ".to_string()+&name)));
+                    }
+                    let mut t = Terrain::new(&name, def.tile(path)?, def.bool(path, "walkable")?, def.bool(path, "transparent")?);
+                    if let Some(col) = def.color(path, "bg")? { t.bg = Ok(col); }
+                    t.sunken = def.depth(path, "sunken")?;
+                    t.flow = def.direction(path, "flow")?;
+                    t.drain = def.depth(path, "drain")?;
+                    t.power = def.flag(path, "power")?;
+                    t.conveyor = def.direction(path, "conveyor")?;
+                    t.unsupported = def.flag(path, "unsupported")?;
+                    if let Some(x) = def.positive(path, "move_cost")? { t.move_cost = x; }
+                    terrain.push(t);
+                },
+                "item" => {
+                    if self.get_item_model(&name).is_some() || items.iter().any(|i| i.is_named(&name)) {
+                        return Err(def.error(path, def.line_no, &("duplicate item ".to_string()+&name)));
+                    }
+                    let mut i_type = ItemModel::new(&name, def.tile(path)?);
+                    if let Some((val, line_no)) = def.fields.get("slot") {
+                        match Slot::from_name(val) {
+                            Some(slot) => i_type.slot = Some(slot),
+                            None => return Err(def.error(path, *line_no, &("slot should be weapon, armor, helmet, or boots: ".to_string()+val)))
+                        }
+                    }
+                    if let Some(x) = def.integer(path, "speed")? { i_type.speed = x; }
+                    if let Some(x) = def.integer(path, "hp")? { i_type.hp = x; }
+                    if let Some(x) = def.integer(path, "attack")? { i_type.attack = x; }
+                    if let Some(x) = def.integer(path, "defense")? { i_type.defense = x; }
+                    if let Some(x) = def.integer(path, "damage")? { i_type.damage = x; }
+                    items.push(Rc::new(i_type));
+                },
+                "actor" => {
+                    if self.get_actor_model(&name).is_some() || actors.iter().any(|a| a.is_named(&name)) {
+                        return Err(def.error(path, def.line_no, &("duplicate actor ".to_string()+&name)));
+                    }
+                    let mut a_type = ActorModel::new(&name, def.tile(path)?);
+                    if let Some(ap) = def.positive(path, "base_AP")? { a_type.base_AP = ap; }
+                    if let Some(hp) = def.positive(path, "base_HP")? { a_type.base_HP = hp; }
+                    if let Some(x) = def.positive(path, "attack")? { a_type.attack = x; }
+                    if let Some(x) = def.positive(path, "defense")? { a_type.defense = x; }
+                    if let Some(x) = def.positive(path, "damage")? { a_type.damage = x; }
+                    if let Some(x) = def.positive(path, "capacity")? { a_type.capacity = usize::try_from(x).unwrap(); }
+                    if let Some((val, line_no)) = def.fields.get("ai") {
+                        match Behavior::from_name(val) {
+                            Some(ai) => a_type.ai = ai,
+                            None => return Err(def.error(path, *line_no, &("ai should be idle, wander, or patrol: ".to_string()+val)))
+                        }
+                    }
+                    actors.push(a_type);
+                },
+                _ => {
+                    if self.get_map_object_model(&name).is_some() || objs.iter().any(|o| o.name(path).unwrap() == name) {
+                        return Err(def.error(path, def.line_no, &("duplicate object ".to_string()+&name)));
+                    }
+                    // validate eagerly so errors are reported in file order
+                    let _ = def.tile(path)?;
+                    def.bool(path, "walkable")?;
+                    def.bool(path, "transparent")?;
+                    def.depth(path, "spring")?;
+                    def.flag(path, "transmits")?;
+                    objs.push(def);
+                }
+            }
+        }
+
+        // references must resolve, either to this file or to already-registered objects and items
+        let known = |name:&str| -> bool {
+            return self.get_map_object_model(name).is_some() || objs.iter().any(|o| o.name(path).unwrap() == name);
+        };
+        let item = |name:&str| -> Option<r_ItemModel> {
+            return self.get_item_model(name).or_else(|| items.iter().find(|i| i.is_named(name)).cloned());
+        };
+        let mut transitions = Vec::<(String,Verb,String,Vec<Precondition>,i16)>::new();
+        for def in &objs {
+            if let Some((target, line_no)) = def.reference("powered") {
+                if !known(&target) { return Err(def.error(path, line_no, &("powered: unknown object ".to_string()+&target))); }
+            }
+            for verb in VERBS.iter() {
+                let needs_key = verb.name().to_string()+" needs";
+                let cost_key = verb.name().to_string()+" cost";
+                let needs = def.needs(path, &needs_key, &item)?;
+                let cost = def.positive(path, &cost_key)?;
+                match def.reference(verb.name()) {
+                    Some((target, line_no)) => {
+                        if !known(&target) { return Err(def.error(path, line_no, &format!("{}: unknown object {}", verb.name(), target))); }
+                        transitions.push((def.name(path)?, *verb, target, needs, cost.unwrap_or(BASE_ACTION_COST)));
+                    },
+                    None => {
+                        for key in [&needs_key, &cost_key].iter() {
+                            if let Some((_, line_no)) = def.reference(key) {
+                                return Err(def.error(path, line_no, &format!("{} without {}", key, verb.name())));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // everything checks out; register
+        for t in terrain { self.new_terrain(t); }
+        for i_type in items { self.item_types.push(i_type); }
+        for a_type in actors { self.new_actor_model(a_type); }
+        for def in &objs {
+            let mut m_type = MapObjectModel::new(&def.name(path)?, def.tile(path)?, def.bool(path, "walkable")?, def.bool(path, "transparent")?);
+            m_type.spring = def.depth(path, "spring")?;
+            m_type.transmits = def.flag(path, "transmits")?;
+            self.new_map_object_model(m_type);
+        }
+        for (from, verb, to, needs, cost) in transitions {
+            let from = self.get_map_object_model(&from).unwrap();
+            let to = self.get_map_object_model(&to).unwrap();
+            self.new_transition(Transition{from, verb, to, needs, cost});
+        }
+        for def in &objs {
+            if let Some((target, _)) = def.reference("powered") {
+                let from = self.get_map_object_model(&def.name(path)?).unwrap();
+                let to = self.get_map_object_model(&target).unwrap();
+                self.obj_powered.push([from, to]);
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOOR:&str = "[object]\nname = door\nglyph = +\nwalkable = false\ntransparent = false\n";
+
+    fn error(src:&str) -> String {
+        let mut w = World::new();
+        return w.parse_content("test", src).unwrap_err().desc;
+    }
+
+    #[test]
+    fn the_shipped_content_loads() {
+        let mut w = World::new();
+        w.load_content("data/content.txt").unwrap();
+        let door = w.get_map_object_model("door (closed)").unwrap();
+        let t = w.get_transition(&door, Verb::Lock).unwrap();
+        assert!(t.to.is_named("door (locked)"));
+        assert!(matches!(t.needs.as_slice(), [Precondition::Key(key)] if key.is_named("iron key")));
+        assert!(w.get_terrain("stone culvert").unwrap().flow == Some(Compass::N));
+        assert!(w.get_map_object_model("millstone").and_then(|x| w.powered_model(&x)).is_some_and(|x| x.is_named("millstone (grinding)")));
+        assert!(w.load_content("data/content.txt").unwrap_err().desc.contains("duplicate terrain"));
+        assert!(w.load_content("data/nowhere.txt").unwrap_err().desc.starts_with("data/nowhere.txt: "));
+    }
+
+    #[test]
+    fn errors_say_where() {
+        assert_eq!("test:2: unknown section [room]", error("# rooms\n[room]\n"));
+        assert_eq!("test:1: key = value outside of a section", error("name = door\n"));
+        assert_eq!("test:1: [object] missing glyph", error("[object]\nname = door\n"));
+        assert_eq!("test:3: [object] duplicate key name", error("[object]\nname = door\nname = gate\n"));
+        assert_eq!("test:4: [object] walkable should be true or false: maybe", error("[object]\nname = door\nglyph = +\nwalkable = maybe\n"));
+        assert_eq!("test:3: [object] glyph should be a single character: ++", error("[object]\nname = door\nglyph = ++\n"));
+        assert_eq!("test:6: [terrain] flow should be a direction, n ... nw: up",
+            error("[terrain]\nname = ramp\nglyph = /\nwalkable = true\ntransparent = true\nflow = up\n"));
+        assert_eq!("test:6: [terrain] sunken should be a water depth, 0 to 9: 10",
+            error("[terrain]\nname = pit\nglyph = _\nwalkable = true\ntransparent = true\nsunken = 10\n"));
+        assert_eq!("test:6: [object] duplicate object door", error(&(DOOR.to_string()+DOOR)));
+        assert_eq!("test:4: [actor] unknown key base_ap", error("[actor]\nname = goblin\nglyph = g\nbase_ap = 50\n"));
+        assert_eq!("test:6: [object] unknown key shut", error(&(DOOR.to_string()+"shut = door\n")));
+        assert_eq!("test:6: [object] unknown key open costs", error(&(DOOR.to_string()+"open costs = 50\n")));
+        assert_eq!("test:3: [item] unknown key walkable", error("[item]\nname = rock\nwalkable = true\n"));
+    }
+
+    #[test]
+    fn objects_must_refer_to_known_things() {
+        assert_eq!("test:6: [object] open: unknown object door (open)", error(&(DOOR.to_string()+"open = door (open)\n")));
+        assert_eq!("test:6: [object] lock needs without lock", error(&(DOOR.to_string()+"lock needs = free cell\n")));
+        assert_eq!("test:7: [object] lock needs: unknown item iron key", error(&(DOOR.to_string()+"lock = door\nlock needs = key iron key\n")));
+        assert_eq!("test:7: [object] break needs should be key item, free cell, or strength n: a hammer",
+            error(&(DOOR.to_string()+"break = door\nbreak needs = a hammer\n")));
+        // later in the file is fine
+        let mut w = World::new();
+        w.parse_content("test", &(DOOR.to_string()+"lock = door\nlock needs = key iron key\n[item]\nname = iron key\nglyph = ,\n")).unwrap();
+        let door = w.get_map_object_model("door").unwrap();
+        assert!(w.get_transition(&door, Verb::Lock).is_some());
+    }
+
+    #[test]
+    fn nothing_is_registered_from_a_bad_file() {
+        let mut w = World::new();
+        let src = "[terrain]\nname = floor\nglyph = .\nwalkable = true\ntransparent = true\n[item]\nname = rock\nglyph = *\n".to_string()+DOOR+"open = gate\n";
+        assert!(w.parse_content("test", &src).is_err());
+        assert!(w.get_terrain("floor").is_none() && w.get_item_model("rock").is_none() && w.get_map_object_model("door").is_none());
+    }
+}
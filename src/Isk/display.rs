@@ -0,0 +1,207 @@
+use crate::isk::*;
+#[cfg(test)]
+use std::any::Any;
+use std::collections::VecDeque;
+use tcod::console::{Root, Offscreen, Console, FontLayout, FontType, BackgroundFlag, blit};
+
+// drawing primitives; DisplayManager handles clipping, dimming, and text on top of these
+// coordinates are always in bounds by the time they get here
+pub trait Backend {
+    fn put_char(&mut self, scr_loc:&[i32;2], img:char, fg:colors::Color);
+    fn set_bg(&mut self, scr_loc:&[i32;2], bg:colors::Color);
+    fn clear(&mut self);
+    fn present(&mut self);
+    fn wait_for_keypress(&mut self) -> Key;
+    fn window_closed(&self) -> bool;
+    fn is_fullscreen(&self) -> bool;
+    fn set_fullscreen(&mut self, fullscreen:bool);
+    // so tests can get at the concrete backend
+    #[cfg(test)]
+    fn as_any(&self) -> &dyn Any;
+    #[cfg(test)]
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+pub struct TcodBackend {
+    root: Root,
+    offscr: Offscreen
+}
+
+impl TcodBackend {
+    pub fn new(name: &str, ft : &str) -> TcodBackend {
+        let root = Root::initializer().size(SCREEN_WIDTH, SCREEN_HEIGHT).title(name).font(ft,FontLayout::Tcod).font_type(FontType::Greyscale).init();
+        let offscr = Offscreen::new(SCREEN_WIDTH, SCREEN_HEIGHT);    // going to double-buffer at some point
+        return TcodBackend{root, offscr};
+    }
+}
+
+impl Backend for TcodBackend {
+    fn put_char(&mut self, scr_loc:&[i32;2], img:char, fg:colors::Color) {
+        self.offscr.set_default_foreground(fg);
+        self.offscr.put_char(scr_loc[0], scr_loc[1], img, BackgroundFlag::None);
+    }
+    fn set_bg(&mut self, scr_loc:&[i32;2], bg:colors::Color) {
+        self.offscr.set_char_background(scr_loc[0], scr_loc[1], bg, BackgroundFlag::Set);
+    }
+    fn clear(&mut self) {
+        self.offscr.set_default_foreground(colors::WHITE);
+        self.offscr.clear();
+    }
+    fn present(&mut self) {
+        blit(&self.offscr, (0, 0), (SCREEN_WIDTH, SCREEN_HEIGHT), &mut self.root, (0, 0), 1.0, 1.0);
+        self.root.flush();
+    }
+    fn wait_for_keypress(&mut self) -> Key {
+        return self.root.wait_for_keypress(true);    // could check_for_keypress instead but then would have to pause/multi-process explicitly
+    }
+    fn window_closed(&self) -> bool { return self.root.window_closed(); }
+    fn is_fullscreen(&self) -> bool { return self.root.is_fullscreen(); }
+    fn set_fullscreen(&mut self, fullscreen:bool) { self.root.set_fullscreen(fullscreen); }
+    #[cfg(test)]
+    fn as_any(&self) -> &dyn Any { return self; }
+    #[cfg(test)]
+    fn as_any_mut(&mut self) -> &mut dyn Any { return self; }
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Cell {
+    pub img: char,
+    pub fg: colors::Color,
+    pub bg: colors::Color
+}
+
+impl Cell {
+    pub fn blank() -> Cell { return Cell{img:' ', fg:colors::WHITE, bg:colors::BLACK}; }
+}
+
+// grid of cells; keys are scripted.  The "window" closes when the scripted keys run out.
+pub struct HeadlessBackend {
+    cells: Vec<Cell>,
+    keys: VecDeque<Key>,
+    fullscreen: bool,
+    frames: usize
+}
+
+impl HeadlessBackend {
+    pub fn new() -> HeadlessBackend {
+        let n = usize::try_from(SCREEN_WIDTH*SCREEN_HEIGHT).unwrap();
+        return HeadlessBackend{cells:vec![Cell::blank(); n], keys:VecDeque::new(), fullscreen:false, frames:0};
+    }
+
+    fn index(scr_loc:&[i32;2]) -> usize { return usize::try_from(scr_loc[0]+scr_loc[1]*SCREEN_WIDTH).unwrap(); }
+}
+
+// for tests to script keys and read the screen back
+#[cfg(test)]
+impl HeadlessBackend {
+    pub fn push_key(&mut self, k:Key) { self.keys.push_back(k); }
+    pub fn frames(&self) -> usize { return self.frames; }
+
+    pub fn cell(&self, scr_loc:&[i32;2]) -> Option<Cell> {
+        if !DisplayManager::in_bounds(scr_loc) { return None; }
+        return Some(self.cells[HeadlessBackend::index(scr_loc)]);
+    }
+
+    pub fn row_text(&self, y:i32) -> String {
+        let mut ret = String::new();
+        for x in 0..SCREEN_WIDTH {
+            if let Some(c) = self.cell(&[x, y]) { ret.push(c.img); }
+        }
+        return ret;
+    }
+}
+
+impl Backend for HeadlessBackend {
+    fn put_char(&mut self, scr_loc:&[i32;2], img:char, fg:colors::Color) {
+        let cell = &mut self.cells[HeadlessBackend::index(scr_loc)];
+        cell.img = img;
+        cell.fg = fg;
+    }
+    fn set_bg(&mut self, scr_loc:&[i32;2], bg:colors::Color) {
+        self.cells[HeadlessBackend::index(scr_loc)].bg = bg;
+    }
+    fn clear(&mut self) {
+        for cell in self.cells.iter_mut() { *cell = Cell::blank(); }
+    }
+    fn present(&mut self) { self.frames += 1; }
+    fn wait_for_keypress(&mut self) -> Key {
+        match self.keys.pop_front() {
+            Some(k) => return k,
+            None => return Key::default()   // KeyCode::NoKey
+        }
+    }
+    fn window_closed(&self) -> bool { return self.keys.is_empty(); }
+    fn is_fullscreen(&self) -> bool { return self.fullscreen; }
+    fn set_fullscreen(&mut self, fullscreen:bool) { self.fullscreen = fullscreen; }
+    #[cfg(test)]
+    fn as_any(&self) -> &dyn Any { return self; }
+    #[cfg(test)]
+    fn as_any_mut(&mut self) -> &mut dyn Any { return self; }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isk::item::*;
+    use crate::isk::messages::*;
+
+    fn scripted(c:char) -> Key {
+        let mut ret = Key::default();
+        ret.code = tcod::input::KeyCode::Char;
+        ret.printable = c;
+        return ret;
+    }
+
+    #[test]
+    fn headless_keys_run_out() {
+        let mut dm = DisplayManager::new_headless();
+        dm.headless_mut().unwrap().push_key(scripted('a'));
+        dm.headless_mut().unwrap().push_key(scripted('b'));
+        assert!(!dm.window_closed());
+        assert_eq!('a', dm.wait_for_keypress().printable);
+        assert_eq!('b', dm.wait_for_keypress().printable);
+        assert!(dm.window_closed());
+        assert_eq!(tcod::input::KeyCode::NoKey, dm.wait_for_keypress().code);
+    }
+
+    #[test]
+    fn the_world_draws_headless() {
+        let mut w = World::new();
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:Some(colors::GREY)}), true, true));
+        let r_m = w.new_map("yard", [10,10], floor);
+        let model = w.new_actor_model(ActorModel::new("guard", Ok(CharSpec{img:'g', c:Some(colors::YELLOW)})));
+        let pc = w.new_actor(Rc::clone(&model), &Location::new(&r_m, [0,0]), [5,5]).unwrap();
+        pc.borrow_mut().is_pc = true;
+        w.new_actor(model, &Location::new(&r_m, [0,0]), [6,5]).unwrap();
+        let rock = w.new_item_model(ItemModel::new("rock", Ok(CharSpec{img:'*', c:None})));
+        assert!(pc.borrow_mut().inventory_mut().add(Item::new(rock, 1)).is_ok());
+
+        let mut dm = DisplayManager::new_headless();
+        let loc = pc.borrow().loc();
+        let camera = w.loc_to_td_camera(loc.clone());
+        let at = [loc.pos[0]-camera.pos[0], loc.pos[1]-camera.pos[1]];
+        dm.clear();
+        w.draw(&mut dm, loc.clone(), &pc);
+        dm.render();
+        let h = dm.headless().unwrap();
+        assert_eq!(1, h.frames());
+        assert_eq!(Some(Cell{img:'@', fg:colors::WHITE, bg:colors::BLACK}), h.cell(&at));
+        assert_eq!(Some(Cell{img:'g', fg:colors::YELLOW, bg:colors::BLACK}), h.cell(&[at[0]+1, at[1]]));
+        assert_eq!(Some(Cell{img:'.', fg:colors::GREY, bg:colors::BLACK}), h.cell(&[at[0]-1, at[1]]));
+        assert_eq!(None, h.cell(&[SCREEN_WIDTH, 0]));
+        let sidebar = |y:i32| h.row_text(y)[usize::try_from(VIEW+1).unwrap()..].trim_end().to_string();
+        assert_eq!("Inventory", sidebar(0));
+        assert_eq!("a) rock", sidebar(1));
+        assert_eq!("yard", sidebar(VIEW-2));
+        assert_eq!("ground floor", sidebar(VIEW-1));
+        assert!(h.row_text(VIEW+1).starts_with('#'));   // no messages yet
+
+        get_messages_cache_mut().get_mut(Rc::clone(&pc)).add_message("The yard is quiet.");
+        dm.clear();
+        w.draw(&mut dm, loc, &pc);
+        dm.render();
+        let h = dm.headless().unwrap();
+        assert_eq!(2, h.frames());
+        assert!(h.row_text(VIEW+1).starts_with("The yard is quiet."));
+    }
+}
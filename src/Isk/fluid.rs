@@ -0,0 +1,178 @@
+use crate::isk::*;
+use crate::isk::gps::*;
+use std::cmp::min;
+use std::collections::HashMap;
+
+// water is a per-map layer of depths, updated once per round:
+// * springs (map objects) add their depth to their cell
+// * a current (terrain flow) carries all the water it can to the next cell, which is what turns a water wheel
+// * otherwise water spills to cardinal neighbors whose surface (depth less sunken) is at least two lower, lowest first
+// * drains remove their depth, unless an object or items cover them
+// Flow is computed from the depths at the start of the round, so it does not depend on iteration order.  Water that
+// would overfill a cell stays where it came from; cells earlier in row-major order get theirs in first.
+pub const MAX_WATER_DEPTH: u8 = 9;
+pub const DEEP_WATER: u8 = 4;
+
+pub fn water_tile(depth:u8) -> TileSpec {
+    if DEEP_WATER <= depth { return Ok(CharSpec{img:'~', c:Some(colors::BLUE)}); }
+    return Ok(CharSpec{img:'~', c:Some(colors::AZURE)});
+}
+
+impl Map {
+    // water can stand (and items lie) here
+    pub fn floodable(&self, pt:[i32;2]) -> bool {
+        if !self.in_bounds(pt) || !self.get_terrain(pt).walkable { return false; }
+        return self.get_map_object(pt).is_none_or(|obj| obj.borrow().model.walkable);
+    }
+
+    fn surface(&self, pt:[i32;2], depth:u8) -> i32 { return i32::from(depth) - i32::from(self.get_terrain(pt).sunken); }
+
+    pub fn water_turn(&mut self) {
+        for obj in self.map_objects() {
+            let o = obj.borrow();
+            if 0 < o.model.spring {
+                let pos = o.loc().pos;
+                let depth = min(MAX_WATER_DEPTH, self.get_water(pos).saturating_add(o.model.spring));
+                self.set_water(pos, depth);
+            }
+        }
+
+        let before = self.water_cells();
+        let mut moves = Vec::<([i32;2],[i32;2],i32,bool)>::new();    // from, to, depth, by a current
+        for (pt, depth) in &before {
+            let mut left = *depth;
+            if let Some(dir) = self.get_terrain(*pt).flow.clone() {
+                let mut dest = *pt;
+                dest += dir;
+                if self.floodable(dest) {
+                    let n = min(left, MAX_WATER_DEPTH - self.get_water(dest));
+                    if 0 < n {
+                        moves.push((*pt, dest, i32::from(n), true));
+                        left -= n;
+                    }
+                }
+            }
+            let mut lower = Vec::<([i32;2],i32)>::new();
+            for dir in CARDINALS.iter() {
+                let mut dest = *pt;
+                dest += dir.clone();
+                if self.floodable(dest) { lower.push((dest, self.surface(dest, self.get_water(dest)))); }
+            }
+            lower.sort_by_key(|x| x.1);  // stable, so ties go in compass order
+            for (dest, level) in lower {
+                if 0 == left || self.surface(*pt, left) <= level+1 { break; }
+                moves.push((*pt, dest, 1, false));
+                left -= 1;
+            }
+        }
+        // a cell has room for what it started without, plus what leaves it; cutting a move back frees less room
+        // upstream, so repeat until nothing changes
+        let start = |pt:[i32;2]| i32::from(self.get_water(pt));
+        loop {
+            let mut out = HashMap::<[i32;2],i32>::new();
+            for x in &moves { *out.entry(x.0).or_insert(0) += x.2; }
+            let mut taken = HashMap::<[i32;2],i32>::new();
+            let mut changed = false;
+            for x in moves.iter_mut() {
+                let used = taken.entry(x.1).or_insert(0);
+                let n = min(x.2, (i32::from(MAX_WATER_DEPTH) - start(x.1) + out.get(&x.1).copied().unwrap_or(0) - *used).max(0));
+                if n != x.2 {
+                    x.2 = n;
+                    changed = true;
+                }
+                *used += n;
+            }
+            if !changed { break; }
+        }
+        let mut delta = HashMap::<[i32;2],i32>::new();
+        for x in &moves {
+            *delta.entry(x.0).or_insert(0) -= x.2;
+            *delta.entry(x.1).or_insert(0) += x.2;
+        }
+        for (pt, n) in delta {
+            let depth = i32::from(self.get_water(pt)) + n;
+            debug_assert!(0 <= depth && i32::from(MAX_WATER_DEPTH) >= depth);
+            self.set_water(pt, u8::try_from(depth).unwrap());
+        }
+        self.set_flowing(moves.iter().filter(|x| x.3 && 0 < x.2).map(|x| x.0).collect());
+
+        for (pt, depth) in self.water_cells() {
+            let drain = self.get_terrain(pt).drain;
+            if 0 == drain || self.get_map_object(pt).is_some() || self.get_items(pt).is_some() { continue; }
+            self.set_water(pt, depth.saturating_sub(drain));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terrain(w:&mut World, name:&str, flow:Option<Compass>, sunken:u8, drain:u8) -> r_Terrain {
+        let mut t = Terrain::new(name, Ok(CharSpec{img:'.', c:None}), true, true);
+        t.flow = flow;
+        t.sunken = sunken;
+        t.drain = drain;
+        return w.new_terrain(t);
+    }
+
+    fn total(m:&Map) -> u32 { return m.water_cells().iter().map(|x| u32::from(x.1)).sum(); }
+
+    #[test]
+    fn water_spills_downhill_and_currents_carry_it() {
+        let mut w = World::new();
+        let floor = terrain(&mut w, "floor", None, 0, 0);
+        let east = terrain(&mut w, "culvert", Some(Compass::E), 2, 0);
+        let pit = terrain(&mut w, "pit", None, 3, 0);
+        let r_m = w.new_map("test", [5,1], floor);
+        let mut m = r_m.borrow_mut();
+        m.set_terrain([1,0], east);
+        m.set_terrain([4,0], pit);
+        m.set_water([1,0], 4);
+        m.set_water([3,0], 3);
+        m.water_turn();
+        // the current carries it all on; the floor spills one each way, lowest first
+        assert_eq!([0, 0, 5, 1, 1], [0,1,2,3,4].map(|x| m.get_water([x,0])));
+        assert_eq!(&[[1,0]], m.flowing());
+        for _ in 0..20 { m.water_turn(); }
+        assert_eq!(7, total(&m));
+    }
+
+    #[test]
+    fn water_that_would_overfill_a_cell_stays_behind() {
+        let mut w = World::new();
+        let floor = terrain(&mut w, "floor", None, 0, 0);
+        let east = terrain(&mut w, "east", Some(Compass::E), 0, 0);
+        let west = terrain(&mut w, "west", Some(Compass::W), 0, 0);
+        let r_m = w.new_map("test", [3,1], floor);
+        let mut m = r_m.borrow_mut();
+        m.set_terrain([0,0], east);
+        m.set_terrain([2,0], west);
+        m.set_water([0,0], MAX_WATER_DEPTH);
+        m.set_water([1,0], 5);
+        m.set_water([2,0], MAX_WATER_DEPTH);
+        m.water_turn();
+        // the middle only had room for 4, which the current from the west got in first
+        assert_eq!([5, MAX_WATER_DEPTH, MAX_WATER_DEPTH], [0,1,2].map(|x| m.get_water([x,0])));
+        assert_eq!(&[[0,0]], m.flowing());
+        assert_eq!(23, total(&m));
+    }
+
+    #[test]
+    fn drains_are_blocked_by_what_covers_them() {
+        let mut w = World::new();
+        let floor = terrain(&mut w, "floor", None, 0, 0);
+        let drain = terrain(&mut w, "drain", None, 0, 2);
+        let r_m = w.new_map("test", [3,1], floor);
+        let mut m = r_m.borrow_mut();
+        m.set_terrain([0,0], Rc::clone(&drain));
+        m.set_terrain([2,0], drain);
+        m.set_water([0,0], 3);
+        m.set_water([2,0], 3);
+        let rock = w.new_item_model(ItemModel::new("rock", Ok(CharSpec{img:'*', c:None})));
+        m.add_item([2,0], Item::new(rock, 1));
+        m.water_turn();
+        // each spills one into the middle, then only the uncovered drain takes its 2
+        assert_eq!([0, 2, 2], [0,1,2].map(|x| m.get_water([x,0])));
+    }
+}
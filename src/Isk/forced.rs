@@ -0,0 +1,139 @@
+use crate::isk::*;
+use crate::isk::gps::*;
+use crate::isk::messages::*;
+
+// terrain that moves what is on it, rather than the other way around:
+// * a conveyor carries actors and item piles one step its way each round, after water (so a water wheel only carries
+//   while a current turns it).  Whatever is in the way stays put.
+// * unsupported footing drops arrivals through a one-way exit if there is one; otherwise they are hurt
+// Movement cost (Terrain::move_energy) is charged by whoever moves voluntarily.
+pub const FALL_DAMAGE:i16 = 2;
+
+impl World {
+    // which way, if any, the terrain at pt carries things this round
+    fn conveyed(m:&Map, pt:[i32;2]) -> Option<Compass> {
+        let t = m.get_terrain(pt);
+        if t.power && !m.is_flowing(pt) { return None; }
+        return t.conveyor.clone();
+    }
+
+    pub fn conveyor_turn(&mut self) {
+        for r_m in self.atlas.clone() {  // actors may be carried off this map
+            // snapshot first, so nothing is carried twice; row-major, so a loaded game moves things in the same order
+            let (actors, piles) = {
+                let m = r_m.borrow();
+                let mut cells: Vec<([i32;2],Compass)> = m.conveyors().iter().filter_map(|pt| World::conveyed(&m, *pt).map(|dir| (*pt, dir))).collect();
+                cells.sort_by_key(|x| [x.0[1], x.0[0]]);
+                let actors: Vec<(r_Actor,Compass)> = cells.iter().filter_map(|x| m.get_actor(x.0).map(|a| (a, x.1.clone())))
+                    .filter(|x| !x.0.borrow().is_dead()).collect();
+                let piles: Vec<([i32;2],Compass)> = cells.into_iter().filter(|x| m.get_items(x.0).is_some()).collect();
+                (actors, piles)
+            };
+            for (r_act, dir) in actors {
+                let src = r_act.borrow().loc();
+                let dest = match self.canonical_loc(src.clone()+dir) {
+                    Some(x) => x,
+                    None => continue
+                };
+                if !dest.is_walkable_for(&r_act.borrow()) { continue; }
+                if r_act.borrow().is_pc {
+                    get_messages_cache_mut().get_mut(Rc::clone(&r_act)).add_message(&("The ".to_string()+&src.get_terrain().name+" carries you along."));
+                }
+                self.move_actor(&r_act, dest);
+            }
+            for (pos, dir) in piles {
+                let src = Location::new(&r_m, pos);
+                let mut dest = match self.canonical_loc(src.clone()+dir) {
+                    Some(x) => x,
+                    None => continue
+                };
+                if !dest.map.borrow().floodable(dest.pos) { continue; }
+                if let Some((next, _)) = self.get_exit_one_way(&dest) { dest = next; }  // \todo items don't take falling damage
+                let mut pile = Vec::<Item>::new();
+                while let Some(item) = src.take_item() { pile.push(item); }
+                while let Some(item) = pile.pop() { dest.add_item(item); }  // keep the stacking order
+            }
+        }
+    }
+
+    // after any involuntary exits have been taken: unsupported footing with nowhere to fall to hurts, and falls can kill
+    pub fn land(&self, r_act:&r_Actor) {
+        let loc = r_act.borrow().loc();
+        let t = loc.get_terrain();
+        if t.unsupported && self.get_exit_one_way(&loc).is_none() {
+            r_act.borrow_mut().hp_mut().takeHit(FALL_DAMAGE);
+            self.announce(std::slice::from_ref(&loc), &|v| {
+                let verb = if Rc::ptr_eq(v, r_act) { " fall on the " } else { " falls on the " };
+                return describe(v, r_act)+verb+&t.name+".";
+            });
+        }
+        if r_act.borrow().is_dead() { self.kill(r_act); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isk::item::*;
+
+    fn belt(w:&mut World, power:bool) -> (r_Map, r_ActorModel) {
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:None}), true, true));
+        let mut t = Terrain::new("belt", Ok(CharSpec{img:'>', c:None}), true, true);
+        t.conveyor = Some(Compass::E);
+        t.power = power;
+        let belt = w.new_terrain(t);
+        let r_m = w.new_map("test", [5,2], floor);
+        for pt in [[1,0], [2,0], [1,1]].iter() { r_m.borrow_mut().set_terrain(*pt, Rc::clone(&belt)); }
+        let model = w.new_actor_model(ActorModel::new("m", Ok(CharSpec{img:'m', c:None})));
+        return (r_m, model);
+    }
+
+    #[test]
+    fn conveyors_carry_one_step_a_round_unless_blocked() {
+        let mut w = World::new();
+        let (r_m, model) = belt(&mut w, false);
+        let camera = Location::new(&r_m, [0,0]);
+        let a = w.new_actor(Rc::clone(&model), &camera, [1,0]).unwrap();
+        let b = w.new_actor(model, &camera, [2,0]).unwrap();
+        let rock = w.new_item_model(ItemModel::new("rock", Ok(CharSpec{img:'*', c:None})));
+        r_m.borrow_mut().add_item([1,1], Item::new(rock, 2));
+
+        w.conveyor_turn();
+        // a goes first, and b is still in the way
+        assert_eq!([[1,0], [3,0]], [a.borrow().loc().pos, b.borrow().loc().pos]);
+        assert!(r_m.borrow().get_items([1,1]).is_none());
+        assert_eq!(2, r_m.borrow().get_items([2,1]).unwrap().stacks()[0].qty);
+        w.conveyor_turn();
+        assert_eq!([[2,0], [3,0]], [a.borrow().loc().pos, b.borrow().loc().pos]);
+        assert!(r_m.borrow().get_items([2,1]).is_some());
+    }
+
+    #[test]
+    fn powered_conveyors_only_carry_while_turning() {
+        let mut w = World::new();
+        let (r_m, model) = belt(&mut w, true);
+        let a = w.new_actor(model, &Location::new(&r_m, [0,0]), [1,0]).unwrap();
+        w.conveyor_turn();
+        assert_eq!([1,0], a.borrow().loc().pos);
+        r_m.borrow_mut().set_flowing(vec![[1,0]]);
+        w.conveyor_turn();
+        assert_eq!([2,0], a.borrow().loc().pos);
+    }
+
+    #[test]
+    fn unsupported_footing_hurts_when_there_is_nowhere_to_fall() {
+        let mut w = World::new();
+        let (r_m, model) = belt(&mut w, false);
+        let mut t = Terrain::new("pit", Ok(CharSpec{img:'_', c:None}), true, true);
+        t.unsupported = true;
+        r_m.borrow_mut().set_terrain([4,1], w.new_terrain(t));
+        let a = w.new_actor(model, &Location::new(&r_m, [0,0]), [3,1]).unwrap();
+        let hp = a.borrow().hp.curHp;
+        w.move_actor(&a, Location::new(&r_m, [4,1]));
+        assert_eq!(hp-FALL_DAMAGE, a.borrow().hp.curHp);
+        a.borrow_mut().hp_mut().takeHit(hp-FALL_DAMAGE-1);
+        w.move_actor(&a, Location::new(&r_m, [4,1]));
+        assert!(a.borrow().is_dead());
+        assert!(r_m.borrow().get_actor([4,1]).is_none());
+    }
+}
@@ -0,0 +1,235 @@
+use crate::isk::*;
+use crate::isk::gps::*;
+use std::collections::{HashMap,HashSet};
+
+// transforms from octant-local (dx, dy) to map coordinates
+const OCTANTS: [[i32;4];8] = [[1,0,0,1], [0,1,1,0], [0,-1,1,0], [-1,0,0,1], [-1,0,0,-1], [0,-1,-1,0], [0,1,-1,0], [1,0,0,-1]];
+
+// recursive shadowcasting, Cf. Bjorn Bergstrom (RogueBasin)
+// radius is a square (Chebyshev) radius, to match the view.  Vision-blocking cells are themselves visible.
+pub fn shadowcast(origin:[i32;2], radius:i32, blocks:&dyn Fn([i32;2]) -> bool, mark:&mut dyn FnMut([i32;2])) {
+    mark(origin);
+    for oct in OCTANTS.iter() {
+        Octant{origin, radius, oct, blocks, mark: &mut *mark}.cast_light(1, 1.0, 0.0);
+    }
+}
+
+// what stays fixed while one octant is scanned
+struct Octant<'a> {
+    origin: [i32;2],
+    radius: i32,
+    oct: &'a [i32;4],
+    blocks: &'a dyn Fn([i32;2]) -> bool,
+    mark: &'a mut dyn FnMut([i32;2])
+}
+
+impl Octant<'_> {
+    fn cast_light(&mut self, row:i32, mut start:f64, end:f64) {
+        if start < end { return; }
+        let (origin, oct) = (self.origin, self.oct);
+        let mut new_start = 0.0;
+        for j in row..=self.radius {
+            let dy = -j;
+            let mut blocked = false;
+            for dx in -j..=0 {
+                let l_slope = (f64::from(dx) - 0.5)/(f64::from(dy) + 0.5);
+                let r_slope = (f64::from(dx) + 0.5)/(f64::from(dy) - 0.5);
+                if start < r_slope { continue; }
+                if end > l_slope { break; }
+                let pt = [origin[0] + dx*oct[0] + dy*oct[1], origin[1] + dx*oct[2] + dy*oct[3]];
+                (self.mark)(pt);
+                if blocked {
+                    if (self.blocks)(pt) {
+                        new_start = r_slope;
+                        continue;
+                    }
+                    blocked = false;
+                    start = new_start;
+                } else if (self.blocks)(pt) && j < self.radius {
+                    blocked = true;
+                    self.cast_light(j+1, start, l_slope);
+                    new_start = r_slope;
+                }
+            }
+            if blocked { break; }
+        }
+    }
+}
+
+// field of view of one actor, in the coordinates of the map it is standing on
+pub struct Fov {
+    map: w_Map, // weak: actors are owned by maps
+    pos: [i32;2],
+    revisions: Vec<(w_Map,u32)>,    // every map the view could have reached
+    visible: HashSet<[i32;2]>
+}
+
+impl Fov {
+    pub fn origin(&self) -> Option<Location> {
+        match self.map.upgrade() {
+            Some(m) => return Some(Location::new(&m, self.pos)),
+            None => return None
+        }
+    }
+
+    // stale once any map it was computed from has changed
+    pub fn is_current(&self) -> bool {
+        for x in &self.revisions {
+            match x.0.upgrade() {
+                Some(m) => { if m.borrow().revision() != x.1 { return false; } },
+                None => return false
+            }
+        }
+        return true;
+    }
+
+    // pt is in the coordinates of origin's map
+    pub fn contains(&self, pt:&[i32;2]) -> bool { return self.visible.contains(pt); }
+}
+
+// what an actor last saw of a location
+#[derive(Clone)]
+pub struct Remembered {
+    pub bg: BackgroundSpec,
+    pub tiles: Vec<TileSpec>
+}
+
+// per-actor fog of war; locations are in the coordinates of their own map
+pub struct MapMemory {
+    maps: Vec<(w_Map, HashMap<[i32;2],Remembered>)>
+}
+
+impl MapMemory {
+    pub fn new() -> MapMemory { return MapMemory{maps:Vec::new()}; }
+
+    fn index(&self, m:&r_Map) -> Option<usize> {
+        return self.maps.iter().position(|x| x.0.upgrade().is_some_and(|y| Rc::ptr_eq(&y, m)));
+    }
+
+    pub fn get(&self, loc:&Location) -> Option<&Remembered> {
+        match self.index(&loc.map) {
+            Some(n) => return self.maps[n].1.get(&loc.pos),
+            None => return None
+        }
+    }
+
+    pub fn set(&mut self, loc:&Location, src:Remembered) {
+        let n = match self.index(&loc.map) {
+            Some(n) => n,
+            None => {
+                self.maps.push((Rc::downgrade(&loc.map), HashMap::new()));
+                self.maps.len()-1
+            }
+        };
+        self.maps[n].1.insert(loc.pos, src);
+    }
+
+    // row-major order within each map, so savefiles are stable
+    pub fn contents(&self) -> Vec<(Location, &Remembered)> {
+        let mut ret = Vec::new();
+        for x in &self.maps {
+            if let Some(m) = x.0.upgrade() {
+                let mut staging: Vec<(&[i32;2], &Remembered)> = x.1.iter().collect();
+                staging.sort_by_key(|y| [y.0[1], y.0[0]]);
+                for y in staging { ret.push((Location::new(&m, *y.0), y.1)); }
+            }
+        }
+        return ret;
+    }
+}
+
+impl World {
+    fn calc_fov(&self, origin:&Location) -> Fov {
+        let mut revisions = vec![(Rc::downgrade(&origin.map), origin.map.borrow().revision())];
+        for m in self.stitched_maps(&origin.map) {
+            revisions.push((Rc::downgrade(&m), m.borrow().revision()));
+        }
+        let mut visible = HashSet::<[i32;2]>::new();
+        let blocks = |pt:[i32;2]| -> bool {
+            match self.canonical_loc(Location::new(&origin.map, pt)) {
+                Some(loc) => return !loc.map.borrow().is_transparent(loc.pos),
+                None => return true
+            }
+        };
+        let mut mark = |pt:[i32;2]| {
+            if self.canonical_loc(Location::new(&origin.map, pt)).is_some() { visible.insert(pt); }
+        };
+        shadowcast(origin.pos, VIEW_RADIUS, &blocks, &mut mark);
+        return Fov{map:Rc::downgrade(&origin.map), pos:origin.pos, revisions, visible};
+    }
+
+    // cached on the actor; recomputed when the actor has moved or anything in view may have changed
+    pub fn fov(&self, r_act:&r_Actor) -> Rc<Fov> {
+        let origin = r_act.borrow().loc();
+        if let Some(fov) = r_act.borrow().fov_cache() {
+            if Some(origin.clone()) == fov.origin() && fov.is_current() { return fov; }
+        }
+        let ret = Rc::new(self.calc_fov(&origin));
+        let mut act = r_act.borrow_mut();
+        act.set_fov_cache(Rc::clone(&ret));
+        // everything in view is remembered as it is now
+        for pt in &ret.visible {
+            if let Some(loc) = self.canonical_loc(Location::new(&origin.map, *pt)) {
+                let m = loc.map.borrow();
+                act.memory_mut().set(&loc, Remembered{bg:m.bg_i32(loc.pos), tiles:m.scenery(loc.pos)});
+            }
+        }
+        return Rc::clone(&ret);
+    }
+
+    pub fn can_see(&self, r_act:&r_Actor, loc:&Location) -> bool {
+        let fov = self.fov(r_act);
+        if let Some(origin) = fov.origin() {
+            if let Some(x) = self.coerce_map(loc.clone(), origin.map) { return fov.contains(&x.pos); }
+        }
+        return false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isk::item::*;
+
+    fn open_room() -> (World, r_Actor) {
+        let mut w = World::new();
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:None}), true, true));
+        let model = w.new_actor_model(ActorModel::new("m", Ok(CharSpec{img:'m', c:None})));
+        let m = w.new_map("m", [10, 10], floor);
+        let pc = w.new_actor(model, &Location::new(&m, [0,0]), [5,5]).unwrap();
+        pc.borrow_mut().is_pc = true;
+        return (w, pc);
+    }
+
+    #[test]
+    fn fov_is_cached_until_the_map_changes() {
+        let (mut w, pc) = open_room();
+        let f1 = w.fov(&pc);
+        assert!(Rc::ptr_eq(&f1, &w.fov(&pc)));
+        let here = pc.borrow().loc();
+        let wall = w.new_terrain(Terrain::new("wall", Ok(CharSpec{img:'#', c:None}), false, false));
+        here.map.borrow_mut().set_terrain([7,5], wall);
+        let f2 = w.fov(&pc);
+        assert!(!Rc::ptr_eq(&f1, &f2));
+        assert!(f2.contains(&[7,5]));
+        assert!(!f2.contains(&[8,5]));
+    }
+
+    #[test]
+    fn memory_follows_items_and_water_in_view() {
+        let (mut w, pc) = open_room();
+        let there = pc.borrow().loc()+[2,0];
+        let _ = w.fov(&pc);
+        assert_eq!(1, pc.borrow().memory().get(&there).unwrap().tiles.len());
+        let coin = w.new_item_model(ItemModel::new("coin", Ok(CharSpec{img:'$', c:None})));
+        there.add_item(Item::new(coin, 1));
+        let _ = w.fov(&pc);
+        assert_eq!(2, pc.borrow().memory().get(&there).unwrap().tiles.len());
+        there.map.borrow_mut().set_water(there.pos, 1);
+        let _ = w.fov(&pc);
+        assert_eq!(3, pc.borrow().memory().get(&there).unwrap().tiles.len());
+        there.take_item();
+        let _ = w.fov(&pc);
+        assert_eq!(2, pc.borrow().memory().get(&there).unwrap().tiles.len());
+    }
+}
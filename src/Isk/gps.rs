@@ -1,4 +1,5 @@
 use crate::isk::*;
+use crate::isk::fluid::water_tile;
 use crate::isk::los::*;
 use crate::isk::numerics::{Norm,Rearrange};
 use rand::Rng;
@@ -98,6 +99,16 @@ pub enum Compass {  // XCOM-like compass directions
     NW
 }
 
+pub const CARDINALS: [Compass; 4] = [Compass::N, Compass::E, Compass::S, Compass::W];
+
+impl Compass {
+    // clockwise, in eighths of a turn
+    pub fn turned(&self, eighths:i32) -> Compass { return Compass::try_from((i32::from(self.clone())+eighths).rem_euclid(8)).unwrap(); }
+    pub fn opposite(&self) -> Compass { return self.turned(4); }
+    // east-west
+    pub fn mirrored(&self) -> Compass { return Compass::try_from((8-i32::from(self.clone())) % 8).unwrap(); }
+}
+
 impl From<Compass> for i32 {
     fn from(src: Compass) -> i32 {
         match src {
@@ -448,6 +459,7 @@ impl Rect {
         return Rect{_origin:Point{pt:o}, _dim:Point{pt:d}};
     }
 
+    // self keeps cut (in [lb, ub)) rows or columns; the rest, on the dir side, is returned
     pub fn split<R: Rng + ?Sized>(&mut self, r:&mut R, dir:Compass, lb:usize, ub:usize) -> Option<Rect> {
         debug_assert!(lb <= ub);
         debug_assert!(1 <= lb);
@@ -456,7 +468,11 @@ impl Rect {
         match dir {
             Compass::N => {
                 debug_assert!(ub < self.height());
-                return None;
+                let rest = self._dim[1]-cut;
+                let ret = Rect::new(*self._origin, [self._dim[0], rest]);
+                self._origin[1] += i32::try_from(rest).unwrap();
+                self._dim[1] = cut;
+                return Some(ret);
             },
             Compass::E => {
                 debug_assert!(ub < self.width());
@@ -472,10 +488,14 @@ impl Rect {
             },
             Compass::W => {
                 debug_assert!(ub < self.width());
-                return None;
+                let rest = self._dim[0]-cut;
+                let ret = Rect::new(*self._origin, [rest, self._dim[1]]);
+                self._origin[0] += i32::try_from(rest).unwrap();
+                self._dim[0] = cut;
+                return Some(ret);
             },
             _ => {
-                debug_assert!(false,"unhandled split direction");
+                debug_assert!(false,"diagonal split direction");
                 return None;
             }
         }
@@ -535,6 +555,8 @@ impl MapRect {
         return MapRect{rect:_rect, _floor:floor, _wall:wall, _wallcode:0};
     }
 
+    pub fn floor(&self) -> &r_Terrain { return &self._floor; }
+
     // 0: none, 1: solid, 2: floor in center (e.g., where a door might go later)
     pub fn set_wallcode(&mut self, n:u8, e:u8, s:u8, w:u8) {
         debug_assert!(3 > n);
@@ -562,11 +584,19 @@ pub struct Map {
     dim : [usize;2],
     name : String,
     actors: Vec<r_Actor>,  // Rogue Survivor Revived needs this for turn ordering
+    occupants: HashMap<[i32;2],r_Actor>,   // position index into actors; kept up to date by Actor::set_loc
     objects: HashMap<[i32;2],r_MapObject>,
-    terrain: Vec<r_Terrain>
+    items: HashMap<[i32;2],Inventory>,  // floor piles; no empty piles
+    water: HashMap<[i32;2],u8>,     // depth; no dry cells
+    flowing: Vec<[i32;2]>,  // cells a current carried water out of, last turn
+    powered: Vec<[i32;2]>,  // sources, transmitters, and machinery with power, last turn
+    terrain: Vec<r_Terrain>,
+    conveyors: Vec<[i32;2]>,    // cells whose terrain is a conveyor, so they can be run without a full scan
+    level: i32,     // floor of a building: 0 ground, negative below
+    revision: u32   // bumped whenever anything drawn changes (terrain, map objects, water, items); invalidates FOV caches, which refreshes memory
 }
 pub type r_Map = Rc<RefCell<Map>>;   // simulates C# class or C++ std::shared_ptr
-//pub type w_Map = Weak<RefCell<Map>>; // simulates C++ std::weak_ptr
+pub type w_Map = std::rc::Weak<RefCell<Map>>; // simulates C++ std::weak_ptr
 
 impl PartialEq for Map {
     fn eq(&self, other: &Map) -> bool {
@@ -582,19 +612,55 @@ impl Map {
 
     pub fn new(_name: &str, _dim: [i32;2], _terrain:r_Terrain) -> Map {
         let staging = Map::usize_cast(_dim);
-        return Map{name:_name.to_string(), dim:staging, actors:Vec::new(), objects:HashMap::new(), terrain:vec![_terrain; staging[0]*staging[0]]};
+        let mut conveyors = Vec::<[i32;2]>::new();
+        if _terrain.conveyor.is_some() {
+            for y in 0.._dim[1] { for x in 0.._dim[0] { conveyors.push([x,y]); } }
+        }
+        return Map{name:_name.to_string(), dim:staging, actors:Vec::new(), occupants:HashMap::new(), objects:HashMap::new(), items:HashMap::new(), water:HashMap::new(), flowing:Vec::new(), powered:Vec::new(), terrain:vec![_terrain; staging[0]*staging[1]], conveyors, level:0, revision:0};
     }
 
     pub fn new_actor(&mut self, _model: r_ActorModel, _loc:Location) -> r_Actor {
         // \todo enforce that the location is ours, at least for debug builds
+        assert!(!self.occupants.contains_key(&_loc.pos), "{} {:?} already occupied", self.name, _loc.pos);
+        let pos = _loc.pos;
         let ret = Rc::new(RefCell::new(Actor::new(_model, _loc)));
         self.actors.push(ret.clone());
+        self.occupants.insert(pos, ret.clone());
         return ret;
     }
 
+    // transfer between maps; caller is responsible for the actor's location (which also moves the position index)
+    pub fn add_actor(&mut self, src:r_Actor) {
+        debug_assert!(!self.actors.iter().any(|act| Rc::ptr_eq(act, &src)));
+        self.actors.push(src);
+    }
+
+    pub fn remove_actor(&mut self, src:&r_Actor) -> bool {
+        if let Some(n) = self.actors.iter().position(|act| Rc::ptr_eq(act, src)) {
+            self.actors.remove(n);
+            return true;
+        }
+        return false;
+    }
+
     // accessor-likes
     pub fn is_named(&self, x:&str) -> bool { return self.name == x; }
     pub fn named(&self) -> String { return self.name.clone(); }
+    pub fn level(&self) -> i32 { return self.level; }
+    pub fn set_level(&mut self, src:i32) { self.level = src; }
+    pub fn actors(&self) -> &[r_Actor] { return &self.actors; }
+    pub fn revision(&self) -> u32 { return self.revision; }
+    pub fn map_objects(&self) -> Vec<r_MapObject> {    // row-major order, so savefiles are stable
+        let mut ret: Vec<r_MapObject> = self.objects.values().map(Rc::clone).collect();
+        ret.sort_by_key(|obj| { let pos = obj.borrow().loc().pos; [pos[1], pos[0]] });
+        return ret;
+    }
+
+    pub fn item_piles(&self) -> Vec<([i32;2],&Inventory)> {   // row-major order, so savefiles are stable
+        let mut ret: Vec<([i32;2],&Inventory)> = self.items.iter().map(|x| (*x.0, x.1)).collect();
+        ret.sort_by_key(|x| [x.0[1], x.0[0]]);
+        return ret;
+    }
 
     pub fn width(&self) -> usize { return self.dim[0]; }
     pub fn height(&self) -> usize { return self.dim[1]; }
@@ -608,7 +674,12 @@ impl Map {
     pub fn set_terrain(&mut self, pt: [i32;2], src:r_Terrain) {
         debug_assert!(self.in_bounds(pt));
         let dest = Map::usize_cast(pt);
+        let was = self.terrain[dest[0]+dest[1]*self.dim[0]].conveyor.is_some();
+        if was != src.conveyor.is_some() {
+            if was { self.conveyors.retain(|x| *x != pt); } else { self.conveyors.push(pt); }
+        }
         self.terrain[dest[0]+dest[1]*self.dim[0]] = src;
+        self.revision = self.revision.wrapping_add(1);
     }
 
     pub fn get_terrain(&self, pt: [i32;2]) -> r_Terrain {
@@ -622,45 +693,122 @@ impl Map {
 //      let map = loc.map.borrow();
 //      debug_assert!(self == map);
         debug_assert!(self.in_bounds(loc.pos));
+        self.revision = self.revision.wrapping_add(1);
         return self.objects.insert(loc.pos, src);
     }
 
+    pub fn remove_map_object(&mut self, pt:[i32;2]) -> Option<r_MapObject> {
+        debug_assert!(self.in_bounds(pt));
+        let ret = self.objects.remove(&pt);
+        if ret.is_some() { self.revision = self.revision.wrapping_add(1); }
+        return ret;
+    }
+
     pub fn get_map_object(&self, pt:[i32;2]) -> Option<r_MapObject> {
         debug_assert!(self.in_bounds(pt));
         if let Some(obj) = self.objects.get(&pt) { return Some(Rc::clone(obj)); }
         else { return None; }
     }
 
+    pub fn get_water(&self, pt:[i32;2]) -> u8 { return self.water.get(&pt).copied().unwrap_or(0); }
+
+    pub fn set_water(&mut self, pt:[i32;2], depth:u8) {
+        debug_assert!(self.in_bounds(pt));
+        if self.get_water(pt) == depth { return; }
+        if 0 == depth { self.water.remove(&pt); }
+        else { self.water.insert(pt, depth); }
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    pub fn water_cells(&self) -> Vec<([i32;2],u8)> {   // row-major order, so savefiles are stable
+        let mut ret: Vec<([i32;2],u8)> = self.water.iter().map(|x| (*x.0, *x.1)).collect();
+        ret.sort_by_key(|x| [x.0[1], x.0[0]]);
+        return ret;
+    }
+
+    pub fn flowing(&self) -> &[[i32;2]] { return &self.flowing; }
+    pub fn is_flowing(&self, pt:[i32;2]) -> bool { return self.flowing.contains(&pt); }
+    pub fn set_flowing(&mut self, src:Vec<[i32;2]>) { self.flowing = src; }
+    pub fn conveyors(&self) -> &[[i32;2]] { return &self.conveyors; }
+    pub fn powered(&self) -> &[[i32;2]] { return &self.powered; }
+    pub fn is_powered(&self, pt:[i32;2]) -> bool { return self.powered.contains(&pt); }
+    pub fn set_powered(&mut self, src:Vec<[i32;2]>) { self.powered = src; }
+
+    pub fn get_items(&self, pt:[i32;2]) -> Option<&Inventory> {
+        debug_assert!(self.in_bounds(pt));
+        return self.items.get(&pt);
+    }
+
+    pub fn add_item(&mut self, pt:[i32;2], src:Item) {
+        debug_assert!(self.in_bounds(pt));
+        let pile = self.items.entry(pt).or_insert_with(|| Inventory::new(None));
+        let _ = pile.add(src);  // floor piles are unlimited
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    // the top of the pile
+    pub fn take_item(&mut self, pt:[i32;2]) -> Option<Item> {
+        debug_assert!(self.in_bounds(pt));
+        let pile = self.items.get_mut(&pt)?;
+        let n = pile.len();
+        let ret = pile.remove(n-1);
+        if pile.is_empty() { self.items.remove(&pt); }
+        self.revision = self.revision.wrapping_add(1);
+        return ret;
+    }
+
     pub fn get_actor(&self, pt:[i32;2]) -> Option<r_Actor> {
         debug_assert!(self.in_bounds(pt));
-        for act in &self.actors {
-            if act.borrow().loc().pos == pt {return Some(Rc::clone(act));}
-        }
-        return None;
+        return self.occupants.get(&pt).cloned();
+    }
+
+    // two actors in one cell would lose one of them from the index, so this is never allowed
+    pub fn occupy(&mut self, pt:[i32;2], src:r_Actor) {
+        debug_assert!(self.in_bounds(pt));
+        assert!(self.occupants.get(&pt).is_none_or(|x| Rc::ptr_eq(x, &src)), "{} {:?} already occupied", self.name, pt);
+        self.occupants.insert(pt, src);
+    }
+
+    // who is compared by address, as the caller usually has the actor borrowed
+    pub fn vacate(&mut self, pt:[i32;2], who:*const Actor) -> Option<r_Actor> {
+        if !self.occupants.get(&pt).is_some_and(|x| std::ptr::eq(x.as_ptr(), who)) { return None; }
+        return self.occupants.remove(&pt);
     }
 
-    pub fn is_walkable_for(&self, pt:&[i32;2], _who:&Actor) -> bool {
+    // terrain and map objects only
+    pub fn is_passable_for(&self, pt:&[i32;2], _who:&Actor) -> bool {
         debug_assert!(self.in_bounds(*pt));
         let dest = Map::usize_cast(*pt);
         if !self.terrain[dest[0]+dest[1]*self.dim[0]].walkable { return false; }    // a ghost (or hologram) might disagree, but non-issue here
-        // \todo don't move into another Actor (could be done elsewhere)
         if let Some(obj) = self.get_map_object(*pt) { // check for map objects
             if !obj.borrow().model.walkable { return false; }
         }
         return true;
     }
 
+    pub fn is_walkable_for(&self, pt:&[i32;2], who:&Actor) -> bool {
+        if let Some(act) = self.occupants.get(pt) {
+            if !std::ptr::eq(act.as_ptr(), who) { return false; }
+        }
+        return self.is_passable_for(pt, who);
+    }
+
+    pub fn is_transparent(&self, pt:[i32;2]) -> bool {
+        debug_assert!(self.in_bounds(pt));
+        let dest = Map::usize_cast(pt);
+        if !self.terrain[dest[0]+dest[1]*self.dim[0]].transparent { return false; }
+        // \todo huge actors might also block LoS
+        if let Some(obj) = self.get_map_object(pt) { // check for map objects
+            if !obj.borrow().model.transparent { return false; }
+        }
+        return true;
+    }
+
     pub fn los(&self, from:&[i32;2], to:&[i32;2]) -> (bool, Vec<Point<i32>>) {
         let is_visible = |x:&Point::<i32>| -> bool {
             if !self.in_bounds(**x) { return false; }
             if *to == **x { return true; }
-            let dest = Map::usize_cast(**x);
-            if !self.terrain[dest[0]+dest[1]*self.dim[0]].transparent { return false; }
-            // \todo huge actors might also block LoS
-            if let Some(obj) = self.get_map_object(**x) { // check for map objects
-                if !obj.borrow().model.transparent { return false; }
-            }
-            return true;
+            return self.is_transparent(**x);
         };
         return AngbandlikeTrace(u32::MAX, &Point::new(from), &Point::new(to), &is_visible);
     }
@@ -671,51 +819,39 @@ impl Map {
         // also, if non-blocking terrain is barely visible, the contained mapobject/actor need not be visible
     }
 
-    pub fn next_actor(&self) -> Option<r_Actor> {
-        for r_act in &self.actors {  // \todo some caching likely needed for performance (index into this array)
-            // \todo: disallow dead actors
-            if 0 < r_act.borrow().energy() { return Some(Rc::clone(r_act)); }
-        }
-        return None;
-    }
-
     // \return true iff no actors on this map
-    pub fn turn_postprocess(&mut self) -> bool {
-        let mut ub = self.actors.len();
-        if 0 >= ub { return true; }
-        while 0 < ub {
-            ub -= 1;
-            // \todo death processing, etc.
-            self.actors[ub].borrow_mut().turn_postprocess();
-        }
-        return false;
-    }
-
     // inappropriate UI functions
     pub fn bg(&self, pt: [usize;2]) -> BackgroundSpec {
         return self.terrain[pt[0]+pt[1]*self.dim[0]].bg.clone();
     }
     pub fn bg_i32(&self, pt: [i32;2]) -> BackgroundSpec { return self.bg(Map::usize_cast(pt)); }
 
-    pub fn tiles(&self, pt: [i32;2]) -> Option<Vec<TileSpec>> {
+    // what an actor would remember: terrain, water, map objects, and the top item
+    pub fn scenery(&self, pt: [i32;2]) -> Vec<TileSpec> {
         let mut ret = Vec::<TileSpec>::new();
         {
         let pt_usize = Map::usize_cast(pt);
         let tile_fg = self.terrain[pt_usize[0]+pt_usize[1]*self.dim[0]].tile.clone();
         if DisplayManager::is_visible(&tile_fg) { ret.push(tile_fg); }
         }
-        // \todo check for map objects
+        if let Some(depth) = self.water.get(&pt) { ret.push(water_tile(*depth)); }
         if let Some(obj) = self.objects.get(&pt) {
             let tile_fg = obj.borrow().model.tile.clone();
             if DisplayManager::is_visible(&tile_fg) { ret.push(tile_fg); }
         }
-        // \todo check for inventory
-        for act in &self.actors {
+        if let Some(item) = self.items.get(&pt).and_then(|pile| pile.top()) {
+            let tile_fg = item.model.tile.clone();
+            if DisplayManager::is_visible(&tile_fg) { ret.push(tile_fg); }
+        }
+        return ret;
+    }
+
+    pub fn tiles(&self, pt: [i32;2]) -> Option<Vec<TileSpec>> {
+        let mut ret = self.scenery(pt);
+        if let Some(act) = self.occupants.get(&pt) {
             if let Ok(a) = act.try_borrow() {
-                if pt == a.loc().pos {
-                    let a_fg = a.fg();
-                    if DisplayManager::is_visible(&a_fg) {ret.push(a_fg);}
-                }
+                let a_fg = a.fg();
+                if DisplayManager::is_visible(&a_fg) {ret.push(a_fg);}
             }
         }
         if !ret.is_empty() { return Some(ret); }
@@ -805,14 +941,24 @@ impl Location {
     }
 
     pub fn is_walkable_for(&self, who:&Actor) -> bool { return self.map.borrow().is_walkable_for(&self.pos, who); }
+    pub fn is_passable_for(&self, who:&Actor) -> bool { return self.map.borrow().is_passable_for(&self.pos, who); }
     pub fn get_map_object(&self) -> Option<r_MapObject> { return self.map.borrow().get_map_object(self.pos); }
     pub fn set_map_object(&self, src:r_MapObjectModel) -> Option<r_MapObject> {
         return self.map.borrow_mut().set_map_object(Rc::new(RefCell::new(MapObject::new(src,self.clone()))));
     }
     pub fn get_actor(&self) -> Option<r_Actor> { return self.map.borrow().get_actor(self.pos); }
+    pub fn get_items(&self) -> Vec<Item> { return self.map.borrow().get_items(self.pos).map_or(Vec::new(), |x| x.stacks().to_vec()); }
+    pub fn add_item(&self, src:Item) { self.map.borrow_mut().add_item(self.pos, src); }
+    pub fn take_item(&self) -> Option<Item> { return self.map.borrow_mut().take_item(self.pos); }
     pub fn get_terrain(&self) -> r_Terrain { return self.map.borrow().get_terrain(self.pos); }
 }
 
+impl PartialEq for Location {
+    fn eq(&self, other: &Location) -> bool {
+        return Rc::ptr_eq(&self.map, &other.map) && self.pos == other.pos;
+    }
+}
+
 impl std::fmt::Debug for Location {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error>
     {
@@ -0,0 +1,246 @@
+use crate::isk::*;
+
+pub const BASE_CAPACITY:usize = 10;  // stacks
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Slot {
+    Weapon,
+    Armor,
+    Helmet,
+    Boots
+}
+
+pub const SLOTS:[Slot;4] = [Slot::Weapon, Slot::Armor, Slot::Helmet, Slot::Boots];
+
+impl Slot {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Slot::Weapon => return "weapon",
+            Slot::Armor => return "armor",
+            Slot::Helmet => return "helmet",
+            Slot::Boots => return "boots"
+        }
+    }
+
+    pub fn from_name(src:&str) -> Option<Slot> {
+        for x in SLOTS.iter() {
+            if x.name() == src { return Some(*x); }
+        }
+        return None;
+    }
+}
+
+pub struct ItemModel {
+    pub name: String,
+    pub tile: TileSpec,
+    pub slot: Option<Slot>,    // None: cannot be equipped
+    // modifiers while equipped
+    pub speed: i16,
+    pub hp: i16,
+    pub attack: i16,
+    pub defense: i16,
+    pub damage: i16
+}
+pub type r_ItemModel = Rc<ItemModel>;
+
+impl ItemModel {
+    pub fn new(_name: &str, _tile:TileSpec) -> ItemModel {
+        return ItemModel{name:_name.to_string(), tile:_tile, slot:None, speed:0, hp:0, attack:0, defense:0, damage:0};
+    }
+
+    pub fn is_named(&self, _name:&str) -> bool { return self.name == _name; }
+}
+
+// a stack of identical items
+#[derive(Clone)]
+pub struct Item {
+    pub model: r_ItemModel,
+    pub qty: u16
+}
+
+impl Item {
+    pub fn new(_model: r_ItemModel, _qty:u16) -> Item {
+        debug_assert!(0 < _qty);
+        return Item{model:_model, qty:_qty};
+    }
+
+    pub fn describe(&self) -> String {
+        if 1 == self.qty { return self.model.name.clone(); }
+        return self.qty.to_string()+" x "+&self.model.name;
+    }
+}
+
+// stacks merge when their models are the same; used both for actors and for floor cells
+#[derive(Clone)]
+pub struct Inventory {
+    stacks: Vec<Item>,
+    capacity: Option<usize>    // None: unlimited (floor)
+}
+
+impl Inventory {
+    pub fn new(_capacity:Option<usize>) -> Inventory { return Inventory{stacks:Vec::new(), capacity:_capacity}; }
+
+    pub fn is_empty(&self) -> bool { return self.stacks.is_empty(); }
+    pub fn len(&self) -> usize { return self.stacks.len(); }
+    pub fn stacks(&self) -> &[Item] { return &self.stacks; }
+    pub fn top(&self) -> Option<&Item> { return self.stacks.last(); }
+
+    // a stack of the same model with room for all of src
+    fn merge_target(&self, src:&Item) -> Option<usize> {
+        return self.stacks.iter().position(|x| Rc::ptr_eq(&x.model, &src.model) && x.qty.checked_add(src.qty).is_some());
+    }
+
+    pub fn can_take(&self, src:&Item) -> bool {
+        if self.merge_target(src).is_some() { return true; }
+        match self.capacity {
+            Some(ub) => return self.stacks.len() < ub,
+            None => return true
+        }
+    }
+
+    // gives the stack back if there is no room; a full stack starts a new one
+    pub fn add(&mut self, src:Item) -> Result<(),Item> {
+        if !self.can_take(&src) { return Err(src); }
+        if let Some(n) = self.merge_target(&src) {
+            self.stacks[n].qty += src.qty;
+            return Ok(());
+        }
+        self.stacks.push(src);
+        return Ok(());
+    }
+
+    pub fn remove(&mut self, n:usize) -> Option<Item> {
+        if n >= self.stacks.len() { return None; }
+        return Some(self.stacks.remove(n));
+    }
+
+    pub fn remove_one(&mut self, n:usize) -> Option<Item> {
+        if n >= self.stacks.len() { return None; }
+        if 1 == self.stacks[n].qty { return self.remove(n); }
+        self.stacks[n].qty -= 1;
+        return Some(Item::new(Rc::clone(&self.stacks[n].model), 1));
+    }
+}
+
+impl Actor {
+    pub fn equipment(&self) -> &[Item] { return &self.equipment; }
+
+    pub fn equipped(&self, slot:Slot) -> Option<&Item> {
+        return self.equipment.iter().find(|x| Some(slot) == x.model.slot);
+    }
+
+    // sum of one modifier over everything equipped
+    pub fn bonus(&self, f:fn(&ItemModel) -> i16) -> i16 {
+        return self.equipment.iter().map(|x| f(&x.model)).sum();
+    }
+
+    // one of the inventory stack at n; anything already in that slot goes back to the inventory
+    pub fn equip(&mut self, n:usize) -> bool {
+        let slot = match self.inventory.stacks().get(n).and_then(|x| x.model.slot) {
+            Some(x) => x,
+            None => return false
+        };
+        if let Some(old) = self.equipped(slot) {
+            let freed = 1 == self.inventory.stacks()[n].qty;    // the stack is used up, which makes room
+            if !freed && !self.inventory.can_take(old) { return false; }
+        }
+        let item = self.inventory.remove_one(n).unwrap();
+        let ok = self.unequip(slot);
+        debug_assert!(ok, "room was checked above");
+        self.hp.change_max(item.model.hp);
+        self.equipment.push(item);
+        return true;
+    }
+
+    // true if the slot is now empty
+    pub fn unequip(&mut self, slot:Slot) -> bool {
+        let n = match self.equipment.iter().position(|x| Some(slot) == x.model.slot) {
+            Some(n) => n,
+            None => return true
+        };
+        if !self.inventory.can_take(&self.equipment[n]) { return false; }
+        let item = self.equipment.remove(n);
+        self.hp.change_max(-item.model.hp);
+        let _ = self.inventory.add(item);
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wearer(w:&mut World) -> r_Actor {
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:None}), true, true));
+        let model = w.new_actor_model(ActorModel::new("m", Ok(CharSpec{img:'m', c:None})));
+        let m = w.new_map("m", [3, 3], floor);
+        return w.new_actor(model, &Location::new(&m, [0,0]), [1,1]).unwrap();
+    }
+
+    fn helmet(w:&mut World, name:&str, hp:i16) -> r_ItemModel {
+        let mut ret = ItemModel::new(name, Ok(CharSpec{img:'[', c:None}));
+        ret.slot = Some(Slot::Helmet);
+        ret.hp = hp;
+        return w.new_item_model(ret);
+    }
+
+    #[test]
+    fn equip_swaps_into_a_full_inventory() {
+        let mut w = World::new();
+        let r_act = wearer(&mut w);
+        let cap = helmet(&mut w, "cap", 0);
+        let helm = helmet(&mut w, "helm", 0);
+        let mut act = r_act.borrow_mut();
+        assert!(act.inventory_mut().add(Item::new(Rc::clone(&cap), 1)).is_ok());
+        assert!(act.equip(0));
+        assert!(act.inventory_mut().add(Item::new(Rc::clone(&helm), 1)).is_ok());
+        for n in 0..BASE_CAPACITY-1 {
+            let junk = Rc::new(ItemModel::new(&n.to_string(), Ok(CharSpec{img:'*', c:None})));
+            assert!(act.inventory_mut().add(Item::new(junk, 1)).is_ok());
+        }
+        assert_eq!(BASE_CAPACITY, act.inventory().len());
+        assert!(act.equip(0));  // the helm's stack is used up, so the cap fits
+        assert!(Rc::ptr_eq(&helm, &act.equipped(Slot::Helmet).unwrap().model));
+        assert!(act.inventory().stacks().iter().any(|x| Rc::ptr_eq(&cap, &x.model)));
+        // a stack of two stays, so there is no room for what is worn
+        let n = act.inventory().stacks().iter().position(|x| Rc::ptr_eq(&cap, &x.model)).unwrap();
+        act.inventory_mut().remove(n);
+        let hood = helmet(&mut w, "hood", 0);
+        assert!(act.inventory_mut().add(Item::new(hood, 2)).is_ok());
+        let n = act.inventory().len()-1;
+        assert!(!act.equip(n));
+        assert!(Rc::ptr_eq(&helm, &act.equipped(Slot::Helmet).unwrap().model));
+    }
+
+    #[test]
+    fn max_hp_carries_wounds_over() {
+        let mut w = World::new();
+        let r_act = wearer(&mut w);
+        let helm = helmet(&mut w, "helm", 5);
+        let mut act = r_act.borrow_mut();
+        act.hp_mut().takeHit(3);
+        assert_eq!((7, 10), (act.hp.curHp, act.hp.maxHp));
+        assert!(act.inventory_mut().add(Item::new(helm, 1)).is_ok());
+        assert!(act.equip(0));
+        assert_eq!((12, 15), (act.hp.curHp, act.hp.maxHp));
+        act.hp_mut().takeHit(10);
+        assert!(act.unequip(Slot::Helmet));
+        assert_eq!((1, 10), (act.hp.curHp, act.hp.maxHp));    // never killed by undressing
+    }
+
+    #[test]
+    fn full_stacks_start_a_new_one() {
+        let rock = Rc::new(ItemModel::new("rock", Ok(CharSpec{img:'*', c:None})));
+        let mut inv = Inventory::new(Some(2));
+        assert!(inv.add(Item::new(Rc::clone(&rock), u16::MAX-1)).is_ok());
+        assert!(inv.add(Item::new(Rc::clone(&rock), 1)).is_ok());
+        assert_eq!(vec![u16::MAX], inv.stacks().iter().map(|x| x.qty).collect::<Vec<_>>());
+        assert!(inv.add(Item::new(Rc::clone(&rock), 5)).is_ok());
+        assert_eq!(vec![u16::MAX, 5], inv.stacks().iter().map(|x| x.qty).collect::<Vec<_>>());
+        // out of room: nothing is lost
+        assert!(inv.add(Item::new(Rc::clone(&rock), 1)).is_ok());
+        let back = inv.add(Item::new(Rc::clone(&rock), u16::MAX)).unwrap_err();
+        assert_eq!(u16::MAX, back.qty);
+        assert_eq!(vec![u16::MAX, 6], inv.stacks().iter().map(|x| x.qty).collect::<Vec<_>>());
+    }
+}
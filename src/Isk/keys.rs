@@ -0,0 +1,362 @@
+use crate::isk::*;
+use crate::isk::gps::*;
+use crate::isk::transition::*;
+use tcod::input::KeyCode;
+
+// keymap files: plain text, one binding per line, key = command.  Lines starting with # are comments.
+// * preset = numpad, arrows, or vi: adds that preset's bindings
+// * key: optional Alt+, Ctrl+ and/or Shift+ prefixes, then either a name from KEY_NAMES or a single printable character.
+//   libtcod reports the unshifted key, so an upper-case letter or a character from SHIFTED means Shift+ that key.
+// * command: a name from Command::from_name, e.g. move nw, close, quit
+// The first binding for a key wins, so lines before a preset override it.
+
+const DIRECTION_NAMES: [&str; 8] = ["n", "ne", "e", "se", "s", "sw", "w", "nw"];    // Compass order
+
+const KEY_NAMES: [(&str, KeyCode); 64] = [
+    ("Escape", KeyCode::Escape), ("Backspace", KeyCode::Backspace), ("Tab", KeyCode::Tab), ("Enter", KeyCode::Enter),
+    ("Shift", KeyCode::Shift), ("Control", KeyCode::Control), ("Alt", KeyCode::Alt), ("Pause", KeyCode::Pause),
+    ("CapsLock", KeyCode::CapsLock), ("PageUp", KeyCode::PageUp), ("PageDown", KeyCode::PageDown), ("End", KeyCode::End),
+    ("Home", KeyCode::Home), ("Up", KeyCode::Up), ("Left", KeyCode::Left), ("Right", KeyCode::Right), ("Down", KeyCode::Down),
+    ("PrintScreen", KeyCode::PrintScreen), ("Insert", KeyCode::Insert), ("Delete", KeyCode::Delete),
+    ("LeftWin", KeyCode::LeftWin), ("RightWin", KeyCode::RightWin), ("Apps", KeyCode::Apps), ("Number0", KeyCode::Number0),
+    ("Number1", KeyCode::Number1), ("Number2", KeyCode::Number2), ("Number3", KeyCode::Number3), ("Number4", KeyCode::Number4),
+    ("Number5", KeyCode::Number5), ("Number6", KeyCode::Number6), ("Number7", KeyCode::Number7), ("Number8", KeyCode::Number8),
+    ("Number9", KeyCode::Number9), ("NumPad0", KeyCode::NumPad0), ("NumPad1", KeyCode::NumPad1), ("NumPad2", KeyCode::NumPad2),
+    ("NumPad3", KeyCode::NumPad3), ("NumPad4", KeyCode::NumPad4), ("NumPad5", KeyCode::NumPad5), ("NumPad6", KeyCode::NumPad6),
+    ("NumPad7", KeyCode::NumPad7), ("NumPad8", KeyCode::NumPad8), ("NumPad9", KeyCode::NumPad9),
+    ("NumPadAdd", KeyCode::NumPadAdd), ("NumPadSubtract", KeyCode::NumPadSubtract), ("NumPadDivide", KeyCode::NumPadDivide),
+    ("NumPadMultiply", KeyCode::NumPadMultiply), ("NumPadDecimal", KeyCode::NumPadDecimal), ("NumPadEnter", KeyCode::NumPadEnter),
+    ("F1", KeyCode::F1), ("F2", KeyCode::F2), ("F3", KeyCode::F3), ("F4", KeyCode::F4), ("F5", KeyCode::F5), ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7), ("F8", KeyCode::F8), ("F9", KeyCode::F9), ("F10", KeyCode::F10), ("F11", KeyCode::F11),
+    ("F12", KeyCode::F12), ("NumLock", KeyCode::NumLock), ("ScrollLock", KeyCode::ScrollLock), ("Space", KeyCode::Spacebar)
+];
+
+// shifted punctuation on a US keyboard, and the key it is on
+const SHIFTED: [(char, char); 21] = [
+    ('!', '1'), ('@', '2'), ('#', '3'), ('$', '4'), ('%', '5'), ('^', '6'), ('&', '7'), ('*', '8'), ('(', '9'), (')', '0'),
+    ('_', '-'), ('+', '='), ('{', '['), ('}', ']'), ('|', '\\'), (':', ';'), ('"', '\''), ('<', ','), ('>', '.'), ('?', '/'),
+    ('~', '`')
+];
+
+// shared by all presets
+const COMMON_KEYS: &str = "
+Escape = quit
+Alt+Enter = fullscreen
+< = up
+> = down
+t = travel
+g = pick up
+d = drop
+w = equip
+r = remove
+o = open
+c = close
+p = pull
+Ctrl+l = lock
+Ctrl+u = unlock
+Ctrl+b = break
+x = look
+";
+
+const NUMPAD_KEYS: &str = "
+NumPad8 = move n
+NumPad9 = move ne
+NumPad6 = move e
+NumPad3 = move se
+NumPad2 = move s
+NumPad1 = move sw
+NumPad4 = move w
+NumPad7 = move nw
+NumPad5 = wait
+";
+
+const ARROW_KEYS: &str = "
+Up = move n
+Right = move e
+Down = move s
+Left = move w
+Space = wait
+";
+
+const VI_KEYS: &str = "
+k = move n
+u = move ne
+l = move e
+n = move se
+j = move s
+b = move sw
+h = move w
+y = move nw
+. = wait
+";
+
+pub fn direction_name(src:Compass) -> &'static str { return DIRECTION_NAMES[usize::try_from(i32::from(src)).unwrap()]; }
+
+pub fn direction_from_name(src:&str) -> Option<Compass> {
+    let n = DIRECTION_NAMES.iter().position(|x| *x == src)?;
+    return Some(Compass::try_from(i32::try_from(n).unwrap()).unwrap());
+}
+
+// everything but KeyCode::Char (and the unused NoKey, Text)
+pub fn key_code_name(src:KeyCode) -> Option<&'static str> {
+    return KEY_NAMES.iter().find(|x| x.1 == src).map(|x| x.0);
+}
+
+pub fn key_code_from_name(src:&str) -> Option<KeyCode> {
+    return KEY_NAMES.iter().find(|x| x.0 == src).map(|x| x.1);
+}
+
+// what the player can ask for, independent of the keys that ask for it
+#[derive(Clone,PartialEq)]
+pub enum Command {
+    Move(Compass),
+    Wait,
+    Travel,     // take the exit here
+    Up,         // take the stairs here, up a floor
+    Down,
+    PickUp,
+    Drop,
+    Equip,
+    Remove,
+    Use(Verb),  // on an adjacent map object
+    Look,       // free; describes an adjacent cell
+    Quit,
+    ToggleFullscreen
+}
+
+impl Command {
+    pub fn from_name(src:&str) -> Option<Command> {
+        if let Some(dir) = src.strip_prefix("move ") { return Some(Command::Move(direction_from_name(dir)?)); }
+        if let Some(verb) = Verb::from_name(src) { return Some(Command::Use(verb)); }
+        match src {
+            "wait" => return Some(Command::Wait),
+            "travel" => return Some(Command::Travel),
+            "up" => return Some(Command::Up),
+            "down" => return Some(Command::Down),
+            "pick up" => return Some(Command::PickUp),
+            "drop" => return Some(Command::Drop),
+            "equip" => return Some(Command::Equip),
+            "remove" => return Some(Command::Remove),
+            "look" => return Some(Command::Look),
+            "quit" => return Some(Command::Quit),
+            "fullscreen" => return Some(Command::ToggleFullscreen),
+            _ => return None
+        }
+    }
+}
+
+// a key and exactly the modifiers that must be down with it
+#[derive(Clone,PartialEq,Debug)]
+pub struct KeyCombo {
+    code: KeyCode,
+    printable: char,    // only for KeyCode::Char; unshifted
+    alt: bool,
+    ctrl: bool,
+    shift: bool
+}
+
+impl KeyCombo {
+    pub fn parse(src:&str) -> Option<KeyCombo> {
+        let mut alt = false;
+        let mut ctrl = false;
+        let mut shift = false;
+        let mut rest = src;
+        loop {
+            if let Some(x) = rest.strip_prefix("Alt+") { alt = true; rest = x; }
+            else if let Some(x) = rest.strip_prefix("Ctrl+") { ctrl = true; rest = x; }
+            else if let Some(x) = rest.strip_prefix("Shift+") { shift = true; rest = x; }
+            else { break; }
+        }
+        if let Some(code) = key_code_from_name(rest) { return Some(KeyCombo{code, printable:'\0', alt, ctrl, shift}); }
+        let mut chars = rest.chars();
+        let c = match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_graphic() => c,
+            _ => return None
+        };
+        let unshifted = if c.is_ascii_uppercase() { Some(c.to_ascii_lowercase()) }
+            else { SHIFTED.iter().find(|x| x.0 == c).map(|x| x.1) };
+        match unshifted {
+            Some(x) => return Some(KeyCombo{code:KeyCode::Char, printable:x, alt, ctrl, shift:true}),
+            None => return Some(KeyCombo{code:KeyCode::Char, printable:c, alt, ctrl, shift})
+        }
+    }
+
+    pub fn matches(&self, key:Key) -> bool {
+        if self.code != key.code || self.alt != key.alt || self.ctrl != key.ctrl || self.shift != key.shift { return false; }
+        return KeyCode::Char != self.code || self.printable == key.printable;
+    }
+}
+
+pub struct KeyMap {
+    bindings: Vec<(KeyCombo,Command)>
+}
+
+impl KeyMap {
+    pub fn new() -> KeyMap { return KeyMap{bindings:Vec::new()}; }
+
+    // numpad and arrows
+    pub fn standard() -> KeyMap {
+        let mut ret = KeyMap::new();
+        for name in ["numpad", "arrows"].iter() { ret.add_preset(name).unwrap(); }
+        return ret;
+    }
+
+    pub fn command(&self, key:Key) -> Option<Command> {
+        return self.bindings.iter().find(|x| x.0.matches(key)).map(|x| x.1.clone());
+    }
+
+    pub fn bind(&mut self, combo:KeyCombo, cmd:Command) {
+        if self.bindings.iter().any(|x| x.0 == combo) { return; }  // first binding wins
+        self.bindings.push((combo, cmd));
+    }
+
+    pub fn add_preset(&mut self, name:&str) -> Result<(),Error> {
+        let keys = match name {
+            "numpad" => NUMPAD_KEYS,
+            "arrows" => ARROW_KEYS,
+            "vi" => VI_KEYS,
+            _ => return Err(Error{desc:"unknown key preset ".to_string()+name})
+        };
+        self.parse("(preset)", keys)?;
+        return self.parse("(preset)", COMMON_KEYS);
+    }
+
+    pub fn load(path:&str) -> Result<KeyMap,Error> {
+        let mut ret = KeyMap::new();
+        match std::fs::read_to_string(path) {
+            Ok(src) => ret.parse(path, &src)?,
+            Err(e) => return Err(Error{desc:path.to_string()+": "+&e.to_string()})
+        }
+        return Ok(ret);
+    }
+
+    // path is only used for error messages
+    pub fn parse(&mut self, path:&str, src:&str) -> Result<(),Error> {
+        for (n, raw) in src.lines().enumerate() {
+            let line_no = n+1;
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            // the key may itself be =
+            let first = line.chars().next().unwrap().len_utf8();
+            let (key, cmd) = match line[first..].find('=') {
+                Some(i) if !line[first+i+1..].trim().is_empty() => (line[..first+i].trim(), line[first+i+1..].trim()),
+                _ => return Err(Error{desc:format!("{}:{}: expected key = command", path, line_no)})
+            };
+            if "preset" == key {
+                if let Err(e) = self.add_preset(cmd) { return Err(Error{desc:format!("{}:{}: {}", path, line_no, e.desc)}); }
+                continue;
+            }
+            let combo = match KeyCombo::parse(key) {
+                Some(x) => x,
+                None => return Err(Error{desc:format!("{}:{}: unknown key {}", path, line_no, key)})
+            };
+            match Command::from_name(cmd) {
+                Some(x) => self.bind(combo, x),
+                None => return Err(Error{desc:format!("{}:{}: unknown command {}", path, line_no, cmd)})
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code:KeyCode, printable:char, alt:bool, ctrl:bool) -> Key {
+        let mut ret = Key::default();
+        ret.code = code;
+        ret.printable = printable;
+        ret.alt = alt;
+        ret.ctrl = ctrl;
+        return ret;
+    }
+
+    fn ch(c:char) -> Key { return key(KeyCode::Char, c, false, false); }
+
+    fn shifted(c:char) -> Key {
+        let mut ret = ch(c);
+        ret.shift = true;
+        return ret;
+    }
+
+    fn parse_err(src:&str) -> String {
+        return KeyMap::new().parse("test", src).unwrap_err().desc;
+    }
+
+    #[test]
+    fn key_combos_parse() {
+        assert_eq!(Some(KeyCombo{code:KeyCode::NumPad7, printable:'\0', alt:false, ctrl:false, shift:false}), KeyCombo::parse("NumPad7"));
+        assert_eq!(Some(KeyCombo{code:KeyCode::Enter, printable:'\0', alt:true, ctrl:false, shift:false}), KeyCombo::parse("Alt+Enter"));
+        assert_eq!(Some(KeyCombo{code:KeyCode::Char, printable:'l', alt:true, ctrl:true, shift:false}), KeyCombo::parse("Ctrl+Alt+l"));
+        assert_eq!(Some(KeyCombo{code:KeyCode::Char, printable:'=', alt:false, ctrl:false, shift:false}), KeyCombo::parse("="));
+        assert_eq!(None, KeyCombo::parse("NumPad10"));
+        assert_eq!(None, KeyCombo::parse("ab"));
+        assert_eq!(None, KeyCombo::parse("Ctrl+"));
+        assert_eq!(None, KeyCombo::parse(" "));
+
+        let combo = KeyCombo::parse("Ctrl+l").unwrap();
+        assert!(combo.matches(key(KeyCode::Char, 'l', false, true)));
+        assert!(!combo.matches(ch('l')));
+        assert!(!combo.matches(key(KeyCode::Char, 'l', true, true)));
+        assert!(!combo.matches(key(KeyCode::Char, 'u', false, true)));
+    }
+
+    #[test]
+    fn shifted_characters_are_the_key_with_shift() {
+        assert_eq!(Some(KeyCombo{code:KeyCode::Char, printable:',', alt:false, ctrl:false, shift:true}), KeyCombo::parse("<"));
+        assert_eq!(Some(KeyCombo{code:KeyCode::Char, printable:'g', alt:false, ctrl:false, shift:true}), KeyCombo::parse("G"));
+        assert_eq!(KeyCombo::parse("Shift+g"), KeyCombo::parse("G"));
+        assert_eq!(Some(KeyCombo{code:KeyCode::Tab, printable:'\0', alt:false, ctrl:false, shift:true}), KeyCombo::parse("Shift+Tab"));
+
+        // as libtcod reports them: the unshifted character, and the shift flag
+        let keys = KeyMap::standard();
+        assert!(Some(Command::Up) == keys.command(shifted(',')));
+        assert!(Some(Command::Down) == keys.command(shifted('.')));
+        assert!(keys.command(ch(',')).is_none());
+        assert!(Some(Command::Travel) == keys.command(ch('t')));
+        assert!(Some(Command::PickUp) == keys.command(ch('g')));
+        assert!(keys.command(shifted('g')).is_none());
+    }
+
+    #[test]
+    fn presets_bind_the_moves() {
+        let numpad = KeyMap::standard();
+        assert!(Some(Command::Move(Compass::NW)) == numpad.command(key(KeyCode::NumPad7, '\0', false, false)));
+        assert!(Some(Command::Wait) == numpad.command(key(KeyCode::NumPad5, '\0', false, false)));
+        assert!(Some(Command::Move(Compass::E)) == numpad.command(key(KeyCode::Right, '\0', false, false)));
+        assert!(Some(Command::PickUp) == numpad.command(ch('g')));
+        assert!(numpad.command(ch('k')).is_none());
+
+        let mut vi = KeyMap::new();
+        vi.parse("test", "preset = vi").unwrap();
+        assert!(Some(Command::Move(Compass::N)) == vi.command(ch('k')));
+        assert!(Some(Command::Move(Compass::SW)) == vi.command(ch('b')));
+        assert!(Some(Command::Wait) == vi.command(ch('.')));
+        assert!(Some(Command::Quit) == vi.command(key(KeyCode::Escape, '\0', false, false)));
+        assert!(vi.command(key(KeyCode::NumPad7, '\0', false, false)).is_none());
+        assert!(Some(Command::Use(Verb::Lock)) == vi.command(key(KeyCode::Char, 'l', false, true)));
+    }
+
+    #[test]
+    fn the_first_binding_wins() {
+        let mut keys = KeyMap::new();
+        keys.parse("test", "# comment\n\nk = look\npreset = vi\nk = quit\n= = wait\n").unwrap();
+        assert!(Some(Command::Look) == keys.command(ch('k')));
+        assert!(Some(Command::Move(Compass::S)) == keys.command(ch('j')));
+        assert!(Some(Command::Wait) == keys.command(ch('=')));
+    }
+
+    #[test]
+    fn errors_say_where() {
+        assert_eq!("test:2: expected key = command", parse_err("g = pick up\ng pick up"));
+        assert_eq!("test:1: unknown key Hyper+g", parse_err("Hyper+g = pick up"));
+        assert_eq!("test:1: unknown key NumPad10", parse_err("NumPad10 = wait"));
+        assert_eq!("test:3: unknown command jump", parse_err("# comment\n\ng = jump"));
+        assert_eq!("test:1: unknown command move up", parse_err("k = move up"));
+        assert_eq!("test:1: unknown key preset emacs", parse_err("preset = emacs"));
+        assert_eq!("test:1: expected key = command", parse_err("g = "));
+        assert_eq!("test:1: expected key = command", parse_err("="));
+        assert_eq!("missing.txt: ", &KeyMap::load("missing.txt").err().unwrap().desc[..13]);
+    }
+}
@@ -1,6 +1,34 @@
 use crate::isk::*;
 use std::sync::{Once,RwLock,RwLockReadGuard,RwLockWriteGuard};
 
+// how viewer refers to r_act in messages
+pub fn describe(viewer:&r_Actor, r_act:&r_Actor) -> String {
+    if Rc::ptr_eq(viewer, r_act) { return "you".to_string(); }
+    return "the ".to_string()+&r_act.borrow().model.name;
+}
+
+fn capitalize(src:&str) -> String {
+    let mut chars = src.chars();
+    match chars.next() {
+        Some(c) => return c.to_uppercase().collect::<String>()+chars.as_str(),
+        None => return String::new()
+    }
+}
+
+impl World {
+    // to every PC that can see any of the locations; only PCs read their message panels
+    pub fn announce(&self, locs:&[Location], msg:&dyn Fn(&r_Actor) -> String) {
+        for r_m in &self.atlas {
+            let viewers: Vec<r_Actor> = r_m.borrow().actors().iter().filter(|a| a.borrow().is_pc).cloned().collect();
+            for r_pc in viewers {
+                if locs.iter().any(|loc| self.can_see(&r_pc, loc)) {
+                    get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).add_message(&capitalize(&msg(&r_pc)));
+                }
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct msg_panel {
     prompt: Option<String>, // UI -- possibly should be player-driven instead
@@ -29,6 +57,11 @@ impl msg_panel {
         return Some(&self.messages[n]);
     }
     pub fn pop_message(&mut self) -> Option<(String,u8)> { return self.messages.pop(); }
+    // savefile support: repeat counts are restored as-is, rather than re-merged
+    pub fn restore_message(&mut self, src:&str, n:u8) {
+        if src.is_empty() || 0 == n {return;}
+        self.messages.push((src.to_string(),n));
+    }
     pub fn unshift_message(&mut self) {
         let ub = self.messages.len();
         if 0 < ub { self.messages.remove(0); }
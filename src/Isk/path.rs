@@ -0,0 +1,213 @@
+use crate::isk::*;
+use crate::isk::gps::*;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap,HashMap};
+
+// costs are in steps: moving is 1, bumping a door open and then moving through it is 2
+pub const STEP_COST:i32 = 1;
+pub const DOOR_COST:i32 = 2;
+
+fn neighbors() -> Vec<Compass> {
+    return (0..8).map(|n| Compass::try_from(n).unwrap()).collect();
+}
+
+fn chebyshev(a:[i32;2], b:[i32;2]) -> i32 {
+    return max((a[0]-b[0]).abs(), (a[1]-b[1]).abs());
+}
+
+// multi-source distances, in the coordinates of one map.  Transient: do not keep these on actors.
+pub struct DijkstraMap {
+    map: r_Map,
+    dist: HashMap<[i32;2],i32>
+}
+
+impl DijkstraMap {
+    pub fn origin(&self) -> r_Map { return Rc::clone(&self.map); }
+
+    // pt is in the coordinates of origin()
+    pub fn get(&self, pt:&[i32;2]) -> Option<i32> { return self.dist.get(pt).copied(); }
+
+    // for fleeing: rolling downhill on the result leads away from the sources, but not into dead ends
+    // (Cf. Brogue's "safety maps")
+    pub fn flee(&self, w:&World, who:&Actor) -> DijkstraMap {
+        let seeds: Vec<([i32;2],i32)> = self.dist.iter().map(|x| (*x.0, -(*x.1*6)/5)).collect();
+        let dist = w.relax(who, &self.map, seeds, &|pt| self.dist.contains_key(pt), i32::MAX);
+        return DijkstraMap{map:Rc::clone(&self.map), dist};
+    }
+}
+
+impl World {
+    // None if impassable for who; closed doors are passable at a cost if bumping them opens them
+    fn step_cost(&self, who:&Actor, frame:&r_Map, pt:[i32;2]) -> Option<i32> {
+        let loc = self.canonical_loc(Location::new(frame, pt))?;
+        if loc.is_passable_for(who) { return Some(STEP_COST); }
+        if !loc.get_terrain().walkable { return None; }
+        if self.opens_way(who, &loc).is_some() { return Some(DOOR_COST); }
+        return None;
+    }
+
+    // Dijkstra's algorithm, from seeds with initial costs
+    fn relax(&self, who:&Actor, frame:&r_Map, seeds:Vec<([i32;2],i32)>, allowed:&dyn Fn(&[i32;2]) -> bool, max_cost:i32) -> HashMap<[i32;2],i32> {
+        let mut dist = HashMap::<[i32;2],i32>::new();
+        let mut queue = BinaryHeap::new();
+        for (pt, cost) in seeds {
+            if dist.get(&pt).is_none_or(|x| cost < *x) {
+                dist.insert(pt, cost);
+                queue.push(Reverse((cost, pt)));
+            }
+        }
+        while let Some(Reverse((cost, pt))) = queue.pop() {
+            if dist[&pt] < cost { continue; }   // stale entry
+            for dir in neighbors() {
+                let mut next = pt;
+                next += dir;
+                if !allowed(&next) { continue; }
+                if let Some(step) = self.step_cost(who, frame, next) {
+                    let next_cost = cost.saturating_add(step);
+                    if max_cost < next_cost { continue; }
+                    if dist.get(&next).is_none_or(|x| next_cost < *x) {
+                        dist.insert(next, next_cost);
+                        queue.push(Reverse((next_cost, next)));
+                    }
+                }
+            }
+        }
+        return dist;
+    }
+
+    // sources must all be reachable from the first source's map (same map, or stitched to it)
+    pub fn dijkstra_map(&self, who:&Actor, sources:&[Location], max_cost:i32) -> Option<DijkstraMap> {
+        let frame = Rc::clone(&sources.first()?.map);
+        let mut seeds = Vec::<([i32;2],i32)>::with_capacity(sources.len());
+        for loc in sources {
+            if let Some(x) = self.coerce_map(loc.clone(), Rc::clone(&frame)) { seeds.push((x.pos, 0)); }
+        }
+        let dist = self.relax(who, &frame, seeds, &|_| true, max_cost);
+        return Some(DijkstraMap{map:frame, dist});
+    }
+
+    // best adjacent location that is strictly downhill from from; None if already at a local minimum
+    pub fn downhill(&self, src:&DijkstraMap, from:&Location) -> Option<Location> {
+        let pos = self.coerce_map(from.clone(), src.origin())?.pos;
+        let mut best = src.get(&pos).unwrap_or(i32::MAX);
+        let mut ret:Option<Location> = None;
+        for dir in neighbors() {
+            let mut next = pos;
+            next += dir;
+            if let Some(cost) = src.get(&next) {
+                if cost < best {
+                    best = cost;
+                    ret = self.canonical_loc(Location::new(&src.map, next));
+                }
+            }
+        }
+        return ret;
+    }
+
+    // A*; the path excludes from and ends at to.  Actors in the way are ignored (they move).
+    pub fn find_path(&self, who:&Actor, from:&Location, to:&Location) -> Option<Vec<Location>> {
+        let frame = Rc::clone(&from.map);
+        let goal = self.coerce_map(to.clone(), Rc::clone(&frame))?.pos;
+        let start = from.pos;
+        if start == goal { return Some(Vec::new()); }
+
+        let mut cost_so_far = HashMap::<[i32;2],i32>::new();
+        let mut came_from = HashMap::<[i32;2],[i32;2]>::new();
+        let mut queue = BinaryHeap::new();
+        cost_so_far.insert(start, 0);
+        queue.push(Reverse((chebyshev(start, goal), 0, start)));
+        while let Some(Reverse((_, cost, pt))) = queue.pop() {
+            if pt == goal {
+                let mut ret = Vec::<Location>::new();
+                let mut cur = goal;
+                while cur != start {
+                    ret.push(self.canonical_loc(Location::new(&frame, cur)).unwrap());
+                    cur = came_from[&cur];
+                }
+                ret.reverse();
+                return Some(ret);
+            }
+            if cost_so_far[&pt] < cost { continue; }    // stale entry
+            for dir in neighbors() {
+                let mut next = pt;
+                next += dir;
+                if let Some(step) = self.step_cost(who, &frame, next) {
+                    let next_cost = cost + step;
+                    if cost_so_far.get(&next).is_none_or(|x| next_cost < *x) {
+                        cost_so_far.insert(next, next_cost);
+                        came_from.insert(next, pt);
+                        queue.push(Reverse((next_cost + chebyshev(next, goal), next_cost, next)));
+                    }
+                }
+            }
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a wall down x = 3 with a gap at the bottom, where the door is if there is one
+    fn walled(door:Option<&str>) -> (World, r_Actor, r_Map) {
+        let mut w = World::new();
+        w.load_content("data/content.txt").unwrap();
+        let floor = w.get_terrain("stone floor").unwrap();
+        let wall = w.get_terrain("wall").unwrap();
+        let r_m = w.new_map("test", [7,5], floor);
+        for y in 0..4 { r_m.borrow_mut().set_terrain([3,y], Rc::clone(&wall)); }
+        if let Some(name) = door { Location::new(&r_m, [3,4]).set_map_object(w.get_map_object_model(name).unwrap()); }
+        let model = w.new_actor_model(ActorModel::new("m", Ok(CharSpec{img:'m', c:None})));
+        let r_act = w.new_actor(model, &Location::new(&r_m, [0,0]), [1,1]).unwrap();
+        return (w, r_act, r_m);
+    }
+
+    #[test]
+    fn paths_go_around_walls_and_through_doors() {
+        for door in [None, Some("door (closed)")].iter() {
+            let (w, r_act, r_m) = walled(*door);
+            let path = w.find_path(&r_act.borrow(), &r_act.borrow().loc(), &Location::new(&r_m, [5,1])).unwrap();
+            assert_eq!(6, path.len());
+            assert!(path.iter().any(|x| [3,4] == x.pos));
+            assert_eq!([5,1], path.last().unwrap().pos);
+            let mut prev = [1,1];
+            for x in &path {
+                assert_eq!(1, chebyshev(prev, x.pos));
+                prev = x.pos;
+            }
+        }
+        let (w, r_act, r_m) = walled(Some("door (locked)"));
+        assert!(w.find_path(&r_act.borrow(), &r_act.borrow().loc(), &Location::new(&r_m, [5,1])).is_none());
+        assert_eq!(Some(Vec::new()), w.find_path(&r_act.borrow(), &r_act.borrow().loc(), &r_act.borrow().loc()).map(|x| x.iter().map(|l| l.pos).collect::<Vec<_>>()));
+    }
+
+    #[test]
+    fn dijkstra_maps_count_steps_and_doors() {
+        let (w, r_act, r_m) = walled(Some("door (closed)"));
+        let sources = [Location::new(&r_m, [1,1]), Location::new(&r_m, [1,3])];
+        let d = w.dijkstra_map(&r_act.borrow(), &sources, i32::MAX).unwrap();
+        assert_eq!(Some(0), d.get(&[1,3]));
+        assert_eq!(Some(1), d.get(&[2,2]));
+        assert_eq!(Some(1+DOOR_COST), d.get(&[3,4]));    // from [1,3], via [2,4] or [2,3]
+        assert_eq!(Some(2+DOOR_COST), d.get(&[4,3]));
+        assert_eq!(None, d.get(&[3,0]));
+        let near = w.dijkstra_map(&r_act.borrow(), &sources, 2).unwrap();
+        assert_eq!(Some(1), near.get(&[2,4]));
+        assert_eq!(None, near.get(&[3,4]));
+
+        // rolling downhill reaches a source; fleeing moves away
+        let mut here = Location::new(&r_m, [6,0]);
+        let mut steps = 0;
+        while let Some(next) = w.downhill(&d, &here) {
+            assert!(d.get(&next.pos).unwrap() < d.get(&here.pos).unwrap());
+            here = next;
+            steps += 1;
+        }
+        assert_eq!(Some(0), d.get(&here.pos));
+        assert_eq!(6, steps);    // diagonally down to the door, then through it to [1,3]
+        let flee = d.flee(&w, &r_act.borrow());
+        let away = w.downhill(&flee, &Location::new(&r_m, [4,3])).unwrap();
+        assert!(d.get(&away.pos).unwrap() > 4);
+    }
+}
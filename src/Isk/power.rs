@@ -0,0 +1,94 @@
+use crate::isk::*;
+use crate::isk::gps::*;
+
+// mechanical power, recomputed once per round after water:
+// * sources are terrain with power set (a water wheel) that a current carried water out of this round
+// * power passes to cardinally adjacent map objects that transmit it (shafts, gears), and on from them
+// * machinery (an object with a powered counterpart) next to anything powered runs; machinery that is not, stops
+
+impl World {
+    // the running model for idle machinery
+    pub fn powered_model(&self, src:&r_MapObjectModel) -> Option<r_MapObjectModel> {
+        return self.obj_powered.iter().find(|x| Rc::ptr_eq(src, &x[0])).map(|x| Rc::clone(&x[1]));
+    }
+
+    // the idle model for running machinery
+    pub fn unpowered_model(&self, src:&r_MapObjectModel) -> Option<r_MapObjectModel> {
+        return self.obj_powered.iter().find(|x| Rc::ptr_eq(src, &x[1])).map(|x| Rc::clone(&x[0]));
+    }
+
+    fn is_machinery(&self, src:&r_MapObjectModel) -> bool {
+        return self.obj_powered.iter().any(|x| Rc::ptr_eq(src, &x[0]) || Rc::ptr_eq(src, &x[1]));
+    }
+
+    pub fn power_turn(&mut self) {
+        for r_m in &self.atlas {
+            let mut m = r_m.borrow_mut();
+            let mut powered: Vec<[i32;2]> = m.flowing().iter().filter(|pt| m.get_terrain(**pt).power).cloned().collect();
+            let mut frontier = powered.clone();
+            while let Some(pt) = frontier.pop() {
+                for dir in CARDINALS.iter() {
+                    let mut dest = pt;
+                    dest += dir.clone();
+                    if !m.in_bounds(dest) || powered.contains(&dest) { continue; }
+                    let model = match m.get_map_object(dest) {
+                        Some(obj) => Rc::clone(&obj.borrow().model),
+                        None => continue
+                    };
+                    if model.transmits {
+                        powered.push(dest);
+                        frontier.push(dest);
+                    } else if self.is_machinery(&model) { powered.push(dest); }
+                }
+            }
+            for obj in m.map_objects() {
+                let (model, pos) = {
+                    let o = obj.borrow();
+                    (Rc::clone(&o.model), o.loc().pos)
+                };
+                let next = if powered.contains(&pos) { self.powered_model(&model) } else { self.unpowered_model(&model) };
+                if let Some(x) = next { m.set_map_object(Rc::new(RefCell::new(MapObject::new(x, Location::new(r_m, pos))))); }
+            }
+            m.set_powered(powered);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_runs_along_shafts_only_while_the_wheel_turns() {
+        let mut w = World::new();
+        w.load_content("data/content.txt").unwrap();
+        let floor = w.get_terrain("stone floor").unwrap();
+        let r_m = w.new_map("test", [6,2], floor);
+        let place = |w:&World, pt:[i32;2], name:&str| {
+            let obj = MapObject::new(w.get_map_object_model(name).unwrap(), Location::new(&r_m, pt));
+            r_m.borrow_mut().set_map_object(Rc::new(RefCell::new(obj)));
+        };
+        r_m.borrow_mut().set_terrain([0,0], w.get_terrain("water wheel in floor").unwrap());
+        place(&w, [1,0], "shaft");
+        place(&w, [2,0], "shaft");
+        place(&w, [3,0], "millstone");
+        place(&w, [4,0], "trip-hammer");    // machinery doesn't pass power on
+        place(&w, [2,1], "gate winch");
+        let model_at = |x:i32, y:i32| Rc::clone(&r_m.borrow().get_map_object([x,y]).unwrap().borrow().model);
+
+        w.power_turn();
+        assert!(model_at(3,0).is_named("millstone"));
+        r_m.borrow_mut().set_flowing(vec![[0,0]]);
+        w.power_turn();
+        assert!(model_at(3,0).is_named("millstone (grinding)"));
+        assert!(model_at(2,1).is_named("gate winch (winding)"));
+        assert!(model_at(4,0).is_named("trip-hammer"));
+        assert!(r_m.borrow().is_powered([2,0]) && !r_m.borrow().is_powered([4,0]));
+
+        r_m.borrow_mut().set_flowing(Vec::new());
+        w.power_turn();
+        assert!(model_at(3,0).is_named("millstone"));
+        assert!(model_at(2,1).is_named("gate winch"));
+        assert!(!r_m.borrow().is_powered([2,0]));
+    }
+}
@@ -0,0 +1,322 @@
+use crate::isk::*;
+use crate::isk::gps::*;
+use crate::isk::keys::{direction_from_name,direction_name};
+
+// prefab files: plain text.  Each prefab starts with a [prefab] header line.  Lines starting with # are comments.
+// * name = (unique)
+// * legend c = terrain, or legend c = terrain + object: what the single character c stands for.  Names are from the
+//   content file.  A cell without an object clears any object already there.  Directional terrain (flow or conveyor)
+//   lists its turned counterparts after it, comma-separated (legend | = stone culvert, stone culvert (e), ...), so
+//   the prefab can be drawn turned or mirrored; the first is the one drawn.
+// * anchor dir = x,y: a named point (dir a direction name: n, ne, ... nw), in the prefab's own coordinates
+// * picture rows, in order: : then the rest of the line, untrimmed.  Spaces leave the map alone; short rows are padded
+//   with them.
+
+#[derive(Clone)]
+pub struct Prefab {
+    name: String,
+    dim: [usize;2],
+    cells: Vec<char>,   // row-major
+    legend: Vec<(char,Vec<r_Terrain>,Option<r_MapObjectModel>)>,  // terrain to draw, then its counterparts
+    anchors: Vec<(Compass,[i32;2])>
+}
+
+impl Prefab {
+    // cells are row-major, already padded to the width; not validated
+    pub fn new(_name:&str, dim:[usize;2], cells:Vec<char>, legend:Vec<(char,Vec<r_Terrain>,Option<r_MapObjectModel>)>, anchors:Vec<(Compass,[i32;2])>) -> Prefab {
+        debug_assert!(dim[0]*dim[1] == cells.len());
+        return Prefab{name:_name.to_string(), dim, cells, legend, anchors};
+    }
+
+    pub fn named(&self) -> String { return self.name.clone(); }
+    pub fn is_named(&self, _name:&str) -> bool { return self.name == _name; }
+    pub fn width(&self) -> usize { return self.dim[0]; }
+    pub fn height(&self) -> usize { return self.dim[1]; }
+    pub fn row(&self, y:usize) -> &[char] { return &self.cells[y*self.dim[0]..(y+1)*self.dim[0]]; }
+    pub fn legend(&self) -> &[(char,Vec<r_Terrain>,Option<r_MapObjectModel>)] { return &self.legend; }
+    pub fn anchors(&self) -> &[(Compass,[i32;2])] { return &self.anchors; }
+
+    pub fn anchor(&self, dir:Compass) -> Option<[i32;2]> {
+        return self.anchors.iter().find(|x| x.0 == dir).map(|x| x.1);
+    }
+
+    fn cell(&self, x:usize, y:usize) -> char { return self.cells[x+y*self.dim[0]]; }
+
+    // a quarter turn clockwise; anchors turn with it (the n anchor is now the e anchor)
+    pub fn rotated(&self) -> Prefab {
+        let h = self.dim[1];
+        let mut cells = Vec::<char>::with_capacity(self.cells.len());
+        for y in 0..self.dim[0] {
+            for x in 0..h { cells.push(self.cell(y, h-1-x)); }
+        }
+        let h_i32 = i32::try_from(h).unwrap();
+        let anchors = self.anchors.iter().map(|x| (x.0.turned(2), [h_i32-1-x.1[1], x.1[0]])).collect();
+        let legend = self.legend.iter().map(|l| (l.0, reoriented(&l.1, |d| d.turned(2)), l.2.clone())).collect();
+        return Prefab{name:self.name.clone(), dim:[h, self.dim[0]], cells, legend, anchors};
+    }
+
+    // east-west
+    pub fn mirrored(&self) -> Prefab {
+        let w = self.dim[0];
+        let mut cells = Vec::<char>::with_capacity(self.cells.len());
+        for y in 0..self.dim[1] {
+            for x in 0..w { cells.push(self.cell(w-1-x, y)); }
+        }
+        let w_i32 = i32::try_from(w).unwrap();
+        let anchors = self.anchors.iter().map(|x| (x.0.mirrored(), [w_i32-1-x.1[0], x.1[1]])).collect();
+        let legend = self.legend.iter().map(|l| (l.0, reoriented(&l.1, |d| d.mirrored()), l.2.clone())).collect();
+        return Prefab{name:self.name.clone(), dim:self.dim, cells, legend, anchors};
+    }
+
+    // the anchor lands on dest.  Nothing is changed unless the whole prefab fits on the map.
+    pub fn stamp(&self, dest:&Location, anchor:Compass) -> Result<(),Error> {
+        let origin = match self.anchor(anchor.clone()) {
+            Some(pt) => [dest.pos[0]-pt[0], dest.pos[1]-pt[1]],
+            None => return Err(Error{desc:format!("prefab {} has no {} anchor", self.name, direction_name(anchor))})
+        };
+        let mut todo = Vec::<([i32;2],&(char,Vec<r_Terrain>,Option<r_MapObjectModel>))>::new();
+        {
+        let m = dest.map.borrow();
+        for y in 0..self.dim[1] {
+            for x in 0..self.dim[0] {
+                let c = self.cell(x, y);
+                if ' ' == c { continue; }
+                let pt = [origin[0]+i32::try_from(x).unwrap(), origin[1]+i32::try_from(y).unwrap()];
+                if !m.in_bounds(pt) { return Err(Error{desc:format!("prefab {} does not fit on map", self.name)}); }
+                todo.push((pt, self.legend.iter().find(|l| l.0 == c).unwrap()));
+            }
+        }
+        }
+        let mut m = dest.map.borrow_mut();
+        for (pt, l) in todo {
+            m.set_terrain(pt, Rc::clone(&l.1[0]));
+            match &l.2 {
+                Some(model) => { m.set_map_object(Rc::new(RefCell::new(MapObject::new(Rc::clone(model), Location::new(&dest.map, pt))))); },
+                None => { m.remove_map_object(pt); }
+            }
+        }
+        return Ok(());
+    }
+}
+
+// where in src is t, pointed the way f turns it
+fn counterpart(src:&[r_Terrain], t:&Terrain, f:fn(&Compass)->Compass) -> Option<usize> {
+    return src.iter().position(|x| x.flow == t.flow.as_ref().map(f) && x.conveyor == t.conveyor.as_ref().map(f));
+}
+
+// the counterpart to draw once the picture is turned by f goes first
+fn reoriented(src:&[r_Terrain], f:fn(&Compass)->Compass) -> Vec<r_Terrain> {
+    let mut ret = src.to_vec();
+    if let Some(n) = counterpart(src, &src[0], f) { ret.swap(0, n); }
+    return ret;
+}
+
+impl World {
+    pub fn load_prefabs(&mut self, path:&str) -> Result<(),Error> {
+        match std::fs::read_to_string(path) {
+            Ok(src) => return self.parse_prefabs(path, &src),
+            Err(e) => return Err(Error{desc:path.to_string()+": "+&e.to_string()})
+        }
+    }
+
+    // path is only used for error messages.  Nothing is registered unless the whole file is valid.
+    pub fn parse_prefabs(&mut self, path:&str, src:&str) -> Result<(),Error> {
+        let err = |line_no:usize, msg:&str| Error{desc:format!("{}:{}: [prefab] {}", path, line_no, msg)};
+        let mut ret = Vec::<Prefab>::new();
+        let mut start = 0;  // line number of the [prefab] being read; 0 before the first
+        let mut name: Option<String> = None;
+        let mut rows = Vec::<(Vec<char>,usize)>::new();
+        let mut legend = Vec::<(char,Vec<r_Terrain>,Option<r_MapObjectModel>)>::new();
+        let mut anchors = Vec::<(Compass,[i32;2])>::new();
+        // a trailing pseudo-header finishes the last prefab
+        for (n, raw) in src.lines().chain(std::iter::once("[prefab]")).enumerate() {
+            let line_no = n+1;
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            if "[prefab]" == line {
+                if 0 < start {
+                    let prefab_name = match name.take() {
+                        Some(x) => x,
+                        None => return Err(err(start, "missing name"))
+                    };
+                    if self.get_prefab(&prefab_name).is_some() || ret.iter().any(|p| p.is_named(&prefab_name)) {
+                        return Err(err(start, &("duplicate prefab ".to_string()+&prefab_name)));
+                    }
+                    if rows.is_empty() { return Err(err(start, "missing picture")); }
+                    let w = rows.iter().map(|r| r.0.len()).max().unwrap();
+                    let mut cells = Vec::<char>::with_capacity(w*rows.len());
+                    for (row, row_line_no) in &rows {
+                        for c in row {
+                            if ' ' != *c && !legend.iter().any(|l| l.0 == *c) {
+                                return Err(err(*row_line_no, &format!("{} is not in the legend", c)));
+                            }
+                            cells.push(*c);
+                        }
+                        cells.resize(cells.len()+w-row.len(), ' ');
+                    }
+                    let dim = [w, rows.len()];
+                    for x in &anchors {
+                        if !(0 <= x.1[0] && usize::try_from(x.1[0]).unwrap() < dim[0] && 0 <= x.1[1] && usize::try_from(x.1[1]).unwrap() < dim[1]) {
+                            return Err(err(start, "anchor outside of the picture"));
+                        }
+                    }
+                    ret.push(Prefab{name:prefab_name, dim, cells, legend:std::mem::take(&mut legend), anchors:std::mem::take(&mut anchors)});
+                    rows.clear();
+                }
+                start = line_no;
+                continue;
+            }
+            if 0 == start { return Err(Error{desc:format!("{}:{}: expected [prefab]", path, line_no)}); }
+            if let Some(row) = raw.trim_start().strip_prefix(':') {
+                rows.push((row.chars().collect(), line_no));
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("legend ") {
+                // the legend character may itself be =
+                let c = rest.chars().next().unwrap();
+                let val = match rest[c.len_utf8()..].trim_start().strip_prefix('=') {
+                    Some(x) => x.trim(),
+                    None => return Err(err(line_no, "expected legend c = terrain"))
+                };
+                if ' ' == c { return Err(err(line_no, "space is reserved for cells left alone")); }
+                if legend.iter().any(|l| l.0 == c) { return Err(err(line_no, &format!("duplicate legend {}", c))); }
+                let names: Vec<&str> = val.splitn(2, " + ").map(|x| x.trim()).collect();
+                let mut terrain = Vec::<r_Terrain>::new();
+                for t_name in names[0].split(',').map(|x| x.trim()) {
+                    match self.get_terrain(t_name) {
+                        Some(x) => terrain.push(x),
+                        None => return Err(err(line_no, &("unknown terrain ".to_string()+t_name)))
+                    }
+                }
+                if 1 < terrain.len() && terrain.iter().any(|t| t.flow.is_none() && t.conveyor.is_none()) {
+                    return Err(err(line_no, "only directional terrain has counterparts"));
+                }
+                for t in &terrain {
+                    if counterpart(&terrain, t, |d| d.turned(2)).is_none() || counterpart(&terrain, t, |d| d.mirrored()).is_none() {
+                        return Err(err(line_no, &format!("{} needs its counterparts turned and mirrored", t.name)));
+                    }
+                }
+                let obj = match names.get(1) {
+                    Some(obj_name) => match self.get_map_object_model(obj_name) {
+                        Some(x) => Some(x),
+                        None => return Err(err(line_no, &("unknown object ".to_string()+obj_name)))
+                    },
+                    None => None
+                };
+                legend.push((c, terrain, obj));
+                continue;
+            }
+            let kv: Vec<&str> = line.splitn(2, '=').collect();
+            if 2 != kv.len() { return Err(err(line_no, "expected key = value")); }
+            let key = kv[0].trim();
+            let val = kv[1].trim();
+            if "name" == key {
+                if name.is_some() { return Err(err(line_no, "duplicate key name")); }
+                name = Some(val.to_string());
+            } else if let Some(dir_name) = key.strip_prefix("anchor ") {
+                let dir = match direction_from_name(dir_name.trim()) {
+                    Some(x) => x,
+                    None => return Err(err(line_no, &("unknown direction ".to_string()+dir_name)))
+                };
+                if anchors.iter().any(|x| x.0 == dir) { return Err(err(line_no, &("duplicate anchor ".to_string()+dir_name))); }
+                let xy: Vec<Result<i32,_>> = val.split(',').map(|x| x.trim().parse::<i32>()).collect();
+                match xy.as_slice() {
+                    [Ok(x), Ok(y)] => anchors.push((dir, [*x, *y])),
+                    _ => return Err(err(line_no, &("anchor should be x,y: ".to_string()+val)))
+                }
+            } else { return Err(err(line_no, &("unknown key ".to_string()+key))); }
+        }
+        self.prefabs.extend(ret);
+        return Ok(());
+    }
+
+    pub fn get_prefab(&self, _name:&str) -> Option<&Prefab> {
+        return self.prefabs.iter().find(|p| p.is_named(_name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loaded() -> World {
+        let mut w = World::new();
+        w.load_content("data/content.txt").unwrap();
+        w.load_prefabs("data/prefabs.txt").unwrap();
+        return w;
+    }
+
+    fn find(m:&Map, name:&str) -> Vec<[i32;2]> {
+        return (0..m.height_i32()).flat_map(|y| (0..m.width_i32()).map(move |x| [x,y])).filter(|pt| m.get_terrain(*pt).is_named(name)).collect();
+    }
+
+    #[test]
+    fn turning_and_mirroring_move_cells_and_anchors() {
+        let mut w = loaded();
+        w.parse_prefabs("test", "[prefab]\nname = L\nlegend # = wall\nanchor n = 1,0\nanchor w = 0,1\n:##\n:#\n").unwrap();
+        let l = w.get_prefab("L").unwrap();
+        let r = l.rotated();
+        assert_eq!([2,2], r.dim);
+        assert_eq!("##\n #", r.cells.chunks(2).map(|x| x.iter().collect::<String>()).collect::<Vec<_>>().join("\n"));
+        assert_eq!(Some([1,1]), r.anchor(Compass::E));
+        assert_eq!(Some([0,0]), r.anchor(Compass::N));
+        assert_eq!(None, r.anchor(Compass::W));
+        let m = l.mirrored();
+        assert_eq!("##\n #", m.cells.chunks(2).map(|x| x.iter().collect::<String>()).collect::<Vec<_>>().join("\n"));
+        assert_eq!(Some([0,0]), m.anchor(Compass::N));
+        assert_eq!(Some([1,1]), m.anchor(Compass::E));
+        let round = r.rotated().rotated().rotated();
+        assert_eq!(l.cells, round.cells);
+        assert!(l.anchors == round.anchors);
+    }
+
+    #[test]
+    fn directional_terrain_turns_with_the_picture() {
+        let mut w = loaded();
+        let floor = w.get_terrain("stone floor").unwrap();
+        let wheel = w.get_prefab("waterwheel").unwrap();
+        for (prefab, toward) in [(wheel.rotated(), Compass::E), (wheel.rotated().mirrored(), Compass::W), (wheel.rotated().rotated(), Compass::S)].iter() {
+            let r_m = w.new_map("test", [12,12], Rc::clone(&floor));
+            // the n anchor, at the wheel's sunken end, turns with the current
+            prefab.stamp(&Location::new(&r_m, [6,6]), toward.clone()).unwrap();
+            let m = r_m.borrow();
+            // each culvert cell carries the water on toward the wheel, which turns the same way
+            let mut culverts = find(&m, "stone culvert");
+            for d in ["e", "s", "w"].iter() { culverts.extend(find(&m, &format!("stone culvert ({})", d))); }
+            assert_eq!(3, culverts.len());
+            for pt in &culverts { assert!(Some(toward.clone()) == m.get_terrain(*pt).flow); }
+            let mut wheels = find(&m, "water wheel in floor");
+            for d in ["e", "s", "w"].iter() { wheels.extend(find(&m, &format!("water wheel in floor ({})", d))); }
+            assert_eq!(1, wheels.len());
+            assert!(Some(toward.clone()) == m.get_terrain(wheels[0]).flow);
+            assert!(Some(toward.clone()) == m.get_terrain(wheels[0]).conveyor);
+            let mut pt = culverts[0];
+            while pt != wheels[0] {
+                assert!(culverts.contains(&pt));
+                pt += toward.clone();
+            }
+        }
+    }
+
+    #[test]
+    fn directional_terrain_needs_its_counterparts() {
+        let mut w = loaded();
+        let err = w.parse_prefabs("test", "[prefab]\nname = a\nlegend | = stone culvert\n:|\n").unwrap_err();
+        assert!(err.desc.contains("test:3:"));
+        assert!(w.parse_prefabs("test", "[prefab]\nname = a\nlegend | = stone culvert, stone culvert (e), stone culvert (s)\n:|\n").is_err());
+        assert!(w.parse_prefabs("test", "[prefab]\nname = a\nlegend | = stone culvert, stone floor\n:|\n").is_err());
+        assert!(w.parse_prefabs("test", "[prefab]\nname = a\nlegend | = stone culvert, nothing\n:|\n").is_err());
+        assert!(w.get_prefab("a").is_none());
+    }
+
+    #[test]
+    fn the_waterwheel_leaves_the_north_wall_whole() {
+        let mut w = loaded();
+        let pc = w.new_game();
+        let r_m = Rc::clone(&pc.borrow().loc().map);
+        let m = r_m.borrow();
+        let sunk = find(&m, "water wheel in floor (sunken)");
+        assert_eq!(1, sunk.len());
+        assert!(!m.get_terrain([sunk[0][0], sunk[0][1]-1]).walkable);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::isk::*;
+use crate::isk::ai::*;
+use crate::isk::gps::*;
+use crate::isk::keys::*;
+use crate::isk::messages::*;
+use crate::isk::transition::*;
+use tcod::input::KeyCode;
+
+pub const HIGHLIGHT_BG: colors::Color = colors::DARKER_YELLOW;
+
+// a verb waiting on its target: one of the choices, picked by direction from the PC
+pub struct DirectionPrompt {
+    pub choices: Vec<Location>,
+    pub verb: Verb
+}
+
+impl World {
+    // the PC's verbs: the prompt says why, if the action does not go through
+    pub fn use_pc(&mut self, r_pc:&r_Actor, dest:Location, verb:Verb) {
+        let checked = self.check_transition(&r_pc.borrow(), &dest, verb);
+        match checked {
+            Ok(_) => { self.perform(r_pc, Action::Use(dest, verb)); },
+            Err(e) => get_messages_cache_mut().get_mut(Rc::clone(r_pc)).set_prompt(&e.desc)
+        }
+    }
+
+    // pushes event_direction_pc; the choices are highlighted until it pops
+    pub fn prompt_direction(&mut self, r_pc:&r_Actor, src:DirectionPrompt, prompt:&str) {
+        get_messages_cache_mut().get_mut(Rc::clone(r_pc)).set_prompt(&(prompt.to_string()+" (direction, Esc to cancel)"));
+        self.direction_prompt = Some(src);
+        self.add_handler(event_direction_pc);
+    }
+}
+
+impl World {
+    // for the look command: whatever is there, top to bottom
+    pub fn describe_loc(&self, loc:&Location) -> String {
+        let m = loc.map.borrow();
+        let mut ret = Vec::<String>::new();
+        if let Some(act) = m.get_actor(loc.pos) { ret.push(act.borrow().model.name.clone()); }
+        if let Some(obj) = m.get_map_object(loc.pos) {
+            let o = obj.borrow();
+            if o.model.transmits && m.is_powered(loc.pos) { ret.push(o.model.name.clone()+" (turning)"); }
+            else { ret.push(o.model.name.clone()); }
+        }
+        if let Some(pile) = m.get_items(loc.pos) {
+            if let Some(item) = pile.top() {
+                if 1 < pile.len() { ret.push(item.describe()+" and more"); }
+                else { ret.push(item.describe()); }
+            }
+        }
+        let depth = m.get_water(loc.pos);
+        if 0 < depth { ret.push(format!("water ({} deep)", depth)); }
+        let t = m.get_terrain(loc.pos);
+        if t.power && m.is_flowing(loc.pos) { ret.push(t.name.clone()+" (turning)"); }
+        else { ret.push(t.name.clone()); }
+        return ret.join(", ");
+    }
+}
+
+// modal: pushed by the look command.  Takes no time.
+pub fn event_look_pc(key:Key, _dm: &mut DisplayManager, w:&mut World, r_pc:r_Actor) -> bool {
+    if let KeyCode::Escape = key.code {
+        get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).clear_prompt();
+        return true;
+    }
+    let here = r_pc.borrow().loc();
+    let (dest, label) = match w.keymap().command(key) {
+        Some(Command::Move(dir)) => (w.canonical_loc(here+dir.clone()), direction_name(dir)),
+        Some(Command::Wait) => (Some(here), "here"),
+        _ => return false
+    };
+    let mut msgs = get_messages_cache_mut();
+    let pc_msgs = msgs.get_mut(Rc::clone(&r_pc));
+    pc_msgs.clear_prompt();
+    match dest {
+        Some(loc) => pc_msgs.add_message(&(label.to_string()+": "+&w.describe_loc(&loc))),
+        None => pc_msgs.add_message(&(label.to_string()+": nothing"))
+    }
+    return true;
+}
+
+// modal.  Returning true pops it.
+pub fn event_direction_pc(key:Key, _dm: &mut DisplayManager, w:&mut World, r_pc:r_Actor) -> bool {
+    if let KeyCode::Escape = key.code {
+        get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).clear_prompt();
+        w.direction_prompt = None;
+        return true;
+    }
+    let dir = match w.keymap().command(key) {   // whatever keys move
+        Some(Command::Move(x)) => x,
+        _ => return false
+    };
+    let dest = w.canonical_loc(r_pc.borrow().loc()+dir);
+    let chosen = match (dest, &w.direction_prompt) {
+        (Some(loc), Some(pr)) if pr.choices.contains(&loc) => loc,
+        _ => {
+            get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt("not that way (direction, Esc to cancel)");
+            return false;
+        }
+    };
+    let pr = w.direction_prompt.take().unwrap();
+    get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).clear_prompt();
+    w.use_pc(&r_pc, chosen, pr.verb);
+    return true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code:KeyCode) -> Key {
+        let mut ret = Key::default();
+        ret.code = code;
+        return ret;
+    }
+
+    // the PC between two closed doors, asked which to open
+    fn doors() -> (World, DisplayManager, r_Actor, Location, Location) {
+        let mut w = World::new();
+        w.load_content("data/content.txt").unwrap();
+        let floor = w.get_terrain("stone floor").unwrap();
+        let r_m = w.new_map("test", [5,5], floor);
+        let model = w.new_actor_model(ActorModel::new("pc", Ok(CharSpec{img:'@', c:None})));
+        let r_pc = w.new_actor(model, &Location::new(&r_m, [0,0]), [2,2]).unwrap();
+        r_pc.borrow_mut().is_pc = true;
+        let east = Location::new(&r_m, [3,2]);
+        let west = Location::new(&r_m, [1,2]);
+        for loc in [&east, &west].iter() { loc.set_map_object(w.get_map_object_model("door (closed)").unwrap()); }
+        w.prompt_direction(&r_pc, DirectionPrompt{choices:vec![east.clone(), west.clone()], verb:Verb::Open}, "open which?");
+        return (w, DisplayManager::new_headless(), r_pc, east, west);
+    }
+
+    fn door(loc:&Location) -> String { return loc.get_map_object().unwrap().borrow().model.name.clone(); }
+
+    fn prompt(r_pc:&r_Actor) -> Option<String> { return get_messages_cache_mut().get(Rc::clone(r_pc)).prompt(); }
+
+    fn highlighted(w:&World, dm:&mut DisplayManager, r_pc:&r_Actor) -> usize {
+        let loc = r_pc.borrow().loc();
+        dm.clear();
+        w.draw(dm, loc, r_pc);
+        dm.render();
+        let h = dm.headless().unwrap();
+        return (0..VIEW).flat_map(|y| (0..VIEW).map(move |x| [x, y])).filter(|x| h.cell(x).unwrap().bg == HIGHLIGHT_BG).count();
+    }
+
+    #[test]
+    fn a_direction_picks_the_target() {
+        let (mut w, mut dm, r_pc, east, west) = doors();
+        assert_eq!(Some("open which? (direction, Esc to cancel)".to_string()), prompt(&r_pc));
+        assert_eq!(2, highlighted(&w, &mut dm, &r_pc));
+        assert!(event_direction_pc(key(KeyCode::Right), &mut dm, &mut w, Rc::clone(&r_pc)));
+        assert_eq!("door (open)", door(&east));
+        assert_eq!("door (closed)", door(&west));
+        assert_eq!(None, prompt(&r_pc));
+        assert_eq!(0, highlighted(&w, &mut dm, &r_pc));
+    }
+
+    #[test]
+    fn escape_cancels() {
+        let (mut w, mut dm, r_pc, east, west) = doors();
+        assert!(event_direction_pc(key(KeyCode::Escape), &mut dm, &mut w, Rc::clone(&r_pc)));
+        assert_eq!("door (closed)", door(&east));
+        assert_eq!("door (closed)", door(&west));
+        assert_eq!(None, prompt(&r_pc));
+        assert_eq!(0, highlighted(&w, &mut dm, &r_pc));
+    }
+
+    #[test]
+    fn no_target_that_way_keeps_asking() {
+        let (mut w, mut dm, r_pc, east, west) = doors();
+        assert!(!event_direction_pc(key(KeyCode::Up), &mut dm, &mut w, Rc::clone(&r_pc)));
+        assert_eq!(Some("not that way (direction, Esc to cancel)".to_string()), prompt(&r_pc));
+        assert!(!event_direction_pc(key(KeyCode::Tab), &mut dm, &mut w, Rc::clone(&r_pc)));  // not a direction at all
+        assert_eq!(2, highlighted(&w, &mut dm, &r_pc));
+        assert!(event_direction_pc(key(KeyCode::Left), &mut dm, &mut w, Rc::clone(&r_pc)));
+        assert_eq!("door (closed)", door(&east));
+        assert_eq!("door (open)", door(&west));
+        assert_eq!(0, highlighted(&w, &mut dm, &r_pc));
+    }
+}
@@ -0,0 +1,295 @@
+use crate::isk::*;
+use crate::isk::keys::*;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter,Write};
+use tcod::input::KeyCode;
+
+// replay files: line-oriented text, tab-separated fields, like savefiles
+// * header: magic, version
+// * seed: the seed of the new game the session started from
+// * key: scheduler round and random steps when the key was read (for detecting divergence), key code name (Char for
+//   printable keys), printable as a number, alt, ctrl, shift
+// Every record is flushed as it is written, so a crash leaves a usable file.
+const REPLAY_VERSION:u32 = 1;
+const REPLAY_MAGIC:&str = "MinGame-Rust2000 replay";
+
+fn bool_to_str(src:bool) -> &'static str { if src { return "1"; } else { return "0"; } }
+
+pub struct Recorder {
+    path: String,
+    dest: BufWriter<File>,
+    error: Option<Error>    // the write that failed; nothing more is recorded once set
+}
+
+impl Recorder {
+    pub fn create(path:&str, seed:u64) -> Result<Recorder,Error> {
+        let file = match File::create(path) {
+            Ok(f) => f,
+            Err(e) => return Err(Error{desc:path.to_string()+": "+&e.to_string()})
+        };
+        let mut ret = Recorder{path:path.to_string(), dest:BufWriter::new(file), error:None};
+        if let Err(e) = ret.write(&format!("{}\t{}\nseed\t{}", REPLAY_MAGIC, REPLAY_VERSION, seed)) {
+            return Err(Error{desc:path.to_string()+": "+&e.to_string()});
+        }
+        return Ok(ret);
+    }
+
+    fn write(&mut self, line:&str) -> std::io::Result<()> {
+        writeln!(self.dest, "{}", line)?;
+        return self.dest.flush();
+    }
+
+    fn record(&mut self, round:u64, steps:u64, key:Key) -> std::io::Result<()> {
+        let code = match key.code {
+            KeyCode::Char => "Char",
+            x => key_code_name(x).unwrap_or("-")    // NoKey, Text
+        };
+        return self.write(&format!("key\t{}\t{}\t{}\t{}\t{}\t{}\t{}", round, steps, code, u32::from(key.printable),
+            bool_to_str(key.alt), bool_to_str(key.ctrl), bool_to_str(key.shift)));
+    }
+}
+
+struct ReplayKey {
+    round: u64,
+    steps: u64,
+    key: Key
+}
+
+pub struct Replay {
+    seed: u64,
+    keys: VecDeque<ReplayKey>,
+    error: Option<Error>    // divergence from the recorded session; no more keys are fed once set
+}
+
+impl Replay {
+    pub fn load(path:&str) -> Result<Replay,Error> {
+        let src = match std::fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) => return Err(Error{desc:path.to_string()+": "+&e.to_string()})
+        };
+        let err = |line_no:usize, msg:&str| Error{desc:format!("{}:{}: {}", path, line_no, msg)};
+        let mut lines = src.lines().enumerate().filter(|x| !x.1.trim().is_empty());
+        let mut next = || lines.next().map(|x| (x.0+1, x.1.split('\t').collect::<Vec<&str>>()));
+
+        match next() {
+            Some((_, rec)) if 2 == rec.len() && REPLAY_MAGIC == rec[0] => {
+                if Ok(REPLAY_VERSION) != rec[1].parse::<u32>() { return Err(err(1, &("unsupported replay version ".to_string()+rec[1]))); }
+            },
+            _ => return Err(err(1, "not a replay file"))
+        }
+        let seed = match next() {
+            Some((line_no, rec)) => {
+                if 2 != rec.len() || "seed" != rec[0] { return Err(err(line_no, "expected seed")); }
+                match rec[1].parse::<u64>() {
+                    Ok(x) => x,
+                    _ => return Err(err(line_no, &("invalid seed: ".to_string()+rec[1])))
+                }
+            },
+            None => return Err(err(2, "expected seed"))
+        };
+        let mut keys = VecDeque::<ReplayKey>::new();
+        while let Some((line_no, rec)) = next() {
+            if 8 != rec.len() || "key" != rec[0] { return Err(err(line_no, "expected key record with 8 fields")); }
+            let mut key = Key::default();
+            key.code = match rec[3] {
+                "Char" => KeyCode::Char,
+                "-" => KeyCode::NoKey,
+                x => match key_code_from_name(x) {
+                    Some(code) => code,
+                    None => return Err(err(line_no, &("unknown key ".to_string()+x)))
+                }
+            };
+            let parsed = (rec[1].parse::<u64>(), rec[2].parse::<u64>(), rec[4].parse::<u32>().ok().and_then(std::char::from_u32));
+            let (round, steps) = match parsed {
+                (Ok(round), Ok(steps), Some(c)) => {
+                    key.printable = c;
+                    (round, steps)
+                },
+                _ => return Err(err(line_no, "invalid key record"))
+            };
+            key.alt = "1" == rec[5];
+            key.ctrl = "1" == rec[6];
+            key.shift = "1" == rec[7];
+            keys.push_back(ReplayKey{round, steps, key});
+        }
+        return Ok(Replay{seed, keys, error:None});
+    }
+
+    pub fn seed(&self) -> u64 { return self.seed; }
+}
+
+impl World {
+    // for a new game: the seed should already be set
+    pub fn set_recorder(&mut self, src:Recorder) { self.recorder = Some(src); }
+
+    // for a new game, after reseed(src.seed())
+    pub fn set_replay(&mut self, src:Replay) { self.replay = Some(src); }
+
+    pub fn recording_error(&self) -> Option<&Error> { return self.recorder.as_ref().and_then(|x| x.error.as_ref()); }
+
+    pub fn is_replaying(&self) -> bool { return self.replay.as_ref().is_some_and(|x| x.error.is_none() && !x.keys.is_empty()); }
+    pub fn replay_error(&self) -> Option<&Error> { return self.replay.as_ref().and_then(|x| x.error.as_ref()); }
+
+    // the recorded key while replaying, otherwise the display's.  The PC hears if recording fails.
+    pub fn next_key(&mut self, dm:&mut DisplayManager, r_pc:&r_Actor) -> Key {
+        use crate::isk::messages::*;
+
+        let round = self.sched.round();
+        let steps = self.rng.steps();
+        let mut key = None;
+        if let Some(replay) = self.replay.as_mut() {
+            if replay.error.is_none() {
+                if let Some(x) = replay.keys.pop_front() {
+                    if x.round != round || x.steps != steps {
+                        replay.error = Some(Error{desc:format!("replay diverged at round {} (recorded round {}), random steps {} (recorded {})",
+                            round, x.round, steps, x.steps)});
+                    } else { key = Some(x.key); }
+                }
+            }
+        }
+        let key = match key {
+            Some(x) => x,
+            None => dm.wait_for_keypress()
+        };
+        if let Some(rec) = self.recorder.as_mut() {
+            if rec.error.is_none() {
+                if let Err(e) = rec.record(round, steps, key) {     // the file so far is still usable
+                    let err = Error{desc:rec.path.clone()+": "+&e.to_string()};
+                    get_messages_cache_mut().get_mut(Rc::clone(r_pc)).add_message(&("Recording stopped: ".to_string()+&err.desc));
+                    rec.error = Some(err);
+                }
+            }
+        }
+        return key;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isk::gps::*;
+    use rand::Rng;
+
+    // hjkl moves, anything else waits
+    fn walker(key:Key, _dm:&mut DisplayManager, w:&mut World, r_pc:r_Actor) -> bool {
+        let dir = match key.printable {
+            'h' => Compass::W,
+            'j' => Compass::S,
+            'k' => Compass::N,
+            'l' => Compass::E,
+            _ => {
+                w.perform(&r_pc, Action::Wait);
+                return false;
+            }
+        };
+        let dest = w.canonical_loc(r_pc.borrow().loc()+dir);
+        if !dest.is_some_and(|x| w.perform(&r_pc, Action::Move(x))) { w.perform(&r_pc, Action::Wait); }
+        return false;
+    }
+
+    // as the main loop does it
+    fn play(w:&mut World, dm:&mut DisplayManager) {
+        while w.is_replaying() || !dm.window_closed() {
+            if let Some(act) = w.next_actor() {
+                if act.borrow().is_pc {
+                    if act.borrow().is_dead() { break; }
+                    w.exec_key(dm, act);
+                } else { w.npc_turn(&act); }
+            }
+        }
+    }
+
+    fn new_game(seed:u64) -> World {
+        let mut w = World::new();
+        w.reseed(seed);
+        w.add_handler(walker);
+        w.load_content("data/content.txt").unwrap();
+        w.load_prefabs("data/prefabs.txt").unwrap();
+        w.new_game();
+        return w;
+    }
+
+    fn saved(w:&World, name:&str) -> String {
+        let path = std::env::temp_dir().join(name);
+        w.save(path.to_str().unwrap()).unwrap();
+        let ret = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        return ret;
+    }
+
+    #[test]
+    fn a_replay_reproduces_the_session() {
+        let path = std::env::temp_dir().join("min_game_replay_test.txt");
+        let path = path.to_str().unwrap();
+        let mut w = new_game(11);
+        w.set_recorder(Recorder::create(path, w.seed()).unwrap());
+        let mut dm = DisplayManager::new_headless();
+        for c in "lljjjzhhkkzlj".chars() {
+            let mut key = Key::default();
+            key.code = KeyCode::Char;
+            key.printable = c;
+            dm.headless_mut().unwrap().push_key(key);
+        }
+        play(&mut w, &mut dm);
+        let recorded = saved(&w, "min_game_replay_a.txt");
+
+        let replay = Replay::load(path).unwrap();
+        assert_eq!(11, replay.seed());
+        let mut w2 = new_game(replay.seed());
+        w2.set_replay(replay);
+        assert!(w2.is_replaying());
+        play(&mut w2, &mut DisplayManager::new_headless());
+        assert!(w2.replay_error().is_none());
+        assert_eq!(recorded, saved(&w2, "min_game_replay_b.txt"));
+
+        // one random number more, and the replay stops at the first key
+        let mut w3 = new_game(11);
+        let _: u32 = w3.rng.gen();
+        w3.set_replay(Replay::load(path).unwrap());
+        play(&mut w3, &mut DisplayManager::new_headless());
+        assert!(w3.replay_error().is_some_and(|e| e.desc.starts_with("replay diverged")));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn a_failed_write_stops_recording_and_says_so() {
+        use crate::isk::messages::*;
+        let path = std::env::temp_dir().join("min_game_replay_ro.txt");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "").unwrap();
+        let mut w = new_game(3);
+        let read_only = File::open(path).unwrap();
+        w.set_recorder(Recorder{path:path.to_string(), dest:BufWriter::new(read_only), error:None});
+        assert!(w.recording_error().is_none());
+        let mut dm = DisplayManager::new_headless();
+        for c in "lh".chars() {
+            let mut key = Key::default();
+            key.code = KeyCode::Char;
+            key.printable = c;
+            dm.headless_mut().unwrap().push_key(key);
+        }
+        play(&mut w, &mut dm);
+        let err = w.recording_error().unwrap().desc.clone();
+        assert!(err.starts_with(&(path.to_string()+": ")));
+        let r_pc = w.atlas.iter().flat_map(|m| m.borrow().actors().to_vec()).find(|a| a.borrow().is_pc).unwrap();
+        let mut c = get_messages_cache_mut();
+        let msgs = c.get(r_pc);
+        let said: Vec<String> = (0..msgs.count()).filter_map(|n| msgs.message(n).map(|x| x.0.clone())).collect();
+        assert_eq!(1, said.iter().filter(|x| **x == "Recording stopped: ".to_string()+&err).count());    // once, not per key
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn replay_files_are_checked() {
+        let path = std::env::temp_dir().join("min_game_replay_bad.txt");
+        let path = path.to_str().unwrap();
+        for (src, line, msg) in [("nonsense\n", 1, "not a replay file"), ("MinGame-Rust2000 replay\t99\n", 1, "unsupported replay version 99"),
+            ("MinGame-Rust2000 replay\t1\nseed\tx\n", 2, "invalid seed: x"),
+            ("MinGame-Rust2000 replay\t1\nseed\t1\nkey\t0\t0\tBogus\t0\t0\t0\t0\n", 3, "unknown key Bogus")].iter() {
+            std::fs::write(path, src).unwrap();
+            assert_eq!(format!("{}:{}: {}", path, line, msg), Replay::load(path).err().unwrap().desc);
+        }
+        let _ = std::fs::remove_file(path);
+    }
+}
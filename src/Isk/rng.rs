@@ -0,0 +1,94 @@
+use rand_xoshiro::rand_core::{Error,RngCore,SeedableRng};
+use rand_xoshiro::Xoshiro256PlusPlus;
+use std::convert::TryFrom;
+use std::time::SystemTime;
+
+// the world's only source of randomness.  Counting steps of the underlying generator lets a savefile
+// record its state as (seed, steps) without depending on the generator's internals.
+pub struct GameRng {
+    seed: u64,
+    steps: u64,
+    rng: Xoshiro256PlusPlus
+}
+
+impl GameRng {
+    pub fn new(_seed:u64) -> GameRng { return GameRng{seed:_seed, steps:0, rng:Xoshiro256PlusPlus::seed_from_u64(_seed)}; }
+
+    // for when the player did not ask for a seed
+    pub fn time_seed() -> u64 {
+        let t = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        return t.as_secs() ^ u64::from(t.subsec_nanos());
+    }
+
+    pub fn restore(_seed:u64, _steps:u64) -> GameRng {
+        let mut ret = GameRng::new(_seed);
+        for _ in 0.._steps { ret.rng.next_u64(); }
+        ret.steps = _steps;
+        return ret;
+    }
+
+    pub fn seed(&self) -> u64 { return self.seed; }
+    pub fn steps(&self) -> u64 { return self.steps; }
+}
+
+// each call to the underlying generator is one step; fill_bytes takes one per 8 bytes or part thereof
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.steps += 1;
+        return self.rng.next_u32();
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.steps += 1;
+        return self.rng.next_u64();
+    }
+
+    fn fill_bytes(&mut self, dest:&mut [u8]) {
+        self.steps += u64::try_from(dest.len().div_ceil(8)).unwrap();
+        self.rng.fill_bytes(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest:&mut [u8]) -> Result<(),Error> {
+        self.fill_bytes(dest);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isk::*;
+    use rand::Rng;
+
+    #[test]
+    fn restoring_picks_up_where_the_steps_left_off() {
+        let mut a = GameRng::new(42);
+        let _: u32 = a.gen();
+        let mut buf = [0u8; 9];
+        a.fill_bytes(&mut buf);
+        assert_eq!(3, a.steps());
+        let mut b = GameRng::restore(a.seed(), a.steps());
+        for _ in 0..10 { assert_eq!(a.next_u64(), b.next_u64()); }
+        assert_eq!(a.steps(), b.steps());
+    }
+
+    fn new_game(seed:u64, path:&str) -> String {
+        let mut w = World::new();
+        w.reseed(seed);
+        w.load_content("data/content.txt").unwrap();
+        w.load_prefabs("data/prefabs.txt").unwrap();
+        w.new_game();
+        let path = std::env::temp_dir().join(path);
+        w.save(path.to_str().unwrap()).unwrap();
+        let ret = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        return ret;
+    }
+
+    #[test]
+    fn a_seed_reproduces_the_game() {
+        let a = new_game(7, "min_game_seed_a.txt");
+        assert_eq!(a, new_game(7, "min_game_seed_b.txt"));
+        assert_ne!(a, new_game(8, "min_game_seed_c.txt"));
+    }
+}
@@ -0,0 +1,904 @@
+use crate::isk::*;
+use crate::isk::fluid::MAX_WATER_DEPTH;
+use crate::isk::messages::*;
+use crate::isk::transition::*;
+use std::fs::File;
+use std::io::{BufRead,BufReader,BufWriter,Write};
+use std::str::FromStr;
+
+// savefile layout: line-oriented text, one record per line, tab-separated fields; the first field is the record tag
+// * header: magic, version
+// * seed: the world's random seed, and how far its generator has advanced
+// * sched: the scheduler's round, and the serial the next new actor gets
+// * type tables (terrain, object_type, powered, item_type, transition, actor_type), in registration order
+// * per prefab: prefab, then legend and anchor records, then one picture record per row
+// * per map: map, then one row record per terrain row, then object/item/water/flowing/powered_cell/actor records; message, carried,
+//   and equipped records follow their actor.  An actor's serial breaks ties in the schedule (- if it was never scheduled)
+// * offset/exit/exit_one_way records, which refer to maps by index
+// * per actor with a map memory, patrol route, or AI state: memory (the actor's location), then seen records (one per
+//   remembered location), then waypoint records (the one being walked to first), then target and opened records
+// * end (so truncated files are detected)
+// bump SAVE_VERSION whenever a record layout changes
+pub const SAVE_VERSION:u32 = 1;
+const SAVE_MAGIC:&str = "MinGame-Rust2000 save";
+
+pub fn escape(src:&str) -> String {
+    let mut ret = String::with_capacity(src.len());
+    for c in src.chars() {
+        match c {
+            '\\' => ret.push_str("\\\\"),
+            '\t' => ret.push_str("\\t"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            _ => ret.push(c)
+        }
+    }
+    return ret;
+}
+
+pub fn unescape(src:&str) -> Result<String,Error> {
+    let mut ret = String::with_capacity(src.len());
+    let mut chars = src.chars();
+    while let Some(c) = chars.next() {
+        if '\\' != c {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => ret.push('\\'),
+            Some('t') => ret.push('\t'),
+            Some('n') => ret.push('\n'),
+            Some('r') => ret.push('\r'),
+            _ => return Err(Error{desc:"invalid escape sequence in ".to_string()+src})
+        }
+    }
+    return Ok(ret);
+}
+
+pub fn color_to_str(src:&colors::Color) -> String {
+    return format!("{},{},{}", src.r, src.g, src.b);
+}
+
+pub fn str_to_color(src:&str) -> Result<colors::Color,Error> {
+    let rgb: Vec<&str> = src.split(',').collect();
+    if 3 != rgb.len() { return Err(Error{desc:"color should be r,g,b: ".to_string()+src}); }
+    let mut ret = [0u8;3];
+    for i in 0..3 {
+        match u8::from_str(rgb[i].trim()) {
+            Ok(val) => ret[i] = val,
+            _ => return Err(Error{desc:"color component out of range: ".to_string()+src})
+        }
+    }
+    return Ok(colors::Color::new(ret[0], ret[1], ret[2]));
+}
+
+// c:<unicode code point>:<color, or - for default>
+// i:<image id>
+fn tile_to_str(src:&TileSpec) -> String {
+    match src {
+        Ok(t) => {
+            let col = match t.c {
+                Some(c) => color_to_str(&c),
+                None => "-".to_string()
+            };
+            return format!("c:{}:{}", u32::from(t.img), col);
+        },
+        Err(im) => { return "i:".to_string()+&escape(&im.img); }
+    }
+}
+
+fn str_to_tile(src:&str) -> Result<TileSpec,Error> {
+    if let Some(id) = src.strip_prefix("i:") { return Ok(Err(ImgSpec{img:unescape(id)?})); }
+    if let Some(spec) = src.strip_prefix("c:") {
+        let parts: Vec<&str> = spec.splitn(2, ':').collect();
+        if 2 == parts.len() {
+            if let Some(img) = u32::from_str(parts[0]).ok().and_then(std::char::from_u32) {
+                if "-" == parts[1] { return Ok(Ok(CharSpec{img, c:None})); }
+                return Ok(Ok(CharSpec{img, c:Some(str_to_color(parts[1])?)}));
+            }
+        }
+    }
+    return Err(Error{desc:"invalid tile: ".to_string()+src});
+}
+
+fn bg_to_str(src:&BackgroundSpec) -> String {
+    match src {
+        Ok(col) => { return color_to_str(col); },
+        Err(im) => { return "i:".to_string()+&escape(&im.img); }
+    }
+}
+
+fn str_to_bg(src:&str) -> Result<BackgroundSpec,Error> {
+    if let Some(id) = src.strip_prefix("i:") { return Ok(Err(ImgSpec{img:unescape(id)?})); }
+    return Ok(Ok(str_to_color(src)?));
+}
+
+fn bool_to_str(src:bool) -> &'static str { if src { return "1"; } else { return "0"; } }
+
+fn index_of<T>(src:&[Rc<T>], x:&Rc<T>) -> Option<usize> {
+    return src.iter().position(|y| Rc::ptr_eq(x, y));
+}
+
+// a prefab being read; it is registered once its last picture row is
+struct PendingPrefab {
+    name: String,
+    dim: [usize;2],
+    legend: Vec<(char,Vec<r_Terrain>,Option<r_MapObjectModel>)>,
+    anchors: Vec<(Compass,[i32;2])>,
+    cells: Vec<char>
+}
+
+struct SaveReader<R:BufRead> {
+    path: String,
+    src: std::io::Lines<R>,
+    line_no: usize
+}
+
+impl<R:BufRead> SaveReader<R> {
+    fn new(path:&str, src:R) -> SaveReader<R> {
+        return SaveReader{path:path.to_string(), src:src.lines(), line_no:0};
+    }
+
+    fn error(&self, msg:&str) -> Error {
+        return Error{desc:format!("{}:{}: {}", self.path, self.line_no, msg)};
+    }
+
+    // blank lines are ignored
+    fn next_record(&mut self) -> Result<Vec<String>,Error> {
+        loop {
+            self.line_no += 1;
+            match self.src.next() {
+                Some(Ok(line)) => {
+                    if line.trim().is_empty() { continue; }
+                    return Ok(line.split('\t').map(|x| x.to_string()).collect());
+                },
+                Some(Err(e)) => return Err(self.error(&e.to_string())),
+                None => return Err(self.error("unexpected end of savefile"))
+            }
+        }
+    }
+
+    fn require_fields(&self, rec:&[String], n:usize) -> Result<(),Error> {
+        if n != rec.len() { return Err(self.error(&format!("{} record should have {} fields, has {}", rec[0], n, rec.len()))); }
+        return Ok(());
+    }
+
+    fn parse<T:FromStr>(&self, src:&str, what:&str) -> Result<T,Error> {
+        match T::from_str(src) {
+            Ok(x) => return Ok(x),
+            _ => return Err(self.error(&format!("invalid {}: {}", what, src)))
+        }
+    }
+
+    fn parse_bool(&self, src:&str, what:&str) -> Result<bool,Error> {
+        match src {
+            "0" => return Ok(false),
+            "1" => return Ok(true),
+            _ => return Err(self.error(&format!("invalid {}: {}", what, src)))
+        }
+    }
+
+    fn parse_loc(&self, w:&World, src:&[String]) -> Result<Location,Error> {
+        let n:usize = self.parse(&src[0], "map index")?;
+        if n >= w.atlas.len() { return Err(self.error("unknown map index")); }
+        let pos = [self.parse(&src[1], "x")?, self.parse(&src[2], "y")?];
+        if !w.atlas[n].borrow().in_bounds(pos) { return Err(self.error("location out of bounds")); }
+        return Ok(Location::new(&w.atlas[n], pos));
+    }
+
+    // item type index, quantity (two fields)
+    fn parse_item(&self, w:&World, src:&[String]) -> Result<Item,Error> {
+        let n:usize = self.parse(&src[0], "item type index")?;
+        if n >= w.item_types.len() { return Err(self.error("unknown item type")); }
+        let qty:u16 = self.parse(&src[1], "quantity")?;
+        if 0 == qty { return Err(self.error("empty item stack")); }
+        return Ok(Item::new(Rc::clone(&w.item_types[n]), qty));
+    }
+
+    fn in_context<T>(&self, src:Result<T,Error>) -> Result<T,Error> {
+        match src {
+            Ok(x) => return Ok(x),
+            Err(e) => return Err(self.error(&e.desc))
+        }
+    }
+}
+
+impl World {
+    // map index, x, y (three fields)
+    fn loc_to_str(&self, src:&Location) -> String {
+        return format!("{}\t{}\t{}", index_of(&self.atlas, &src.map).unwrap(), src.pos[0], src.pos[1]);
+    }
+
+    fn write_savefile(&self, dest:&mut dyn Write) -> std::io::Result<()> {
+        writeln!(dest, "{}\t{}", SAVE_MAGIC, SAVE_VERSION)?;
+        writeln!(dest, "seed\t{}\t{}", self.rng.seed(), self.rng.steps())?;
+        writeln!(dest, "sched\t{}\t{}", self.sched.round(), self.sched.next_serial())?;
+        for t in &self.terrain_types {
+            let flow = match &t.flow {
+                Some(dir) => direction_name(dir.clone()),
+                None => "-"
+            };
+            let conveyor = match &t.conveyor {
+                Some(dir) => direction_name(dir.clone()),
+                None => "-"
+            };
+            writeln!(dest, "terrain\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", escape(&t.name), tile_to_str(&t.tile), bg_to_str(&t.bg),
+                bool_to_str(t.walkable), bool_to_str(t.transparent), t.sunken, flow, t.drain, bool_to_str(t.power), conveyor,
+                bool_to_str(t.unsupported), t.move_cost)?;
+        }
+        for m_type in &self.obj_types {
+            writeln!(dest, "object_type\t{}\t{}\t{}\t{}\t{}\t{}", escape(&m_type.name), tile_to_str(&m_type.tile),
+                bool_to_str(m_type.walkable), bool_to_str(m_type.transparent), m_type.spring, bool_to_str(m_type.transmits))?;
+        }
+        for x in &self.obj_powered {
+            writeln!(dest, "powered\t{}\t{}", index_of(&self.obj_types, &x[0]).unwrap(), index_of(&self.obj_types, &x[1]).unwrap())?;
+        }
+        for i_type in &self.item_types {
+            let slot = match i_type.slot {
+                Some(x) => x.name(),
+                None => "-"
+            };
+            writeln!(dest, "item_type\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", escape(&i_type.name), tile_to_str(&i_type.tile), slot,
+                i_type.speed, i_type.hp, i_type.attack, i_type.defense, i_type.damage)?;
+        }
+        for x in &self.transitions {
+            let needs: Vec<String> = x.needs.iter().map(|need| match need {
+                Precondition::Key(key) => "key ".to_string()+&index_of(&self.item_types, key).unwrap().to_string(),
+                Precondition::FreeCell => "free".to_string(),
+                Precondition::Strength(n) => "strength ".to_string()+&n.to_string()
+            }).collect();
+            let needs = if needs.is_empty() { "-".to_string() } else { needs.join(",") };
+            writeln!(dest, "transition\t{}\t{}\t{}\t{}\t{}", index_of(&self.obj_types, &x.from).unwrap(), x.verb.name(),
+                index_of(&self.obj_types, &x.to).unwrap(), needs, x.cost)?;
+        }
+        for a_type in &self.actor_types {
+            writeln!(dest, "actor_type\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", escape(&a_type.name), tile_to_str(&a_type.tile), a_type.base_AP, a_type.base_HP,
+                a_type.attack, a_type.defense, a_type.damage, a_type.capacity, a_type.ai.name())?;
+        }
+        for p in &self.prefabs {
+            writeln!(dest, "prefab\t{}\t{}\t{}", escape(&p.named()), p.width(), p.height())?;
+            for (c, terrain, obj) in p.legend() {
+                let terrain: Vec<String> = terrain.iter().map(|t| index_of(&self.terrain_types, t).unwrap().to_string()).collect();
+                let obj = match obj {
+                    Some(x) => index_of(&self.obj_types, x).unwrap().to_string(),
+                    None => "-".to_string()
+                };
+                writeln!(dest, "legend\t{}\t{}\t{}", u32::from(*c), terrain.join(","), obj)?;
+            }
+            for (dir, pos) in p.anchors() {
+                writeln!(dest, "anchor\t{}\t{}\t{}", direction_name(dir.clone()), pos[0], pos[1])?;
+            }
+            for y in 0..p.height() {
+                writeln!(dest, "picture\t{}", escape(&p.row(y).iter().collect::<String>()))?;
+            }
+        }
+        for r_m in &self.atlas {
+            let m = r_m.borrow();
+            writeln!(dest, "map\t{}\t{}\t{}\t{}", escape(&m.named()), m.width(), m.height(), m.level())?;
+            for y in 0..m.height_i32() {
+                let mut row = Vec::<String>::with_capacity(m.width());
+                for x in 0..m.width_i32() {
+                    row.push(index_of(&self.terrain_types, &m.get_terrain([x,y])).unwrap().to_string());
+                }
+                writeln!(dest, "row\t{}", row.join(" "))?;
+            }
+            for obj in m.map_objects() {
+                let o = obj.borrow();
+                let pos = o.loc().pos;
+                writeln!(dest, "object\t{}\t{}\t{}", pos[0], pos[1], index_of(&self.obj_types, &o.model).unwrap())?;
+            }
+            for (pos, pile) in m.item_piles() {
+                for item in pile.stacks() {
+                    writeln!(dest, "item\t{}\t{}\t{}\t{}", pos[0], pos[1], index_of(&self.item_types, &item.model).unwrap(), item.qty)?;
+                }
+            }
+            for (pos, depth) in m.water_cells() {
+                writeln!(dest, "water\t{}\t{}\t{}", pos[0], pos[1], depth)?;
+            }
+            for pos in m.flowing() {
+                writeln!(dest, "flowing\t{}\t{}", pos[0], pos[1])?;
+            }
+            for pos in m.powered() {
+                writeln!(dest, "powered_cell\t{}\t{}", pos[0], pos[1])?;
+            }
+            for r_act in m.actors() {
+                let act = r_act.borrow();
+                let serial = match act.sched {
+                    Some((serial, _)) => serial.to_string(),
+                    None => "-".to_string()
+                };
+                writeln!(dest, "actor\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", escape(&act.model.name), act.my_loc.pos[0], act.my_loc.pos[1],
+                    bool_to_str(act.is_pc), act.energy_at(self.sched.round()), act.hp.curHp, act.hp.maxHp, serial)?;
+                if act.is_pc {
+                    let mut catalog = get_messages_cache_mut();
+                    let msgs = catalog.get(Rc::clone(r_act));
+                    for n in 0..msgs.count() {
+                        let msg = msgs.message(n).unwrap();
+                        writeln!(dest, "message\t{}\t{}", msg.1, escape(&msg.0))?;
+                    }
+                }
+                for item in act.inventory().stacks() {
+                    writeln!(dest, "carried\t{}\t{}", index_of(&self.item_types, &item.model).unwrap(), item.qty)?;
+                }
+                for item in act.equipment() {
+                    writeln!(dest, "equipped\t{}", index_of(&self.item_types, &item.model).unwrap())?;
+                }
+            }
+        }
+        for x in &self.offsets {
+            let a = index_of(&self.atlas, &x.0).unwrap();
+            let b = index_of(&self.atlas, &x.1).unwrap();
+            if a < b { writeln!(dest, "offset\t{}\t{}\t{}\t{}", a, b, x.2[0], x.2[1])?; }  // set_offset registers the reverse
+        }
+        for x in &self.exits {
+            writeln!(dest, "exit\t{}\t{}", self.loc_to_str(&x[0]), self.loc_to_str(&x[1]))?;
+        }
+        for x in &self.exits_one_way {
+            writeln!(dest, "exit_one_way\t{}\t{}\t{}", self.loc_to_str(&x.0), self.loc_to_str(&x.1), x.2)?;
+        }
+        // memories can refer to any map, so they go after all maps
+        for r_m in &self.atlas {
+            for r_act in r_m.borrow().actors() {
+                let act = r_act.borrow();
+                let seen = act.memory().contents();
+                let ai = act.ai();
+                if seen.is_empty() && ai.waypoints().is_empty() && ai.target().is_none() && ai.opened().is_none() { continue; }
+                writeln!(dest, "memory\t{}", self.loc_to_str(&act.my_loc))?;
+                for (loc, mem) in seen {
+                    write!(dest, "seen\t{}\t{}", self.loc_to_str(&loc), bg_to_str(&mem.bg))?;
+                    for img in &mem.tiles { write!(dest, "\t{}", tile_to_str(img))?; }
+                    writeln!(dest)?;
+                }
+                for loc in ai.waypoints() {
+                    writeln!(dest, "waypoint\t{}", self.loc_to_str(loc))?;
+                }
+                if let Some(loc) = ai.target() { writeln!(dest, "target\t{}", self.loc_to_str(loc))?; }
+                if let Some(loc) = ai.opened() { writeln!(dest, "opened\t{}", self.loc_to_str(loc))?; }
+            }
+        }
+        writeln!(dest, "end")?;
+        return Ok(());
+    }
+
+    pub fn save(&self, path:&str) -> Result<(),Error> {
+        let to_error = |e:std::io::Error| Error{desc:path.to_string()+": "+&e.to_string()};
+        let file = File::create(path).map_err(to_error)?;
+        let mut dest = BufWriter::new(file);
+        self.write_savefile(&mut dest).map_err(to_error)?;
+        dest.flush().map_err(to_error)?;
+        return Ok(());
+    }
+
+    // replaces the current game (event handlers are retained); return value is a PC
+    pub fn load(&mut self, path:&str) -> Result<r_Actor,Error> {
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => return Err(Error{desc:path.to_string()+": "+&e.to_string()})
+        };
+        let mut src = SaveReader::new(path, BufReader::new(file));
+        let mut staging = World::new();
+
+        let header = src.next_record()?;
+        if 2 != header.len() || SAVE_MAGIC != header[0] { return Err(src.error("not a savefile")); }
+        let version:u32 = src.parse(&header[1], "version")?;
+        if SAVE_VERSION != version { return Err(src.error(&format!("unsupported savefile version {}", version))); }
+
+        let mut cur_map: Option<r_Map> = None;
+        let mut rows_pending:usize = 0;
+        let mut cur_actor: Option<r_Actor> = None;
+        let mut pc: Option<r_Actor> = None;
+        let mut serials = Vec::<(r_Actor,u64)>::new();
+        let mut messages = Vec::<(r_Actor,String,u8)>::new();  // the message cache is global, so it is only touched once the file is known good
+        let mut cur_prefab: Option<PendingPrefab> = None;
+        loop {
+            let rec = src.next_record()?;
+            if 0 < rows_pending && "row" != rec[0] { return Err(src.error(&format!("map is missing {} rows", rows_pending))); }
+            if cur_prefab.is_some() && !["legend", "anchor", "picture"].contains(&rec[0].as_str()) { return Err(src.error("prefab is missing picture rows")); }
+            match rec[0].as_str() {
+                "seed" => {
+                    src.require_fields(&rec, 3)?;
+                    staging.rng = GameRng::restore(src.parse(&rec[1], "seed")?, src.parse(&rec[2], "random steps")?);
+                },
+                "sched" => {
+                    src.require_fields(&rec, 3)?;
+                    if !staging.atlas.is_empty() { return Err(src.error("sched after maps")); }
+                    staging.restore_schedule(src.parse(&rec[1], "round")?, src.parse(&rec[2], "next serial")?);
+                },
+                "prefab" => {
+                    src.require_fields(&rec, 4)?;
+                    let name = src.in_context(unescape(&rec[1]))?;
+                    if staging.get_prefab(&name).is_some() { return Err(src.error(&("duplicate prefab ".to_string()+&name))); }
+                    let dim:[usize;2] = [src.parse(&rec[2], "width")?, src.parse(&rec[3], "height")?];
+                    if 0 == dim[0] || 0 == dim[1] { return Err(src.error("prefab dimensions must be positive")); }
+                    cur_prefab = Some(PendingPrefab{name, dim, legend:Vec::new(), anchors:Vec::new(), cells:Vec::new()});
+                },
+                "legend" => {
+                    src.require_fields(&rec, 4)?;
+                    let p = match &mut cur_prefab {
+                        Some(p) => p,
+                        None => return Err(src.error("legend outside of prefab"))
+                    };
+                    let c = match std::char::from_u32(src.parse(&rec[1], "legend character")?) {
+                        Some(c) if ' ' != c && !p.legend.iter().any(|l| l.0 == c) => c,
+                        _ => return Err(src.error("invalid legend character"))
+                    };
+                    let mut terrain = Vec::<r_Terrain>::new();
+                    for code in rec[2].split(',') {
+                        let n:usize = src.parse(code, "terrain index")?;
+                        if n >= staging.terrain_types.len() { return Err(src.error("legend refers to unknown terrain")); }
+                        terrain.push(Rc::clone(&staging.terrain_types[n]));
+                    }
+                    let obj = match rec[3].as_str() {
+                        "-" => None,
+                        code => {
+                            let n:usize = src.parse(code, "object type index")?;
+                            if n >= staging.obj_types.len() { return Err(src.error("legend refers to unknown object type")); }
+                            Some(Rc::clone(&staging.obj_types[n]))
+                        }
+                    };
+                    p.legend.push((c, terrain, obj));
+                },
+                "anchor" => {
+                    src.require_fields(&rec, 4)?;
+                    let p = match &mut cur_prefab {
+                        Some(p) => p,
+                        None => return Err(src.error("anchor outside of prefab"))
+                    };
+                    let dir = match direction_from_name(&rec[1]) {
+                        Some(dir) if !p.anchors.iter().any(|x| x.0 == dir) => dir,
+                        _ => return Err(src.error(&("invalid anchor direction: ".to_string()+&rec[1])))
+                    };
+                    let pos:[i32;2] = [src.parse(&rec[2], "x")?, src.parse(&rec[3], "y")?];
+                    if !(0 <= pos[0] && usize::try_from(pos[0]).unwrap() < p.dim[0] && 0 <= pos[1] && usize::try_from(pos[1]).unwrap() < p.dim[1]) {
+                        return Err(src.error("anchor outside of the picture"));
+                    }
+                    p.anchors.push((dir, pos));
+                },
+                "picture" => {
+                    src.require_fields(&rec, 2)?;
+                    let p = match &mut cur_prefab {
+                        Some(p) => p,
+                        None => return Err(src.error("picture outside of prefab"))
+                    };
+                    let row: Vec<char> = src.in_context(unescape(&rec[1]))?.chars().collect();
+                    if p.dim[0] != row.len() { return Err(src.error("picture row length does not match prefab width")); }
+                    if let Some(c) = row.iter().find(|c| ' ' != **c && !p.legend.iter().any(|l| l.0 == **c)) {
+                        return Err(src.error(&format!("{} is not in the legend", c)));
+                    }
+                    p.cells.extend(row);
+                    if p.dim[0]*p.dim[1] == p.cells.len() {
+                        let p = cur_prefab.take().unwrap();
+                        staging.prefabs.push(Prefab::new(&p.name, p.dim, p.cells, p.legend, p.anchors));
+                    }
+                },
+                "terrain" => {
+                    src.require_fields(&rec, 13)?;
+                    let flow = match rec[7].as_str() {
+                        "-" => None,
+                        x => match direction_from_name(x) {
+                            Some(dir) => Some(dir),
+                            None => return Err(src.error(&("invalid flow: ".to_string()+x)))
+                        }
+                    };
+                    let conveyor = match rec[10].as_str() {
+                        "-" => None,
+                        x => match direction_from_name(x) {
+                            Some(dir) => Some(dir),
+                            None => return Err(src.error(&("invalid conveyor: ".to_string()+x)))
+                        }
+                    };
+                    let move_cost:i16 = src.parse(&rec[12], "move_cost")?;
+                    if 0 >= move_cost { return Err(src.error("move_cost should be positive")); }
+                    let t = Terrain{name:src.in_context(unescape(&rec[1]))?, tile:src.in_context(str_to_tile(&rec[2]))?,
+                        bg:src.in_context(str_to_bg(&rec[3]))?, walkable:src.parse_bool(&rec[4], "walkable")?,
+                        transparent:src.parse_bool(&rec[5], "transparent")?, sunken:src.parse(&rec[6], "sunken")?,
+                        flow, drain:src.parse(&rec[8], "drain")?, power:src.parse_bool(&rec[9], "power")?,
+                        conveyor, unsupported:src.parse_bool(&rec[11], "unsupported")?, move_cost};
+                    staging.new_terrain(t);
+                },
+                "object_type" => {
+                    src.require_fields(&rec, 7)?;
+                    let mut m_type = MapObjectModel::new(&src.in_context(unescape(&rec[1]))?, src.in_context(str_to_tile(&rec[2]))?,
+                        src.parse_bool(&rec[3], "walkable")?, src.parse_bool(&rec[4], "transparent")?);
+                    m_type.spring = src.parse(&rec[5], "spring")?;
+                    m_type.transmits = src.parse_bool(&rec[6], "transmits")?;
+                    staging.new_map_object_model(m_type);
+                },
+                "transition" => {
+                    src.require_fields(&rec, 6)?;
+                    let from:usize = src.parse(&rec[1], "object type index")?;
+                    let to:usize = src.parse(&rec[3], "object type index")?;
+                    if from >= staging.obj_types.len() || to >= staging.obj_types.len() { return Err(src.error("transition refers to unknown object type")); }
+                    let verb = match Verb::from_name(&rec[2]) {
+                        Some(x) => x,
+                        None => return Err(src.error(&("invalid verb: ".to_string()+&rec[2])))
+                    };
+                    let mut needs = Vec::<Precondition>::new();
+                    if "-" != rec[4] {
+                        for need in rec[4].split(',') {
+                            if "free" == need { needs.push(Precondition::FreeCell); }
+                            else if let Some(n) = need.strip_prefix("key ") {
+                                let n:usize = src.parse(n, "item type index")?;
+                                if n >= staging.item_types.len() { return Err(src.error("transition refers to unknown item type")); }
+                                needs.push(Precondition::Key(Rc::clone(&staging.item_types[n])));
+                            } else if let Some(n) = need.strip_prefix("strength ") {
+                                needs.push(Precondition::Strength(src.parse(n, "strength")?));
+                            } else { return Err(src.error(&("invalid precondition: ".to_string()+need))); }
+                        }
+                    }
+                    let from = Rc::clone(&staging.obj_types[from]);
+                    if staging.get_transition(&from, verb).is_some() { return Err(src.error("duplicate transition")); }
+                    staging.new_transition(Transition{from, verb, to:Rc::clone(&staging.obj_types[to]), needs, cost:src.parse(&rec[5], "cost")?});
+                },
+                "powered" => {
+                    src.require_fields(&rec, 3)?;
+                    let from:usize = src.parse(&rec[1], "object type index")?;
+                    let to:usize = src.parse(&rec[2], "object type index")?;
+                    if from >= staging.obj_types.len() || to >= staging.obj_types.len() { return Err(src.error("powered refers to unknown object type")); }
+                    staging.obj_powered.push([Rc::clone(&staging.obj_types[from]), Rc::clone(&staging.obj_types[to])]);
+                },
+                "item_type" => {
+                    src.require_fields(&rec, 9)?;
+                    let mut i_type = ItemModel::new(&src.in_context(unescape(&rec[1]))?, src.in_context(str_to_tile(&rec[2]))?);
+                    if "-" != rec[3] {
+                        match Slot::from_name(&rec[3]) {
+                            Some(slot) => i_type.slot = Some(slot),
+                            None => return Err(src.error(&("invalid slot: ".to_string()+&rec[3])))
+                        }
+                    }
+                    i_type.speed = src.parse(&rec[4], "speed")?;
+                    i_type.hp = src.parse(&rec[5], "hp")?;
+                    i_type.attack = src.parse(&rec[6], "attack")?;
+                    i_type.defense = src.parse(&rec[7], "defense")?;
+                    i_type.damage = src.parse(&rec[8], "damage")?;
+                    staging.new_item_model(i_type);
+                },
+                "actor_type" => {
+                    src.require_fields(&rec, 10)?;
+                    let mut a_type = ActorModel::new(&src.in_context(unescape(&rec[1]))?, src.in_context(str_to_tile(&rec[2]))?);
+                    a_type.base_AP = src.parse(&rec[3], "base AP")?;
+                    a_type.base_HP = src.parse(&rec[4], "base HP")?;
+                    a_type.attack = src.parse(&rec[5], "attack")?;
+                    a_type.defense = src.parse(&rec[6], "defense")?;
+                    a_type.damage = src.parse(&rec[7], "damage")?;
+                    a_type.capacity = src.parse(&rec[8], "capacity")?;
+                    a_type.ai = match Behavior::from_name(&rec[9]) {
+                        Some(ai) => ai,
+                        None => return Err(src.error(&("invalid ai: ".to_string()+&rec[9])))
+                    };
+                    staging.new_actor_model(a_type);
+                },
+                "map" => {
+                    src.require_fields(&rec, 5)?;
+                    if staging.terrain_types.is_empty() { return Err(src.error("map before any terrain")); }
+                    let w:i32 = src.parse(&rec[2], "width")?;
+                    let h:i32 = src.parse(&rec[3], "height")?;
+                    if 0 >= w || 0 >= h { return Err(src.error("map dimensions must be positive")); }
+                    let t = Rc::clone(&staging.terrain_types[0]);
+                    let r_m = staging.new_map(&src.in_context(unescape(&rec[1]))?, [w, h], t);
+                    r_m.borrow_mut().set_level(src.parse(&rec[4], "level")?);
+                    cur_map = Some(r_m);
+                    cur_actor = None;
+                    rows_pending = usize::try_from(h).unwrap();
+                },
+                "row" => {
+                    src.require_fields(&rec, 2)?;
+                    let r_m = match &cur_map {
+                        Some(m) if 0 < rows_pending => Rc::clone(m),
+                        _ => return Err(src.error("row outside of map"))
+                    };
+                    let mut m = r_m.borrow_mut();
+                    let y = m.height_i32() - i32::try_from(rows_pending).unwrap();
+                    let codes: Vec<&str> = rec[1].split(' ').collect();
+                    if m.width() != codes.len() { return Err(src.error("row length does not match map width")); }
+                    for (x, code) in codes.iter().enumerate() {
+                        let n:usize = src.parse(code, "terrain index")?;
+                        if n >= staging.terrain_types.len() { return Err(src.error("row refers to unknown terrain")); }
+                        m.set_terrain([i32::try_from(x).unwrap(), y], Rc::clone(&staging.terrain_types[n]));
+                    }
+                    rows_pending -= 1;
+                },
+                "object" => {
+                    src.require_fields(&rec, 4)?;
+                    let r_m = match &cur_map {
+                        Some(m) => Rc::clone(m),
+                        None => return Err(src.error("object outside of map"))
+                    };
+                    let pos = [src.parse(&rec[1], "x")?, src.parse(&rec[2], "y")?];
+                    let n:usize = src.parse(&rec[3], "object type index")?;
+                    if n >= staging.obj_types.len() { return Err(src.error("object refers to unknown object type")); }
+                    if !r_m.borrow().in_bounds(pos) { return Err(src.error("object out of bounds")); }
+                    r_m.borrow_mut().set_map_object(Rc::new(RefCell::new(MapObject::new(Rc::clone(&staging.obj_types[n]), Location::new(&r_m, pos)))));
+                },
+                "item" => {
+                    src.require_fields(&rec, 5)?;
+                    let r_m = match &cur_map {
+                        Some(m) => Rc::clone(m),
+                        None => return Err(src.error("item outside of map"))
+                    };
+                    let pos = [src.parse(&rec[1], "x")?, src.parse(&rec[2], "y")?];
+                    if !r_m.borrow().in_bounds(pos) { return Err(src.error("item out of bounds")); }
+                    let item = src.parse_item(&staging, &rec[3..5])?;
+                    r_m.borrow_mut().add_item(pos, item);
+                },
+                "water" => {
+                    src.require_fields(&rec, 4)?;
+                    let r_m = match &cur_map {
+                        Some(m) => Rc::clone(m),
+                        None => return Err(src.error("water outside of map"))
+                    };
+                    let pos = [src.parse(&rec[1], "x")?, src.parse(&rec[2], "y")?];
+                    let depth:u8 = src.parse(&rec[3], "depth")?;
+                    if !r_m.borrow().in_bounds(pos) { return Err(src.error("water out of bounds")); }
+                    if 0 == depth || MAX_WATER_DEPTH < depth { return Err(src.error("invalid water depth")); }
+                    r_m.borrow_mut().set_water(pos, depth);
+                },
+                "flowing" | "powered_cell" => {
+                    src.require_fields(&rec, 3)?;
+                    let r_m = match &cur_map {
+                        Some(m) => Rc::clone(m),
+                        None => return Err(src.error(&(rec[0].clone()+" outside of map")))
+                    };
+                    let pos = [src.parse(&rec[1], "x")?, src.parse(&rec[2], "y")?];
+                    if !r_m.borrow().in_bounds(pos) { return Err(src.error(&(rec[0].clone()+" out of bounds"))); }
+                    let mut m = r_m.borrow_mut();
+                    if "flowing" == rec[0] {
+                        let mut cells = m.flowing().to_vec();
+                        cells.push(pos);
+                        m.set_flowing(cells);
+                    } else {
+                        let mut cells = m.powered().to_vec();
+                        cells.push(pos);
+                        m.set_powered(cells);
+                    }
+                },
+                "carried" => {
+                    src.require_fields(&rec, 3)?;
+                    let r_act = match &cur_actor {
+                        Some(act) => Rc::clone(act),
+                        None => return Err(src.error("carried without actor"))
+                    };
+                    let item = src.parse_item(&staging, &rec[1..3])?;
+                    if r_act.borrow_mut().inventory_mut().add(item).is_err() { return Err(src.error("inventory over capacity")); }
+                },
+                "equipped" => {
+                    src.require_fields(&rec, 2)?;
+                    let r_act = match &cur_actor {
+                        Some(act) => Rc::clone(act),
+                        None => return Err(src.error("equipped without actor"))
+                    };
+                    let item = src.parse_item(&staging, &[rec[1].clone(), "1".to_string()])?;
+                    let mut act = r_act.borrow_mut();
+                    match item.model.slot {
+                        Some(slot) if act.equipped(slot).is_none() => act.equipment.push(item),    // saved HP already include modifiers
+                        _ => return Err(src.error("item cannot be equipped"))
+                    }
+                },
+                "actor" => {
+                    src.require_fields(&rec, 9)?;
+                    let r_m = match &cur_map {
+                        Some(m) => Rc::clone(m),
+                        None => return Err(src.error("actor outside of map"))
+                    };
+                    let name = src.in_context(unescape(&rec[1]))?;
+                    let model = match staging.get_actor_model(&name) {
+                        Some(a_type) => a_type,
+                        None => return Err(src.error(&("unknown actor type ".to_string()+&name)))
+                    };
+                    let pos = [src.parse(&rec[2], "x")?, src.parse(&rec[3], "y")?];
+                    if !r_m.borrow().in_bounds(pos) { return Err(src.error("actor out of bounds")); }
+                    let r_act = r_m.borrow_mut().new_actor(model, Location::new(&r_m, pos));
+                    {
+                    let mut act = r_act.borrow_mut();
+                    act.is_pc = src.parse_bool(&rec[4], "is_pc")?;
+                    act.ap = src.parse(&rec[5], "AP")?;
+                    act.ap_round = staging.sched.round();
+                    act.hp = HPstats{curHp:src.parse(&rec[6], "HP")?, maxHp:src.parse(&rec[7], "max HP")?};
+                    if act.is_pc && pc.is_none() { pc = Some(Rc::clone(&r_act)); }
+                    }
+                    if "-" != rec[8] {
+                        let serial:u64 = src.parse(&rec[8], "serial")?;
+                        if serial >= staging.sched.next_serial() || serials.iter().any(|x| x.1 == serial) { return Err(src.error("invalid serial")); }
+                        serials.push((Rc::clone(&r_act), serial));
+                    }
+                    cur_actor = Some(r_act);
+                },
+                "message" => {
+                    src.require_fields(&rec, 3)?;
+                    let r_act = match &cur_actor {
+                        Some(act) => Rc::clone(act),
+                        None => return Err(src.error("message without actor"))
+                    };
+                    let n:u8 = src.parse(&rec[1], "message count")?;
+                    messages.push((r_act, src.in_context(unescape(&rec[2]))?, n));
+                },
+                "offset" => {
+                    src.require_fields(&rec, 5)?;
+                    let a:usize = src.parse(&rec[1], "map index")?;
+                    let b:usize = src.parse(&rec[2], "map index")?;
+                    if a >= staging.atlas.len() || b >= staging.atlas.len() || a == b { return Err(src.error("offset refers to unknown map")); }
+                    let delta = [src.parse(&rec[3], "x offset")?, src.parse(&rec[4], "y offset")?];
+                    let (m_a, m_b) = (Rc::clone(&staging.atlas[a]), Rc::clone(&staging.atlas[b]));
+                    match staging.get_offset(&m_a, &m_b) {
+                        // already implied by earlier offsets
+                        Some(d) => if d != delta { return Err(src.error("offset disagrees with earlier offsets")); },
+                        None => staging.set_offset(&m_a, &m_b, delta)
+                    }
+                },
+                "exit" => {
+                    src.require_fields(&rec, 7)?;
+                    let a = src.parse_loc(&staging, &rec[1..4])?;
+                    let b = src.parse_loc(&staging, &rec[4..7])?;
+                    staging.new_exit(a, b);
+                },
+                "exit_one_way" => {
+                    src.require_fields(&rec, 8)?;
+                    let from = src.parse_loc(&staging, &rec[1..4])?;
+                    let to = src.parse_loc(&staging, &rec[4..7])?;
+                    staging.new_exit_one_way(from, to, src.parse(&rec[7], "damage")?);
+                },
+                "memory" => {
+                    src.require_fields(&rec, 4)?;
+                    let loc = src.parse_loc(&staging, &rec[1..4])?;
+                    match loc.get_actor() {
+                        Some(act) => cur_actor = Some(act),
+                        None => return Err(src.error("memory refers to missing actor"))
+                    }
+                },
+                "seen" => {
+                    if 5 > rec.len() { return Err(src.error(&format!("seen record should have at least 5 fields, has {}", rec.len()))); }
+                    let r_act = match &cur_actor {
+                        Some(act) => Rc::clone(act),
+                        None => return Err(src.error("seen without memory"))
+                    };
+                    let loc = src.parse_loc(&staging, &rec[1..4])?;
+                    let bg = src.in_context(str_to_bg(&rec[4]))?;
+                    let mut tiles = Vec::<TileSpec>::with_capacity(rec.len()-5);
+                    for x in &rec[5..] { tiles.push(src.in_context(str_to_tile(x))?); }
+                    r_act.borrow_mut().memory_mut().set(&loc, Remembered{bg, tiles});
+                },
+                "waypoint" => {
+                    src.require_fields(&rec, 4)?;
+                    let r_act = match &cur_actor {
+                        Some(act) => Rc::clone(act),
+                        None => return Err(src.error("waypoint without memory"))
+                    };
+                    let loc = src.parse_loc(&staging, &rec[1..4])?;
+                    let mut route = r_act.borrow().ai().waypoints().to_vec();
+                    route.push(loc);
+                    r_act.borrow_mut().ai_mut().set_waypoints(route);
+                },
+                "target" | "opened" => {
+                    src.require_fields(&rec, 4)?;
+                    let r_act = match &cur_actor {
+                        Some(act) => Rc::clone(act),
+                        None => return Err(src.error(&(rec[0].clone()+" without memory")))
+                    };
+                    let loc = Some(src.parse_loc(&staging, &rec[1..4])?);
+                    let mut act = r_act.borrow_mut();
+                    if "target" == rec[0] { act.ai_mut().set_target(loc); } else { act.ai_mut().set_opened(loc); }
+                },
+                "end" => break,
+                _ => return Err(src.error(&("unrecognized record ".to_string()+&rec[0])))
+            }
+        }
+
+        match pc {
+            Some(r_pc) => {
+                staging.event_handlers = std::mem::take(&mut self.event_handlers);
+                staging.keymap = std::mem::replace(&mut self.keymap, KeyMap::new());
+                for (r_act, serial) in &serials { staging.schedule_as(r_act, *serial); }  // now that energy and equipment are final
+                {
+                let mut catalog = get_messages_cache_mut();
+                for (r_act, msg, n) in messages { catalog.get_mut(r_act).restore_message(&msg, n); }
+                }
+                *self = staging;
+                return Ok(r_pc);
+            },
+            None => return Err(src.error("savefile has no PC"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(w:&World, name:&str) -> String {
+        let path = std::env::temp_dir().join(name);
+        w.save(path.to_str().unwrap()).unwrap();
+        let ret = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        return ret;
+    }
+
+    fn loaded(src:&str, name:&str) -> Result<(World,r_Actor),Error> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, src).unwrap();
+        let mut w = World::new();
+        let ret = w.load(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+        return ret.map(|pc| (w, pc));
+    }
+
+    // the PC waits; everyone else does as they would
+    fn play(w:&mut World, turns:usize) {
+        for _ in 0..turns {
+            let act = w.next_actor().unwrap();
+            if act.borrow().is_pc { w.perform(&act, Action::Wait); } else { w.npc_turn(&act); }
+        }
+    }
+
+    #[test]
+    fn a_game_survives_a_round_trip() {
+        let mut w = World::new();
+        w.reseed(5);
+        w.load_content("data/content.txt").unwrap();
+        w.load_prefabs("data/prefabs.txt").unwrap();
+        let pc = w.new_game();
+        play(&mut w, 300);
+        // state the turns so far need not have reached
+        let r_m = Rc::clone(&pc.borrow().loc().map);
+        let npc = r_m.borrow().actors().iter().find(|a| !a.borrow().is_pc).cloned().unwrap();
+        npc.borrow_mut().ai_mut().set_target(Some(pc.borrow().loc()));
+        npc.borrow_mut().ai_mut().set_opened(Some(Location::new(&r_m, [1,1])));
+        r_m.borrow_mut().set_flowing(vec![[2,3], [4,5]]);
+        r_m.borrow_mut().set_powered(vec![[6,7]]);
+        get_messages_cache_mut().get_mut(Rc::clone(&pc)).add_message("Remember this");
+
+        let before = saved(&w, "min_game_round_trip_a.txt");
+        let (w2, pc2) = loaded(&before, "min_game_round_trip_b.txt").unwrap();
+        assert_eq!(before, saved(&w2, "min_game_round_trip_c.txt"));
+        assert!(0 < w2.sched.round() && w.sched.round() == w2.sched.round());
+        assert!(w2.get_prefab("waterwheel").is_some());
+        let r_m2 = Rc::clone(&pc2.borrow().loc().map);
+        assert_eq!(&[[2,3], [4,5]], r_m2.borrow().flowing());
+        assert_eq!(&[[6,7]], r_m2.borrow().powered());
+        let npc2 = r_m2.borrow().actors().iter().find(|a| !a.borrow().is_pc).cloned().unwrap();
+        assert!(npc2.borrow().ai().target() == Some(&pc2.borrow().loc()));
+        let mut c = get_messages_cache_mut();
+        let msgs = c.get(pc2);
+        assert_eq!("Remember this", msgs.message(msgs.count()-1).unwrap().0);
+    }
+
+    #[test]
+    fn a_loaded_game_plays_on_the_same() {
+        let mut w = World::new();
+        w.reseed(9);
+        w.load_content("data/content.txt").unwrap();
+        w.load_prefabs("data/prefabs.txt").unwrap();
+        w.new_game();
+        play(&mut w, 137);
+        let (mut w2, _) = loaded(&saved(&w, "min_game_plays_on_a.txt"), "min_game_plays_on_b.txt").unwrap();
+        // who comes up, in order: ties in the schedule must break the same way
+        for _ in 0..200 {
+            let (a, b) = (w.next_actor().unwrap(), w2.next_actor().unwrap());
+            assert!(a.borrow().loc().pos == b.borrow().loc().pos && a.borrow().energy() == b.borrow().energy());
+            if a.borrow().is_pc { w.perform(&a, Action::Wait); } else { w.npc_turn(&a); }
+            if b.borrow().is_pc { w2.perform(&b, Action::Wait); } else { w2.npc_turn(&b); }
+        }
+        assert_eq!(saved(&w, "min_game_plays_on_c.txt"), saved(&w2, "min_game_plays_on_d.txt"));
+    }
+
+    #[test]
+    fn a_bad_file_leaves_the_game_alone() {
+        let mut w = World::new();
+        w.load_content("data/content.txt").unwrap();
+        w.load_prefabs("data/prefabs.txt").unwrap();
+        w.new_game();
+        let good = saved(&w, "min_game_bad_a.txt");
+        let truncated = &good[..good.rfind("end").unwrap()];
+        let path = std::env::temp_dir().join("min_game_bad_b.txt");
+        std::fs::write(&path, truncated).unwrap();
+        let err = w.load(path.to_str().unwrap()).err().unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(err.desc.ends_with("unexpected end of savefile"));
+        assert_eq!(good, saved(&w, "min_game_bad_c.txt"));
+
+        let bad_picture = good.replacen("picture\t", "picture\t?", 1);
+        assert!(loaded(&bad_picture, "min_game_bad_d.txt").err().unwrap().desc.ends_with("picture row length does not match prefab width"));
+        let bad_serial = good.replacen("\t0\n", "\t999999\n", 1);
+        assert!(bad_serial != good);
+        assert!(loaded(&bad_serial, "min_game_bad_e.txt").is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::isk::*;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap,HashMap};
+
+// actors are keyed on the round in which they next have positive energy; ties go to the
+// earlier-scheduled actor.  Entries are invalidated lazily: each actor has at most one live entry,
+// the one whose generation matches its own.  Energy is granted lazily too, when an actor comes up,
+// so a round costs nothing for actors who are not due.
+pub struct Scheduler {
+    round: u64,
+    queue: BinaryHeap<Reverse<(u64,u64,u32)>>,  // round, serial, generation
+    actors: HashMap<u64,w_Actor>,   // by serial
+    next_serial: u64,
+    current: Option<r_Actor>   // returned by next_actor; rescheduled on the next call
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        return Scheduler{round:0, queue:BinaryHeap::new(), actors:HashMap::new(), next_serial:0, current:None};
+    }
+
+    pub fn round(&self) -> u64 { return self.round; }
+    pub fn next_serial(&self) -> u64 { return self.next_serial; }
+    pub fn is_empty(&self) -> bool { return self.actors.is_empty(); }
+}
+
+impl World {
+    // new actors must be scheduled (World::new_actor does this)
+    pub fn schedule(&mut self, r_act:&r_Actor) {
+        let mut act = r_act.borrow_mut();
+        let serial = match act.sched {
+            Some((serial, _)) => serial,
+            None => {
+                let serial = self.sched.next_serial;
+                self.sched.next_serial += 1;
+                self.sched.actors.insert(serial, Rc::downgrade(r_act));
+                act.ap_round = self.sched.round;    // no back pay
+                serial
+            }
+        };
+        World::catch_up(self.sched.round, &mut act);
+        let gen = act.sched.map_or(0, |x| x.1.wrapping_add(1));
+        act.sched = Some((serial, gen));
+        let ready = if 0 < act.ap { self.sched.round } else {
+            self.sched.round + u64::try_from((-i32::from(act.ap))/i32::from(act.speed()) + 1).unwrap()
+        };
+        self.sched.queue.push(Reverse((ready, serial, gen)));
+    }
+
+    // loading a saved game: the round it was saved in, and the serial the next new actor gets
+    pub fn restore_schedule(&mut self, round:u64, next_serial:u64) {
+        debug_assert!(self.sched.is_empty());
+        self.sched.round = round;
+        self.sched.next_serial = next_serial;
+    }
+
+    // loading a saved game: the serial the actor was saved with, so ties go the same way they would have
+    pub fn schedule_as(&mut self, r_act:&r_Actor, serial:u64) {
+        debug_assert!(r_act.borrow().sched.is_none() && serial < self.sched.next_serial && !self.sched.actors.contains_key(&serial));
+        self.sched.actors.insert(serial, Rc::downgrade(r_act));
+        {
+        let mut act = r_act.borrow_mut();
+        act.ap_round = self.sched.round;
+        act.sched = Some((serial, 0));
+        }
+        self.schedule(r_act);
+    }
+
+    // everyone on the maps, in atlas order
+    pub fn schedule_all(&mut self) {
+        let mut staging = Vec::<r_Actor>::new();
+        for r_m in &self.atlas { staging.extend(r_m.borrow().actors().iter().cloned()); }
+        for r_act in staging { self.schedule(&r_act); }
+    }
+
+    // the energy of the rounds since the actor's last turn, at its speed then
+    fn catch_up(round:u64, act:&mut Actor) {
+        act.ap = act.energy_at(round);
+        act.ap_round = round;
+    }
+
+    // anything that changes an actor's speed goes through here: energy so far is at the old speed, and its next turn
+    // moves with the new one
+    pub fn change_speed<T>(&mut self, r_act:&r_Actor, f:impl FnOnce(&mut Actor) -> T) -> T {
+        let ret = {
+            let mut act = r_act.borrow_mut();
+            World::catch_up(self.sched.round, &mut act);
+            f(&mut act)
+        };
+        let is_current = self.sched.current.as_ref().is_some_and(|x| Rc::ptr_eq(x, r_act));
+        if !is_current && r_act.borrow().sched.is_some() { self.schedule(r_act); }
+        return ret;
+    }
+
+    // the map simulation; actors are not visited
+    fn turn_postprocess(&mut self) {
+        for r_m in &self.atlas { r_m.borrow_mut().water_turn(); }
+        self.conveyor_turn();
+        self.power_turn();
+    }
+
+    pub fn next_actor(&mut self) -> Option<r_Actor> {
+        if let Some(r_act) = self.sched.current.take() {
+            if r_act.borrow().is_dead() {
+                let serial = r_act.borrow_mut().sched.take().unwrap().0;
+                self.sched.actors.remove(&serial);
+                World::remove_dead(&r_act);
+            } else { self.schedule(&r_act); }
+        }
+        if self.sched.is_empty() { self.schedule_all(); }
+        loop {
+            let Reverse((ready, serial, gen)) = self.sched.queue.pop()?;
+            let r_act = match self.sched.actors.get(&serial).and_then(|x| x.upgrade()) {
+                Some(x) => x,
+                None => {
+                    self.sched.actors.remove(&serial);
+                    continue;
+                }
+            };
+            if r_act.borrow().sched.is_none_or(|x| x.1 != gen) { continue; }    // stale entry
+            if r_act.borrow().is_dead() {   // the live entry: the actor is gone for good
+                self.sched.actors.remove(&serial);
+                r_act.borrow_mut().sched = None;
+                World::remove_dead(&r_act);
+                continue;
+            }
+            while self.sched.round < ready {
+                self.turn_postprocess();
+                self.sched.round += 1;
+            }
+            World::catch_up(self.sched.round, &mut r_act.borrow_mut());
+            if 0 >= r_act.borrow().energy() {  // spent energy out of turn
+                self.schedule(&r_act);
+                continue;
+            }
+            self.sched.current = Some(Rc::clone(&r_act));
+            return Some(r_act);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isk::gps::*;
+    use std::time::{Duration,Instant};
+
+    fn arena(w:&mut World, dim:i32) -> (r_Map, r_ActorModel) {
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:None}), true, true));
+        let model = w.new_actor_model(ActorModel::new("m", Ok(CharSpec{img:'m', c:None})));
+        return (w.new_map("m", [dim, dim], floor), model);
+    }
+
+    fn speed_model(w:&mut World, speed:i16) -> r_ActorModel {
+        let mut ret = ActorModel::new("x", Ok(CharSpec{img:'x', c:None}));
+        ret.base_AP = speed;
+        return w.new_actor_model(ret);
+    }
+
+    // who acts, for each of n turns; each turn costs BASE_ACTION_COST
+    fn turns(w:&mut World, who:&[&r_Actor], n:usize) -> String {
+        let mut ret = String::new();
+        for _ in 0..n {
+            let r_act = w.next_actor().unwrap();
+            ret.push(char::from(b'a'+u8::try_from(who.iter().position(|x| Rc::ptr_eq(x, &r_act)).unwrap()).unwrap()));
+            r_act.borrow_mut().spend_energy(BASE_ACTION_COST);
+        }
+        return ret;
+    }
+
+    #[test]
+    fn ties_go_in_scheduling_order_and_speed_counts() {
+        let mut w = World::new();
+        let (m, slow) = arena(&mut w, 10);
+        let fast = speed_model(&mut w, 2*BASE_ACTION_COST);
+        let cam = Location::new(&m, [0,0]);
+        let a = w.new_actor(Rc::clone(&slow), &cam, [1,1]).unwrap();
+        let b = w.new_actor(fast, &cam, [2,2]).unwrap();
+        let c = w.new_actor(slow, &cam, [3,3]).unwrap();
+        assert_eq!("abbcabbcabbc", turns(&mut w, &[&a, &b, &c], 12));
+        // the dead are dropped when they come up
+        c.borrow_mut().hp_mut().takeHit(1000);
+        assert_eq!("abbabbabb", turns(&mut w, &[&a, &b, &c], 9));
+        assert_eq!(2, m.borrow().actors().len());
+    }
+
+    #[test]
+    fn speed_changes_move_the_next_turn() {
+        let mut w = World::new();
+        let (m, model) = arena(&mut w, 10);
+        let slow = speed_model(&mut w, BASE_ACTION_COST/10);
+        let cam = Location::new(&m, [0,0]);
+        let a = w.new_actor(model, &cam, [1,1]).unwrap();
+        let b = w.new_actor(slow, &cam, [2,2]).unwrap();
+        assert_eq!("ab", turns(&mut w, &[&a, &b], 2));
+        assert_eq!("aaa", turns(&mut w, &[&a, &b], 3));     // b is ten rounds away
+        let haste = w.new_item_model({
+            let mut x = crate::isk::item::ItemModel::new("boots", Ok(CharSpec{img:'[', c:None}));
+            x.slot = Some(crate::isk::item::Slot::Boots);
+            x.speed = BASE_ACTION_COST;
+            x
+        });
+        assert!(b.borrow_mut().inventory_mut().add(crate::isk::item::Item::new(haste, 1)).is_ok());
+        assert!(w.change_speed(&b, |act| act.equip(0)));
+        assert_eq!("ab", turns(&mut w, &[&a, &b], 2));
+    }
+
+    #[test]
+    fn actors_who_are_not_due_are_not_visited() {
+        let mut w = World::new();
+        let (m, model) = arena(&mut w, 100);
+        let idle = speed_model(&mut w, 1);
+        let cam = Location::new(&m, [0,0]);
+        let a = w.new_actor(model, &cam, [0,0]).unwrap();
+        let mut sleepers = Vec::new();
+        for n in 1..1000 {
+            let r_act = w.new_actor(Rc::clone(&idle), &cam, [n % 100, n / 100]).unwrap();
+            r_act.borrow_mut().spend_energy(BASE_ACTION_COST);   // due in a hundred rounds
+            sleepers.push(r_act);
+        }
+        for _ in 0..50 {
+            let r_act = w.next_actor().unwrap();
+            assert!(Rc::ptr_eq(&a, &r_act));
+            r_act.borrow_mut().spend_energy(BASE_ACTION_COST);
+        }
+        assert!(40 < w.sched.round());
+        assert!(sleepers.iter().all(|x| 0 == x.borrow().ap_round));
+    }
+
+    // n_actors on one open map, each spending a varying amount of energy per action
+    fn bench(n_actors:i32, n_actions:usize) -> Duration {
+        let mut w = World::new();
+        let side = (1..).find(|x| x*x >= n_actors).unwrap();
+        let (m, model) = arena(&mut w, side);
+        for n in 0..n_actors {
+            let r_act = m.borrow_mut().new_actor(Rc::clone(&model), Location::new(&m, [n % side, n / side]));
+            w.schedule(&r_act);
+        }
+        let start = Instant::now();
+        for n in 0..n_actions {
+            let r_act = w.next_actor().unwrap();
+            r_act.borrow_mut().spend_energy(BASE_ACTION_COST/2 + i16::try_from(n % 100).unwrap());
+        }
+        return start.elapsed();
+    }
+
+    // wall-clock, so flaky on a loaded machine: cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn time_per_action_barely_grows_with_actors() {
+        let small = bench(100, 20000);
+        let large = bench(10000, 20000);
+        assert!(large < 10*small, "100 actors: {:?}, 10000 actors: {:?}", small, large);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::isk::*;
+use crate::isk::bsp::*;
+use crate::isk::gps::*;
+use crate::isk::keys::direction_name;
+use rand::Rng;
+
+// towers: each floor above or below the ground floor is its own map, the size of the tower's Rect on the ground floor
+// and with its origin at the Rect's northwest corner, so a point means the same place on every floor.
+// Stairs are map objects paired by exits.  The stairwell prefab sits in the tower's outer corner; each floor flips the
+// one below it north-south, so the stairs up land on the floor above's stairs down.
+
+pub fn level_name(level:i32) -> String {
+    if 0 == level { return "ground floor".to_string(); }
+    if -1 == level { return "cellar".to_string(); }
+    if 0 > level { return "cellar ".to_string()+&(-level).to_string(); }
+    return "floor ".to_string()+&level.to_string();
+}
+
+// the cell in corner, inside a wall one thick
+fn stairwell_corner(src:&Rect, corner:Compass) -> [i32;2] {
+    let mut ret = src.anchor(corner.clone());
+    ret[0] += if Compass::NE == corner || Compass::SE == corner { -2 } else { 1 };    // anchor is one past the east edge
+    ret[1] += if Compass::SE == corner || Compass::SW == corner { -2 } else { 1 };
+    return ret;
+}
+
+fn find_object(m:&Map, src:&Rect, model:&r_MapObjectModel) -> Option<[i32;2]> {
+    let nw = src.anchor(Compass::NW);
+    let se = src.anchor(Compass::SE);
+    for y in nw[1]..se[1] {
+        for x in nw[0]..se[0] {
+            if m.get_map_object([x,y]).is_some_and(|obj| Rc::ptr_eq(&obj.borrow().model, model)) { return Some([x,y]); }
+        }
+    }
+    return None;
+}
+
+impl World {
+    // the exit here, if it goes dir (1 up, -1 down) floors
+    pub fn get_stairs(&self, o:&Location, dir:i32) -> Option<Location> {
+        let dest = self.get_exit(o)?;
+        if o.map.borrow().level()+dir != dest.map.borrow().level() { return None; }
+        return Some(dest);
+    }
+
+    // a cellar, and one to three floors above the ground floor; tower is already drawn on ground, in its corner of
+    // the castle
+    pub fn build_tower(&mut self, ground:&r_Map, tower:&MapRect, corner:Compass) {
+        let upper:i32 = self.rng.gen_range(1, 4);
+        let t_stone_floor = self.get_terrain("stone floor").unwrap();
+        let t_wall = self.get_terrain("wall").unwrap();
+        let closed_door = self.get_map_object_model("door (closed)").unwrap();
+        let stairs_up = self.get_map_object_model("stairs up").unwrap();
+        let stairs_down = self.get_map_object_model("stairs down").unwrap();
+
+        // the stairwell as it is on even floors; the prefab is drawn for the northwest corner
+        let east = Compass::NE == corner || Compass::SE == corner;
+        let south = Compass::SE == corner || Compass::SW == corner;
+        let base = self.get_prefab("stairwell").unwrap();
+        debug_assert!(base.width()+2 <= tower.rect.width() && base.height()+2 <= tower.rect.height());
+        let mut stairwell = if south { base.rotated().rotated().mirrored() } else { base.clone() };
+        if east { stairwell = stairwell.mirrored(); }
+        let flipped = stairwell.rotated().rotated().mirrored();   // north-south
+
+        let ground_name = ground.borrow().named();
+        let local = Rect::new([0, 0], [tower.rect.width(), tower.rect.height()]);
+        let mut floors = Vec::<(i32,r_Map,Rect)>::new();
+        for level in -1..=upper {
+            let prefab = if 0 == level%2 { &stairwell } else { &flipped };
+            if 0 == level {
+                prefab.stamp(&Location::new(ground, stairwell_corner(&tower.rect, corner.clone())), corner.clone()).unwrap();
+                floors.push((level, Rc::clone(ground), tower.rect.clone()));
+                continue;
+            }
+            let name = format!("{}, {} tower {}", ground_name, direction_name(corner.clone()).to_uppercase(), level_name(level));
+            let dim = [i32::try_from(local.width()).unwrap(), i32::try_from(local.height()).unwrap()];
+            let r_m = self.new_map(&name, dim, Rc::clone(&t_wall));
+            r_m.borrow_mut().set_level(level);
+            let mut shell = MapRect::new(local.clone(), Rc::clone(&t_stone_floor), Rc::clone(&t_wall));
+            if 0 > level {
+                shell.set_wallcode(1,1,1,1);
+                shell.draw(&mut r_m.borrow_mut());
+            } else { bsp_rooms(&mut self.rng, &shell, 4, 6).draw(&r_m, &closed_door); }
+            prefab.stamp(&Location::new(&r_m, stairwell_corner(&local, corner.clone())), corner.clone()).unwrap();
+            floors.push((level, r_m, local.clone()));
+        }
+
+        // nothing above the top floor, or below the cellar
+        for (level, r_m, rect) in &floors {
+            let model = if upper == *level { &stairs_up } else if -1 == *level { &stairs_down } else { continue; };
+            let pt = find_object(&r_m.borrow(), rect, model).unwrap();
+            r_m.borrow_mut().remove_map_object(pt);
+        }
+        for pair in floors.windows(2) {
+            let (up, down) = {
+                let below = pair[0].1.borrow();
+                let above = pair[1].1.borrow();
+                (find_object(&below, &pair[0].2, &stairs_up).unwrap(), find_object(&above, &pair[1].2, &stairs_down).unwrap())
+            };
+            let origin = [pair[0].2.anchor(Compass::NW), pair[1].2.anchor(Compass::NW)];
+            debug_assert!([up[0]-origin[0][0], up[1]-origin[0][1]] == [down[0]-origin[1][0], down[1]-origin[1][1]]);
+            self.new_exit(Location::new(&pair[0].1, up), Location::new(&pair[1].1, down));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the floors reached from the ground floor's stairs going dir, and where the stairs come out on each
+    fn climb(w:&World, ground:&r_Map, tower:&Rect, dir:i32) -> Vec<(i32,[i32;2])> {
+        let model = w.get_map_object_model(if 0 < dir { "stairs up" } else { "stairs down" }).unwrap();
+        let origin = tower.anchor(Compass::NW);
+        let local = Rect::new([0,0], [tower.width(), tower.height()]);
+        let mut ret = Vec::<(i32,[i32;2])>::new();
+        let mut here = Location::new(ground, find_object(&ground.borrow(), tower, &model).unwrap());
+        let mut pos = [here.pos[0]-origin[0], here.pos[1]-origin[1]];
+        while let Some(next) = w.get_stairs(&here, dir) {
+            assert_eq!(pos, next.pos);     // the same place, a floor away
+            ret.push((next.map.borrow().level(), next.pos));
+            let pt = match find_object(&next.map.borrow(), &local, &model) {
+                Some(x) => x,
+                None => break
+            };
+            here = Location::new(&next.map, pt);
+            pos = pt;
+        }
+        return ret;
+    }
+
+    #[test]
+    fn stairs_join_every_floor_in_the_same_place() {
+        let mut w = World::new();
+        w.reseed(3);
+        w.load_content("data/content.txt").unwrap();
+        w.load_prefabs("data/prefabs.txt").unwrap();
+        let floor = w.get_terrain("stone floor").unwrap();
+        let wall = w.get_terrain("wall").unwrap();
+        for _ in 0..5 {
+            for corner in [Compass::NW, Compass::NE, Compass::SE, Compass::SW].iter() {
+                let ground = w.new_map("ground", [20,20], Rc::clone(&floor));
+                let mut tower = MapRect::new(Rect::new([5,6], [9,9]), Rc::clone(&floor), Rc::clone(&wall));
+                tower.set_wallcode(1,1,1,1);
+                tower.draw(&mut ground.borrow_mut());
+                w.build_tower(&ground, &tower, corner.clone());
+                let up = climb(&w, &ground, &tower.rect, 1);
+                let down = climb(&w, &ground, &tower.rect, -1);
+                assert!((1..=3).contains(&up.len()));
+                assert_eq!((1..=i32::try_from(up.len()).unwrap()).collect::<Vec<_>>(), up.iter().map(|x| x.0).collect::<Vec<_>>());
+                assert_eq!(vec![-1], down.iter().map(|x| x.0).collect::<Vec<_>>());
+                // the stairwell stays in the tower's outer corner on every floor
+                let c = stairwell_corner(&Rect::new([0,0], [9,9]), corner.clone());
+                for x in up.iter().chain(down.iter()) { assert!((x.1[0]-c[0]).abs() <= 1 && (x.1[1]-c[1]).abs() <= 2); }
+            }
+        }
+    }
+}
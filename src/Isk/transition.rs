@@ -0,0 +1,194 @@
+use crate::isk::*;
+use crate::isk::gps::*;
+use crate::isk::item::*;
+
+// what actors can do to map objects: each verb turns one object model into another (a door opens, a lever is pulled).
+// A model has at most one transition per verb.  Preconditions are checked against the actor doing it.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Verb {
+    Open,
+    Close,
+    Lock,
+    Unlock,
+    Break,
+    Pull
+}
+
+pub const VERBS: [Verb;6] = [Verb::Open, Verb::Close, Verb::Lock, Verb::Unlock, Verb::Break, Verb::Pull];
+pub const BUMP_VERBS: [Verb;2] = [Verb::Open, Verb::Pull];    // tried in order, on walking into an object
+
+impl Verb {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Verb::Open => return "open",
+            Verb::Close => return "close",
+            Verb::Lock => return "lock",
+            Verb::Unlock => return "unlock",
+            Verb::Break => return "break",
+            Verb::Pull => return "pull"
+        }
+    }
+
+    pub fn from_name(src:&str) -> Option<Verb> {
+        return VERBS.iter().find(|x| x.name() == src).cloned();
+    }
+}
+
+#[derive(Clone)]
+pub enum Precondition {
+    Key(r_ItemModel),   // carried
+    FreeCell,           // no actor or items where the object is
+    Strength(i16)       // damage, with equipment
+}
+
+#[derive(Clone)]
+pub struct Transition {
+    pub from: r_MapObjectModel,
+    pub verb: Verb,
+    pub to: r_MapObjectModel,
+    pub needs: Vec<Precondition>,
+    pub cost: i16   // energy
+}
+
+impl World {
+    pub fn new_transition(&mut self, src:Transition) {
+        debug_assert!(self.get_transition(&src.from, src.verb).is_none());
+        self.transitions.push(src);
+    }
+
+    pub fn get_transition(&self, src:&r_MapObjectModel, verb:Verb) -> Option<&Transition> {
+        return self.transitions.iter().find(|x| x.verb == verb && Rc::ptr_eq(src, &x.from));
+    }
+
+    // what walking into this does, if anything
+    pub fn bump_verb(&self, src:&r_MapObjectModel) -> Option<Verb> {
+        return BUMP_VERBS.iter().find(|x| self.get_transition(src, **x).is_some()).cloned();
+    }
+
+    // the error is why not, worded as a prompt
+    pub fn check_transition(&self, who:&Actor, dest:&Location, verb:Verb) -> Result<Transition,Error> {
+        let model = match dest.get_map_object() {
+            Some(obj) => Rc::clone(&obj.borrow().model),
+            None => return Err(Error{desc:"nothing to ".to_string()+verb.name()+" there"})
+        };
+        let ret = match self.get_transition(&model, verb) {
+            Some(x) => x,
+            None => return Err(Error{desc:format!("can't {} the {}", verb.name(), model.name)})
+        };
+        for need in &ret.needs {
+            match need {
+                Precondition::Key(key) => {
+                    if !who.inventory().stacks().iter().any(|x| Rc::ptr_eq(&x.model, key)) { return Err(Error{desc:"needs ".to_string()+&key.name}); }
+                },
+                Precondition::FreeCell => {
+                    if dest.get_actor().is_some() || !dest.get_items().is_empty() { return Err(Error{desc:"something in the way".to_string()}); }
+                },
+                Precondition::Strength(x) => {
+                    if who.damage() < *x { return Err(Error{desc:"not strong enough".to_string()}); }
+                }
+            }
+        }
+        return Ok(ret.clone());
+    }
+
+    // the bump verb, if who can use it to walk into dest
+    pub fn opens_way(&self, who:&Actor, dest:&Location) -> Option<Verb> {
+        let obj = dest.get_map_object()?;
+        let verb = self.bump_verb(&obj.borrow().model)?;
+        match self.check_transition(who, dest, verb) {
+            Ok(t) if t.to.walkable => return Some(verb),
+            _ => return None
+        }
+    }
+
+    // adjacent objects the verb applies to, whether or not the preconditions hold
+    pub fn get_transition_locations(&self, o:&Location, verb:Verb) -> Vec<Location> {
+        let mut ret = Vec::<Location>::new();
+        // \todo properly iterate over all directions; cf crates.io/enum-iterator, https://github.com/rust-lang/rust/issues/5417 (declined by devteam)
+        for i in 0..8 {
+            let test = o.clone()+Compass::try_from(i).unwrap();
+            if let Some(obj) = test.get_map_object() {
+                if self.get_transition(&obj.borrow().model, verb).is_some() { ret.push(test); }
+            }
+        }
+        return ret;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn door_and_actor(damage:i16) -> (World, r_Actor, Location) {
+        let mut w = World::new();
+        w.load_content("data/content.txt").unwrap();
+        let floor = w.get_terrain("stone floor").unwrap();
+        let r_m = w.new_map("test", [5,3], floor);
+        let door = Location::new(&r_m, [2,1]);
+        door.set_map_object(w.get_map_object_model("door (closed)").unwrap());
+        let mut model = ActorModel::new("m", Ok(CharSpec{img:'m', c:None}));
+        model.damage = damage;
+        let model = w.new_actor_model(model);
+        let r_act = w.new_actor(model, &Location::new(&r_m, [0,0]), [1,1]).unwrap();
+        return (w, r_act, door);
+    }
+
+    fn why_not(w:&World, r_act:&r_Actor, dest:&Location, verb:Verb) -> String {
+        return w.check_transition(&r_act.borrow(), dest, verb).err().map_or(String::new(), |e| e.desc);
+    }
+
+    fn model_at(src:&Location) -> String { return src.get_map_object().unwrap().borrow().model.name.clone(); }
+
+    #[test]
+    fn doors_open_close_lock_and_unlock() {
+        let (mut w, r_act, door) = door_and_actor(1);
+        assert_eq!(Some(Verb::Open), w.opens_way(&r_act.borrow(), &door));
+        assert!(w.perform(&r_act, Action::Use(door.clone(), Verb::Open)));
+        assert_eq!("door (open)", model_at(&door));
+        assert_eq!(None, w.bump_verb(&door.get_map_object().unwrap().borrow().model));
+
+        let key = w.get_item_model("iron key").unwrap();
+        door.add_item(Item::new(Rc::clone(&key), 1));
+        assert_eq!("something in the way", why_not(&w, &r_act, &door, Verb::Close));
+        let found = door.take_item().unwrap();
+        assert!(w.perform(&r_act, Action::Use(door.clone(), Verb::Close)));
+        assert_eq!("door (closed)", model_at(&door));
+
+        assert_eq!("needs iron key", why_not(&w, &r_act, &door, Verb::Lock));
+        assert!(!w.perform(&r_act, Action::Use(door.clone(), Verb::Lock)));
+        assert!(r_act.borrow_mut().inventory_mut().add(found).is_ok());
+        assert!(w.perform(&r_act, Action::Use(door.clone(), Verb::Lock)));
+        assert_eq!("door (locked)", model_at(&door));
+        assert_eq!(None, w.opens_way(&r_act.borrow(), &door));
+        assert_eq!("can't open the door (locked)", why_not(&w, &r_act, &door, Verb::Open));
+        assert!(w.perform(&r_act, Action::Use(door.clone(), Verb::Unlock)));
+        assert_eq!("door (closed)", model_at(&door));
+    }
+
+    #[test]
+    fn breaking_takes_strength() {
+        let (w, weak, door) = door_and_actor(3);
+        assert_eq!("not strong enough", why_not(&w, &weak, &door, Verb::Break));
+        let (mut w, strong, door) = door_and_actor(4);
+        let t = w.check_transition(&strong.borrow(), &door, Verb::Break).unwrap();
+        assert_eq!(300, t.cost);
+        assert!(w.perform(&strong, Action::Use(door.clone(), Verb::Break)));
+        assert_eq!("door (broken)", model_at(&door));
+        assert!(door.is_walkable_for(&strong.borrow()));
+        assert!(w.get_transition(&door.get_map_object().unwrap().borrow().model, Verb::Close).is_none());
+    }
+
+    #[test]
+    fn levers_flip_back_and_forth() {
+        let (mut w, r_act, door) = door_and_actor(1);
+        door.set_map_object(w.get_map_object_model("lever").unwrap());
+        assert_eq!(Some(Verb::Pull), w.bump_verb(&door.get_map_object().unwrap().borrow().model));
+        assert_eq!(None, w.opens_way(&r_act.borrow(), &door));   // pulled or not, it's in the way
+        assert!(w.perform(&r_act, Action::Use(door.clone(), Verb::Pull)));
+        assert_eq!("lever (pulled)", model_at(&door));
+        assert!(w.perform(&r_act, Action::Use(door.clone(), Verb::Pull)));
+        assert_eq!("lever", model_at(&door));
+        assert_eq!(1, w.get_transition_locations(&r_act.borrow().loc(), Verb::Pull).len());
+        assert!(w.get_transition_locations(&r_act.borrow().loc(), Verb::Open).is_empty());
+    }
+}
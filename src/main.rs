@@ -1,8 +1,13 @@
 mod isk;
 
-use tcod::console::Root;
 use crate::isk::*;
+use crate::isk::ai::*;
 use crate::isk::gps::*;
+use crate::isk::item::*;
+use crate::isk::keys::*;
+use crate::isk::prompt::*;
+use crate::isk::replay::*;
+use std::convert::TryFrom;
 use std::rc::Rc;
 use tcod::input::{Key, KeyCode /*,EventFlags,check_for_event*/};
 // Failed attempt at singleton wrapper class
@@ -36,9 +41,28 @@ impl<T> Singleton<T> {
 static ideal_line_cache:Singleton<HashMap<([i32;2],[i32;2]),Vec<[i32;2]>>> = Singleton{ooao:None,init:Once::new()};
 */
 
+// travel, and stairs up or down
+fn take_exit(w:&mut World, r_pc:&r_Actor, dest:Option<Location>, none_msg:&str) {
+    use crate::isk::messages::*;
+    match dest {
+        Some(dest) => {
+            if let Some(act) = dest.get_actor() {
+                get_messages_cache_mut().get_mut(Rc::clone(r_pc)).set_prompt(&(act.borrow().model.name.clone()+" in way"));
+            } else if !dest.is_walkable_for(&r_pc.borrow()) {
+                get_messages_cache_mut().get_mut(Rc::clone(r_pc)).set_prompt("exit blocked");
+            } else {
+                let cost = dest.get_terrain().move_energy();
+                w.move_actor(r_pc, dest);
+                r_pc.borrow_mut().spend_energy(cost);
+            }
+        },
+        None => get_messages_cache_mut().get_mut(Rc::clone(r_pc)).set_prompt(none_msg)
+    }
+}
+
 // this is going to lift to another file eventually
 // errors at this handler cannot overwrite other modes, so plausibly best to use prompt rather than set_message here
-fn event_backbone_pc(key:Key, r: &mut Root, w:&mut World, r_pc:r_Actor) -> bool {
+fn event_backbone_pc(key:Key, dm: &mut DisplayManager, w:&mut World, r_pc:r_Actor) -> bool {
     use crate::isk::messages::*;
 
     get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).clear_prompt();
@@ -46,100 +70,114 @@ fn event_backbone_pc(key:Key, r: &mut Root, w:&mut World, r_pc:r_Actor) -> bool
     let cur_loc = r_pc.borrow().loc();
     let mut next_loc: Option<Location> = Some(cur_loc.clone());
 
-    match key {
-        Key { code: KeyCode::Enter, alt: true, .. } => {
-            // Alt+Enter: toggle fullscreen
-            let fullscreen = r.is_fullscreen();
-            r.set_fullscreen(!fullscreen);
+    match w.keymap().command(key) {
+        Some(Command::ToggleFullscreen) => {
+            let fullscreen = dm.is_fullscreen();
+            dm.set_fullscreen(!fullscreen);
             return false;
         },
-        Key { code: KeyCode::Escape, .. } => return true,
-        // movement keys
-        Key { code: KeyCode::Up, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[0, -1]);
-        },
-        Key { code: KeyCode::NumPad8, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[0, -1]);
-        },
-        Key { code: KeyCode::Down, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[0, 1]);
+        Some(Command::Quit) => return true,
+        Some(Command::Move(dir)) => {
+            next_loc = w.canonical_loc(cur_loc.clone()+dir);
         },
-        Key { code: KeyCode::NumPad2, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[0, 1]);
+        Some(Command::Wait) => {
+            w.perform(&r_pc, Action::Wait);
+            return false;
         },
-        Key { code: KeyCode::Left, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[-1, 0]);
+        Some(Command::Travel) => {
+            let dest = w.get_exit(&cur_loc);
+            take_exit(w, &r_pc, dest, "no exit here");
+            return false;
         },
-        Key { code: KeyCode::NumPad4, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[-1, 0]);
+        Some(Command::Up) => {
+            let dest = w.get_stairs(&cur_loc, 1);
+            take_exit(w, &r_pc, dest, "no stairs up here");
+            return false;
         },
-        Key { code: KeyCode::Right, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[1, 0]);
+        Some(Command::Down) => {
+            let dest = w.get_stairs(&cur_loc, -1);
+            take_exit(w, &r_pc, dest, "no stairs down here");
+            return false;
         },
-        Key { code: KeyCode::NumPad6, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[1, 0]);
+        Some(Command::PickUp) => {
+            match cur_loc.get_items().last() {
+                Some(item) => {
+                    if !w.perform(&r_pc, Action::PickUp) {
+                        get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt(&("no room for ".to_string()+&item.describe()));
+                    }
+                },
+                None => get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt("nothing to pick up")
+            }
+            return false;
         },
-        Key { code: KeyCode::NumPad7, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[-1, -1]);
+        Some(Command::Equip) => {
+            let n = r_pc.borrow().inventory().len();
+            if !r_pc.borrow().inventory().stacks().iter().any(|x| x.model.slot.is_some()) {
+                get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt("nothing to wield or wear");
+            } else {
+                get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt(&format!("wield or wear which? (a-{}, Esc to cancel)", inventory_letter(n-1)));
+                w.add_handler(event_equip_pc);
+            }
+            return false;
         },
-        Key { code: KeyCode::NumPad9, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[1, -1]);
+        Some(Command::Remove) => {
+            let n = r_pc.borrow().equipment().len();
+            if 0 == n {
+                get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt("nothing to remove");
+            } else {
+                get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt(&format!("remove which? (a-{}, Esc to cancel)", inventory_letter(n-1)));
+                w.add_handler(event_remove_pc);
+            }
+            return false;
         },
-        Key { code: KeyCode::NumPad1, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[-1, 1]);
+        Some(Command::Drop) => {
+            let n = r_pc.borrow().inventory().len();
+            if 0 == n {
+                get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt("nothing to drop");
+            } else {
+                get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt(&format!("drop which? (a-{}, Esc to cancel)", inventory_letter(n-1)));
+                w.add_handler(event_drop_pc);
+            }
+            return false;
         },
-        Key { code: KeyCode::NumPad3, .. } => {
-            next_loc = w.canonical_loc(cur_loc.clone()+[1, 1]);
+        Some(Command::Look) => {
+            get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt("look which way? (direction or wait, Esc to cancel)");
+            w.add_handler(event_look_pc);
+            return false;
         },
-        // libtcod, for letter keys: canonical value in printable is the lower-case, even when modifiers applied
-        Key { code: KeyCode::Char, printable:'c', .. } => {
-            let locs = w.get_closable_locations(&cur_loc);
+        Some(Command::Use(verb)) => {
+            let locs = w.get_transition_locations(&cur_loc, verb);
             match locs.len() {
-                1 => {
-                    let mut pc = r_pc.borrow_mut();
-                    w.close(&locs[0], &pc);
-                    pc.spend_energy(BASE_ACTION_COST);
-                    return false;
-                },
-                0 => {
-                    get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt("nothing closeable in reach");
-                    return false;
-                },
-                _ => {
-                    debug_assert!(false, "multiple_locations, need UI buildout");
-                    return false;
-                }
+                1 => w.use_pc(&r_pc, locs[0].clone(), verb),
+                0 => get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt(&("nothing to ".to_string()+verb.name()+" in reach")),
+                _ => w.prompt_direction(&r_pc, DirectionPrompt{choices:locs, verb}, &(verb.name().to_string()+" which?"))
             }
+            return false;
         },
 
-        _ => {
+        None => {
             get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt("Unrecognized command");
             return false;
         }
     }
     if let Some(loc) = next_loc {
         // \todo process bump moving
-        if let Some(act) = loc.get_actor() {    // linear search crashes, set up cache first
+        if let Some(act) = loc.get_actor() {
             // we do not handle ghosts or non-forcefeedback holograms here
-            // \todo context-sensitive interpretation (melee attack/chat-trade/no-op)
-            get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt(&(act.borrow().model.name.clone()+" in way"));
-            return false;
-        }
-        if loc.is_walkable_for(&r_pc.borrow()) {
-            if !Rc::ptr_eq(&loc.map,&cur_loc.map) {
-                // transfer between owning maps
+            // \todo context-sensitive interpretation (chat-trade/no-op)
+            if r_pc.borrow().is_hostile_to(&act.borrow()) {
+                w.perform(&r_pc, Action::Attack(loc));
+            } else {
+                get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt(&(act.borrow().model.name.clone()+" in way"));
             }
-            let mut pc = r_pc.borrow_mut();
-            pc.set_loc(loc);
-            pc.spend_energy(BASE_ACTION_COST);
             return false;
         }
+        if w.perform(&r_pc, Action::Move(loc.clone())) { return false; }
         if let Some(obj) = loc.get_map_object() {
-            if let Some(next_obj) = &obj.borrow().model.morph_on_bump {
-                loc.set_map_object(Rc::clone(&next_obj));
-                r_pc.borrow_mut().spend_energy(BASE_ACTION_COST);
-            } else {
-                get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt(&(obj.borrow().model.name.clone()+" in way"));
+            let bump = w.bump_verb(&obj.borrow().model);
+            match bump {
+                Some(verb) => w.use_pc(&r_pc, loc.clone(), verb),
+                None => get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt(&(obj.borrow().model.name.clone()+" in way"))
             }
         } else if !loc.get_terrain().walkable {
             get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt(&(loc.get_terrain().name.clone() + " in way"));
@@ -153,13 +191,171 @@ fn event_backbone_pc(key:Key, r: &mut Root, w:&mut World, r_pc:r_Actor) -> bool
     return false;
 }
 
+fn inventory_letter(n:usize) -> char { return char::from(b'a'+u8::try_from(n).unwrap()); }
+
+// shared by the item menus: Some(None) is Esc, Some(Some(n)) is a letter choice in range
+fn menu_choice(key:Key, r_pc:&r_Actor, ub:usize) -> Option<Option<usize>> {
+    use crate::isk::messages::*;
+
+    match key {
+        Key { code: KeyCode::Escape, .. } => {
+            get_messages_cache_mut().get_mut(Rc::clone(r_pc)).clear_prompt();
+            return Some(None);
+        },
+        Key { code: KeyCode::Char, printable, .. } if printable.is_ascii_lowercase() => {
+            let n = usize::from(u8::try_from(printable).unwrap() - b'a');
+            if n < ub {
+                get_messages_cache_mut().get_mut(Rc::clone(r_pc)).clear_prompt();
+                return Some(Some(n));
+            }
+            return None;
+        },
+        _ => return None
+    }
+}
+
+// modal: pushed by 'd'.  Returning true pops it.
+fn event_drop_pc(key:Key, _dm: &mut DisplayManager, w:&mut World, r_pc:r_Actor) -> bool {
+    let ub = r_pc.borrow().inventory().len();
+    match menu_choice(key, &r_pc, ub) {
+        Some(Some(n)) => { w.perform(&r_pc, Action::Drop(n)); return true; },
+        Some(None) => return true,
+        None => return false
+    }
+}
+
+// modal: pushed by 'w'
+fn event_equip_pc(key:Key, _dm: &mut DisplayManager, w:&mut World, r_pc:r_Actor) -> bool {
+    use crate::isk::messages::*;
+
+    let ub = r_pc.borrow().inventory().len();
+    match menu_choice(key, &r_pc, ub) {
+        Some(Some(n)) => {
+            if !w.perform(&r_pc, Action::Equip(n)) {
+                get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt("cannot wield or wear that");
+            }
+            return true;
+        },
+        Some(None) => return true,
+        None => return false
+    }
+}
+
+// modal: pushed by 'r'.  Letters follow the sidebar's equipment listing.
+fn event_remove_pc(key:Key, _dm: &mut DisplayManager, w:&mut World, r_pc:r_Actor) -> bool {
+    use crate::isk::messages::*;
+
+    let slots: Vec<Slot> = SLOTS.iter().filter(|x| r_pc.borrow().equipped(**x).is_some()).cloned().collect();
+    match menu_choice(key, &r_pc, slots.len()) {
+        Some(Some(n)) => {
+            if !w.perform(&r_pc, Action::Remove(slots[n])) {
+                get_messages_cache_mut().get_mut(Rc::clone(&r_pc)).set_prompt("no room in inventory");
+            }
+            return true;
+        },
+        Some(None) => return true,
+        None => return false
+    }
+}
+
+const CONTENT_FILE:&str = "data/content.txt";
+const KEYS_FILE:&str = "data/keys.txt";
+const PREFABS_FILE:&str = "data/prefabs.txt";
+const SAVE_FILE:&str = "save.txt";
+
 fn main() {
-    let mut dm = DisplayManager::new("TCOD Skeleton Game", "fonts/dejavu12x12_gs_tc.png");
+    let args: Vec<String> = std::env::args().collect();
+    // --seed N: a new game from that seed, even if there is a savefile
+    // --record FILE: a new game, with every key logged to FILE
+    // --replay FILE: a new game from FILE's seed, fed FILE's keys; --headless runs it without a window, at full speed
+    let arg_value = |flag:&str| args.iter().position(|x| flag == x).map(|n| args.get(n+1));
+    let seed = match arg_value("--seed") {
+        Some(x) => match x.and_then(|x| x.parse::<u64>().ok()) {
+            Some(x) => Some(x),
+            None => {
+                eprintln!("--seed needs a number");
+                return;
+            }
+        },
+        None => None
+    };
+    let record = match arg_value("--record") {
+        Some(Some(path)) => Some(path.clone()),
+        Some(None) => {
+            eprintln!("--record needs a file");
+            return;
+        },
+        None => None
+    };
+    let replay = match arg_value("--replay") {
+        Some(Some(path)) => match Replay::load(path) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        Some(None) => {
+            eprintln!("--replay needs a file");
+            return;
+        },
+        None => None
+    };
+    let replaying = replay.is_some();
+    let headless = args.iter().any(|x| "--headless" == x);
+    if headless && !replaying {     // there would be no keys, and the game would end (and save) at once
+        eprintln!("--headless needs --replay");
+        return;
+    }
+    let mut dm = if headless { DisplayManager::new_headless() }
+        else { DisplayManager::new("TCOD Skeleton Game", "fonts/dejavu12x12_gs_tc.png") };
     let mut world = World::new();
     world.add_handler(event_backbone_pc);
-    let mut player = world.new_game();
+    if let Err(e) = world.load_content(CONTENT_FILE) {
+        eprintln!("{}", e);
+        return;
+    }
+    if let Err(e) = world.load_prefabs(PREFABS_FILE) {
+        eprintln!("{}", e);
+        return;
+    }
+    match KeyMap::load(KEYS_FILE) {
+        Ok(keymap) => world.set_keymap(keymap),
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    }
+    let seed = match &replay {
+        Some(x) => Some(x.seed()),
+        None => seed
+    };
+    let mut player = if seed.is_some() || record.is_some() {
+        if let Some(x) = seed { world.reseed(x); }
+        world.new_game()
+    } else if std::path::Path::new(SAVE_FILE).exists() {
+        match world.load(SAVE_FILE) {
+            Ok(pc) => pc,
+            Err(e) => {     // leave it be: saving on exit would overwrite it
+                eprintln!("{}", e);
+                eprintln!("move or delete {} to start a new game", SAVE_FILE);
+                return;
+            }
+        }
+    } else { world.new_game() };
+    if let Some(path) = record {
+        match Recorder::create(&path, world.seed()) {
+            Ok(x) => world.set_recorder(x),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    }
+    if let Some(x) = replay { world.set_replay(x); }
 
-    while !dm.root.window_closed() {
+    while world.is_replaying() || !dm.window_closed() {
+        if player.borrow().is_dead() { break; }
         dm.clear();
         {
         let p_loc = player.borrow().loc();
@@ -173,14 +369,122 @@ fn main() {
             if act.borrow().is_pc {
                 player = act;
                 // Handling user input
-                if world.exec_key(&mut dm.root, Rc::clone(&player)) { return; }
+                if world.exec_key(&mut dm, Rc::clone(&player)) { break; }
             } else {
-                // \todo process NPC AI
-                act.borrow_mut().spend_energy(BASE_ACTION_COST);
+                world.npc_turn(&act);
             }
         }
 
         // Updating the gamestate
         // Rendering the results
     }
+
+    if let Some(e) = world.recording_error() { eprintln!("{}", e); }
+    if replaying {   // leave the savefile alone
+        match world.replay_error() {
+            Some(e) => eprintln!("{}", e),
+            None => println!("Replay done.")
+        }
+        return;
+    }
+    if player.borrow().is_dead() {
+        println!("You die.");
+        let _ = std::fs::remove_file(SAVE_FILE);  // permadeath
+        return;
+    }
+    if let Err(e) = world.save(SAVE_FILE) { eprintln!("{}", e); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isk::messages::*;
+
+    fn scripted(c:char) -> Key {
+        let mut ret = Key::default();
+        ret.code = KeyCode::Char;
+        ret.printable = c;
+        return ret;
+    }
+
+    // the PC alone on a floor, standing on a heap of rocks
+    fn setup() -> (World, DisplayManager, r_Actor, r_ItemModel) {
+        let mut w = World::new();
+        w.add_handler(event_backbone_pc);
+        let floor = w.new_terrain(Terrain::new("floor", Ok(CharSpec{img:'.', c:None}), true, true));
+        let r_m = w.new_map("test", [3,3], floor);
+        let model = w.new_actor_model(ActorModel::new("you", Ok(CharSpec{img:'@', c:None})));
+        let r_pc = w.new_actor(model, &Location::new(&r_m, [0,0]), [1,1]).unwrap();
+        r_pc.borrow_mut().is_pc = true;
+        let rock = w.new_item_model(ItemModel::new("rock", Ok(CharSpec{img:'*', c:None})));
+        r_pc.borrow().loc().add_item(Item::new(Rc::clone(&rock), 3));
+        return (w, DisplayManager::new_headless(), r_pc, rock);
+    }
+
+    fn press(w:&mut World, dm:&mut DisplayManager, r_pc:&r_Actor, c:char) {
+        dm.headless_mut().unwrap().push_key(scripted(c));
+        assert!(!w.exec_key(dm, Rc::clone(r_pc)));
+    }
+
+    fn prompt(r_pc:&r_Actor) -> Option<String> { return get_messages_cache_mut().get(Rc::clone(r_pc)).prompt(); }
+
+    #[test]
+    fn pick_up_and_drop_by_key() {
+        let (mut w, mut dm, r_pc, rock) = setup();
+        press(&mut w, &mut dm, &r_pc, 'g');
+        assert!(r_pc.borrow().loc().get_items().is_empty());
+        assert_eq!(3, r_pc.borrow().inventory().stacks()[0].qty);
+        press(&mut w, &mut dm, &r_pc, 'g');
+        assert_eq!(Some("nothing to pick up".to_string()), prompt(&r_pc));
+
+        press(&mut w, &mut dm, &r_pc, 'd');
+        assert_eq!(Some("drop which? (a-a, Esc to cancel)".to_string()), prompt(&r_pc));
+        press(&mut w, &mut dm, &r_pc, 'b');    // out of range: still asking
+        assert_eq!(1, r_pc.borrow().inventory().len());
+        press(&mut w, &mut dm, &r_pc, 'a');
+        assert!(r_pc.borrow().inventory().is_empty());
+        let here = r_pc.borrow().loc().get_items();
+        assert!(Rc::ptr_eq(&rock, &here[0].model));
+        assert_eq!(3, here[0].qty);
+        assert_eq!(None, prompt(&r_pc));
+
+        press(&mut w, &mut dm, &r_pc, 'd');
+        assert_eq!(Some("nothing to drop".to_string()), prompt(&r_pc));
+    }
+
+    #[test]
+    fn escape_backs_out_of_drop() {
+        let (mut w, mut dm, r_pc, _) = setup();
+        press(&mut w, &mut dm, &r_pc, 'g');
+        press(&mut w, &mut dm, &r_pc, 'd');
+        let mut esc = Key::default();
+        esc.code = KeyCode::Escape;
+        dm.headless_mut().unwrap().push_key(esc);
+        assert!(!w.exec_key(&mut dm, Rc::clone(&r_pc)));   // Escape here cancels rather than quits
+        assert_eq!(1, r_pc.borrow().inventory().len());
+        assert_eq!(None, prompt(&r_pc));
+        press(&mut w, &mut dm, &r_pc, 'd');
+        press(&mut w, &mut dm, &r_pc, 'a');
+        assert!(r_pc.borrow().inventory().is_empty());
+    }
+
+    #[test]
+    fn a_full_pack_turns_things_away() {
+        let (mut w, mut dm, r_pc, rock) = setup();
+        for n in 0..BASE_CAPACITY {
+            let junk = w.new_item_model(ItemModel::new(&n.to_string(), Ok(CharSpec{img:'*', c:None})));
+            assert!(r_pc.borrow_mut().inventory_mut().add(Item::new(junk, 1)).is_ok());
+        }
+        press(&mut w, &mut dm, &r_pc, 'g');
+        assert_eq!(Some("no room for 3 x rock".to_string()), prompt(&r_pc));
+        assert_eq!(3, r_pc.borrow().loc().get_items()[0].qty);
+        assert_eq!(BASE_CAPACITY, r_pc.borrow().inventory().len());
+        // room for more of what is already carried
+        r_pc.borrow_mut().inventory_mut().remove(0);
+        assert!(r_pc.borrow_mut().inventory_mut().add(Item::new(Rc::clone(&rock), 1)).is_ok());
+        press(&mut w, &mut dm, &r_pc, 'g');
+        assert!(r_pc.borrow().loc().get_items().is_empty());
+        assert_eq!(BASE_CAPACITY, r_pc.borrow().inventory().len());
+        assert_eq!(4, r_pc.borrow().inventory().stacks().last().unwrap().qty);
+    }
 }